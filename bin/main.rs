@@ -1,37 +1,196 @@
+use std::{
+    fs::File,
+    io::{
+        self,
+        Read,
+        Write,
+    },
+    path::PathBuf,
+    process,
+    thread,
+};
+
 use rvvm::prelude::*;
 
-#[device]
-struct TestDev(i32);
+const USAGE: &str =
+    "Usage: rvvmrs [--gdb <addr>] [--kernel <path>] <firmware>";
+
+/// UART is placed where the RVVM machines usually have it
+const UART_ADDR: u64 = 0x1000_0000;
+const UART_IRQ: u32 = 1;
+
+#[derive(Debug)]
+struct Args {
+    gdb: Option<String>,
+    kernel: Option<PathBuf>,
+    firmware: PathBuf,
+}
 
-impl Drop for TestDev {
-    fn drop(&mut self) {
-        eprintln!("Без мата");
+/// Parses `[--gdb <addr>] [--kernel <path>] <firmware>`,
+/// flags also accept the `--flag=value` form
+fn parse_args() -> Result<Args, String> {
+    let mut gdb = None;
+    let mut kernel = None;
+    let mut firmware = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => {
+                (flag.to_owned(), Some(value.to_owned()))
+            }
+            _ => (arg, None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{flag} requires a value"))
+        };
+
+        match flag.as_str() {
+            "--gdb" => gdb = Some(value()?),
+            "--kernel" => kernel = Some(PathBuf::from(value()?)),
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                process::exit(0);
+            }
+            _ if flag.starts_with('-') => {
+                return Err(format!("Unknown flag {flag}"));
+            }
+            _ if firmware.is_none() => {
+                firmware = Some(PathBuf::from(flag));
+            }
+            _ => return Err(format!("Unexpected argument {flag}")),
+        }
     }
+
+    Ok(Args {
+        gdb,
+        kernel,
+        firmware: firmware.ok_or("Firmware path is missing")?,
+    })
 }
 
-impl Device<i32> for TestDev {
-    type Error = ();
+fn load(instance: &mut Instance, args: &Args) -> Result<(), String> {
+    let open = |path: &PathBuf| {
+        File::open(path)
+            .map_err(|err| format!("{}: {err}", path.display()))
+    };
 
-    fn read(
-        &self,
-        dest: &mut [u8],
-        size: u8,
-        offset: usize,
-    ) -> Result<(), Self::Error> {
-        self.data();
-        Ok(())
+    instance
+        .load_bootrom_from(open(&args.firmware)?)
+        .map_err(|err| format!("{}: {err}", args.firmware.display()))?;
+    if let Some(kernel) = &args.kernel {
+        instance
+            .load_kernel_from(open(kernel)?)
+            .map_err(|err| format!("{}: {err}", kernel.display()))?;
     }
 
-    fn write(
-        &self,
-        dest: &mut [u8],
-        size: u8,
-        offset: usize,
-    ) -> Result<(), Self::Error> {
-        Ok(())
+    Ok(())
+}
+
+/// Copies the guest output to the stdout until the UART is
+/// removed together with the machine
+fn forward_output(mut reader: UartReader) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut buf = [0; 256];
+        let mut stdout = io::stdout();
+        while let Ok(len @ 1..) = reader.read(&mut buf) {
+            if stdout
+                .write_all(&buf[..len])
+                .and_then(|()| stdout.flush())
+                .is_err()
+            {
+                break;
+            }
+        }
+    })
+}
+
+/// Copies the stdin to the guest input, thread is left
+/// detached since reading the stdin can't be interrupted
+fn forward_input(mut writer: UartWriter) {
+    thread::spawn(move || {
+        let _ = io::copy(&mut io::stdin().lock(), &mut writer);
+    });
+}
+
+#[cfg(feature = "internals")]
+fn serve_gdb(instance: &mut Instance, addr: &str) -> io::Result<()> {
+    eprintln!("Waiting for GDB on {addr}");
+
+    // Socket path if it looks like a path, TCP address
+    // otherwise
+    #[cfg(unix)]
+    let result = if addr.contains('/') {
+        rvvm::gdbstub::serve_unix(instance, addr)
+    } else {
+        rvvm::gdbstub::serve_tcp(instance, addr)
+    };
+    #[cfg(not(unix))]
+    let result = rvvm::gdbstub::serve_tcp(instance, addr);
+
+    result
+}
+
+#[cfg(not(feature = "internals"))]
+fn serve_gdb(_instance: &mut Instance, _addr: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "rvvmrs is built without the `internals` feature",
+    ))
+}
+
+/// Runs the machine until the guest powers it off, reset
+/// machine is started again
+fn run(instance: &mut Instance) {
+    loop {
+        match instance.run() {
+            Ok(ExitReason::Reset) => continue,
+            Ok(ExitReason::PoweredOff | ExitReason::Paused) => break,
+            Err(err) => {
+                eprintln!("Failed to start the machine: {err}");
+                break;
+            }
+        }
     }
 }
 
 fn main() {
-    let a = TestDev::new(10, 20, 1..=1, 1024i32);
+    let args = parse_args().unwrap_or_else(|err| {
+        eprintln!("{err}\n{USAGE}");
+        process::exit(2);
+    });
+
+    // Breakpoints patch the guest code, which JIT doesn't
+    // notice
+    let mut instance = Instance::builder()
+        .jit(args.gdb.is_none())
+        .build();
+    if let Err(err) = load(&mut instance, &args) {
+        eprintln!("Failed to load {err}");
+        process::exit(1);
+    }
+
+    let (reader, writer) = instance
+        .attach_uart(UART_ADDR, UART_IRQ)
+        .expect("Failed to attach UART");
+    let output = forward_output(reader);
+    forward_input(writer);
+
+    if let Some(addr) = &args.gdb {
+        if let Err(err) = serve_gdb(&mut instance, addr) {
+            eprintln!("GDB session failed: {err}");
+            process::exit(1);
+        }
+    }
+    // Machine is left running once the debugger detaches and
+    // paused if it killed the target
+    if args.gdb.is_none() || instance.pause().is_ok() {
+        run(&mut instance);
+    }
+
+    drop(instance);
+    output.join().expect("Output thread panicked");
 }
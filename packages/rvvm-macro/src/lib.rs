@@ -1,16 +1,36 @@
+use darling::FromMeta;
 use proc_macro::TokenStream;
-use proc_macro2::Span;
+use proc_macro2::{
+    Span,
+    TokenStream as TokenStream2,
+    TokenTree,
+};
 use proc_macro_error::{
     abort,
     proc_macro_error,
 };
-use quote::quote;
+use quote::{
+    format_ident,
+    quote,
+    ToTokens,
+};
 use syn::{
     punctuated::Punctuated,
     Fields,
+    GenericParam,
     TypeTuple,
 };
 
+#[derive(FromMeta)]
+struct HandlerArgs {
+    ty: Option<syn::Type>,
+}
+
+enum HandlerKind {
+    Type,
+    Remove,
+}
+
 fn unit() -> syn::Type {
     syn::Type::Tuple(TypeTuple {
         paren_token: syn::token::Paren {
@@ -20,6 +40,41 @@ fn unit() -> syn::Type {
     })
 }
 
+/// Names of the `DeviceData`/`Device`/`DeviceExt` methods,
+/// accessors of the fields with these names would shadow
+/// them
+const RESERVED_FIELDS: &[&str] =
+    &["data", "data_mut", "new", "read", "write"];
+
+/// Whether `tokens` mention the `ident` anywhere
+fn mentions(tokens: TokenStream2, ident: &syn::Ident) -> bool {
+    tokens.into_iter().any(|tt| match tt {
+        TokenTree::Ident(other) => other == *ident,
+        TokenTree::Group(group) => mentions(group.stream(), ident),
+        _ => false,
+    })
+}
+
+/// Turns struct into the MMIO device.
+///
+/// - Unit-like struct or tuple-like struct without fields
+///   has `()` data
+/// - Tuple-like struct with single field has data of the
+///   field type
+/// - Struct with named fields has data of the synthesized
+///   `{Name}Data` struct with the same fields. Device also
+///   gets `field()`/`field_mut()` accessors for every
+///   field, so fields can't be named `data`, `data_mut`,
+///   `new`, `read` or `write`
+///
+/// Attributes other than docs are placed on the data
+/// struct if it's synthesized, otherwise on the device.
+/// Derives are allowed only in the former case.
+///
+/// Generic parameters and where-clauses are preserved.
+/// Every type and lifetime parameter of the struct with
+/// named fields must be used by some field, since the data
+/// struct is generic over them too.
 #[proc_macro_attribute]
 #[proc_macro_error]
 pub fn device(_attrs: TokenStream, stream: TokenStream) -> TokenStream {
@@ -27,6 +82,33 @@ pub fn device(_attrs: TokenStream, stream: TokenStream) -> TokenStream {
 
     let ident = dev.ident;
     let vis = dev.vis;
+    let generics = dev.generics;
+    let (impl_generics, ty_generics, where_clause) =
+        generics.split_for_impl();
+
+    // Docs are left on the device, everything else (e.g.
+    // derives) belongs to the data if it's synthesized
+    let (mut device_attrs, attrs): (Vec<_>, Vec<_>) = dev
+        .attrs
+        .into_iter()
+        .partition(|attr| attr.path.is_ident("doc"));
+    if !matches!(dev.fields, Fields::Named(_)) {
+        if let Some(derive) = attrs
+            .iter()
+            .find(|attr| attr.path.is_ident("derive"))
+        {
+            abort! {
+                derive, "Derives are supported only for the struct \
+                         with named fields";
+                help = "Device has data of the field type, derive on \
+                        that type instead"
+            }
+        }
+        device_attrs.extend(attrs.iter().cloned());
+    }
+
+    let mut data_def = quote!();
+    let mut accessors = quote!();
 
     let ty: syn::Type = match dev.fields {
         Fields::Unit => unit(),
@@ -41,36 +123,135 @@ pub fn device(_attrs: TokenStream, stream: TokenStream) -> TokenStream {
 
                 _ => abort! {
                     unnamed, "Struct must be tuple-like with single \
-                              field or unit-like or tuple-like with no fields"
+                              field or unit-like or tuple-like with no fields";
+                    help = "Use named fields to store multiple values"
                 },
             }
         }
 
-        fields @ Fields::Named(_) => abort! {
-            fields, "Device struct can only be created with the single unnamed field or without fields";
-            help = "Create unit or tuple-like structure with the single field"
-        },
+        Fields::Named(named) => {
+            let data_ident = format_ident!("{}Data", ident);
+            let fields = named.named.iter();
+
+            let field_tys = named
+                .named
+                .iter()
+                .map(|field| field.ty.to_token_stream());
+            let field_tys: TokenStream2 = field_tys.collect();
+            for param in &generics.params {
+                let param_ident = match param {
+                    GenericParam::Type(ty) => &ty.ident,
+                    GenericParam::Lifetime(def) => &def.lifetime.ident,
+                    GenericParam::Const(_) => continue,
+                };
+
+                if !mentions(field_tys.clone(), param_ident) {
+                    abort! {
+                        param, "Generic parameter `{}` is not used by any \
+                                field", param_ident;
+                        help = "Add a `PhantomData` field that uses it"
+                    }
+                }
+            }
+
+            data_def = quote! {
+                #(#attrs)*
+                #vis struct #data_ident #generics #where_clause {
+                    #(#fields),*
+                }
+            };
+
+            for field in named.named.iter() {
+                let field_vis = &field.vis;
+                let field_ty = &field.ty;
+                let name = field.ident.as_ref().unwrap();
+                let name_mut = format_ident!("{}_mut", name);
+
+                if RESERVED_FIELDS
+                    .iter()
+                    .any(|reserved| name == reserved)
+                {
+                    abort! {
+                        name, "Field can't be named `{}`", name;
+                        help = "Its accessor would shadow the method of the \
+                                `DeviceData` or `DeviceExt`, rename the field"
+                    }
+                }
+
+                accessors.extend(quote! {
+                    #field_vis fn #name(&self) -> &#field_ty {
+                        &::rvvm::dev::mmio::DeviceData::data(self).#name
+                    }
+
+                    #field_vis fn #name_mut(&mut self) -> &mut #field_ty {
+                        &mut ::rvvm::dev::mmio::DeviceData::data_mut(self).#name
+                    }
+                });
+            }
+
+            syn::parse_quote!(#data_ident #ty_generics)
+        }
     };
 
+    // `UnsafeDevice` requires data to be `Send + Sync`, which
+    // can't be checked until generic parameters are known
+    let mut struct_generics = generics.clone();
+    if !generics.params.is_empty() {
+        struct_generics
+            .make_where_clause()
+            .predicates
+            .push(syn::parse_quote!(#ty: Send + Sync));
+    }
+    let struct_where_clause = &struct_generics.where_clause;
+
+    // Unit-like and tuple-like devices may not use their
+    // generic parameters in the data
+    let markers = generics
+        .params
+        .iter()
+        .filter_map(|param| match param {
+            GenericParam::Type(ty) => {
+                let ident = &ty.ident;
+                Some(quote!(#ident))
+            }
+            GenericParam::Lifetime(def) => {
+                let lifetime = &def.lifetime;
+                Some(quote!(&#lifetime ()))
+            }
+            GenericParam::Const(_) => None,
+        });
+
     quote! {
+        #data_def
+
+        #(#device_attrs)*
         #[repr(transparent)]
-        #vis struct #ident {
+        #vis struct #ident #generics #struct_where_clause {
             inner: ::rvvm::types::UnsafeDevice<#ty>,
+            _marker: ::core::marker::PhantomData<fn() -> (#(#markers,)*)>,
         }
 
-        unsafe impl ::rvvm::dev::mmio::DeviceData for #ident {
+        impl #impl_generics #ident #ty_generics #struct_where_clause {
+            #accessors
+        }
+
+        unsafe impl #impl_generics ::rvvm::dev::mmio::DeviceData
+            for #ident #ty_generics #struct_where_clause
+        {
             type Ty = #ty;
 
             fn data(&self) -> &Self::Ty {
-                unsafe { self.inner.data() }
+                self.inner.data()
             }
 
             fn data_mut(&mut self) -> &mut Self::Ty {
-                unsafe { self.inner.data_mut() }
+                self.inner.data_mut()
             }
         }
 
-        impl ::rvvm::dev::mmio::DeviceExt for #ident {
+        impl #impl_generics ::rvvm::dev::mmio::DeviceExt
+            for #ident #ty_generics #struct_where_clause
+        {
             type DataTy = #ty;
 
             fn new(
@@ -86,8 +267,8 @@ pub fn device(_attrs: TokenStream, stream: TokenStream) -> TokenStream {
 
                 let data_boxed: Box<Self::DataTy> = Box::new(data);
                 let data_voidptr: *mut ::core::ffi::c_void =
-                    unsafe { Box::into_raw(data_boxed) } as *mut () as *mut _;
-                
+                    Box::into_raw(data_boxed) as *mut () as *mut _;
+
                 Self {
                     inner: unsafe { ::rvvm::types::UnsafeDevice::<Self::DataTy>::new(
                         ::rvvm::ffi::rvvm_mmio_dev_t {
@@ -104,10 +285,117 @@ pub fn device(_attrs: TokenStream, stream: TokenStream) -> TokenStream {
                             machine: ::core::ptr::null_mut(),
                             type_: ::core::ptr::null_mut(),
                         }
-                    ) }
+                    ) },
+                    _marker: ::core::marker::PhantomData,
                 }
             }
         }
     }
     .into()
 }
+
+/// Turns `fn(&Dev)` into the
+/// `rvvm::dev::type_::TypeHandler<T>` constant with the
+/// same name. `Dev` is either the `#[device]` struct or
+/// the `UnsafeDevice<T>`, `T` is inferred from it. Optional
+/// `ty` spells the data type of the device explicitly and
+/// must match it.
+#[proc_macro_attribute]
+#[proc_macro_error]
+pub fn type_handler(
+    attrs: TokenStream,
+    stream: TokenStream,
+) -> TokenStream {
+    handler_impl(attrs, stream, HandlerKind::Type)
+}
+
+/// Turns `fn(&mut Dev)` into the
+/// `rvvm::dev::type_::RemoveHandler<T>` constant with the
+/// same name. Device data is dropped right after the
+/// function returns. `Dev` and `ty` are the same as for
+/// the `type_handler`.
+#[proc_macro_attribute]
+#[proc_macro_error]
+pub fn on_remove(attrs: TokenStream, stream: TokenStream) -> TokenStream {
+    handler_impl(attrs, stream, HandlerKind::Remove)
+}
+
+fn handler_impl(
+    attrs: TokenStream,
+    stream: TokenStream,
+    kind: HandlerKind,
+) -> TokenStream {
+    let attrs = syn::parse_macro_input!(attrs as syn::AttributeArgs);
+    let handler = syn::parse_macro_input!(stream as syn::ItemFn);
+
+    let args = match HandlerArgs::from_list(&attrs) {
+        Ok(args) => args,
+        Err(e) => return e.write_errors().into(),
+    };
+
+    let sig = &handler.sig;
+    let help = "Handler signature is `fn(dev: &Dev)`, or `fn(dev: &mut \
+                Dev)` for the `on_remove`, where `Dev` is the device \
+                struct or `UnsafeDevice<T>`";
+    if sig.inputs.len() != 1 {
+        abort!(sig.inputs, "Handler must take exactly one argument"; help = help);
+    }
+
+    let dev_ty = match sig.inputs.first() {
+        Some(syn::FnArg::Typed(arg)) => match &*arg.ty {
+            syn::Type::Reference(reference)
+                if reference.mutability.is_some()
+                    == matches!(kind, HandlerKind::Remove) =>
+            {
+                &reference.elem
+            }
+            other => {
+                abort!(other, "Unexpected device argument"; help = help)
+            }
+        },
+        other => abort!(other, "Handler can't take `self`"; help = help),
+    };
+    let ty = match args.ty {
+        Some(ty) => quote!(#ty),
+        None => quote!(<#dev_ty as ::rvvm::dev::mmio::DeviceData>::Ty),
+    };
+    if !sig.generics.params.is_empty() {
+        abort!(sig.generics, "Handler can't be generic");
+    }
+    if let Some(asyncness) = sig.asyncness {
+        abort!(asyncness, "Handler can't be async");
+    }
+
+    let ident = &sig.ident;
+    let vis = &handler.vis;
+    let raw = format_ident!("__rvvm_raw_{}", ident);
+
+    let (handler_ty, call) = match kind {
+        HandlerKind::Type => (
+            quote!(::rvvm::dev::type_::TypeHandler),
+            quote!(::rvvm::dev::type_::__call_type_handler),
+        ),
+        HandlerKind::Remove => (
+            quote!(::rvvm::dev::type_::RemoveHandler),
+            quote!(::rvvm::dev::type_::__call_remove_handler),
+        ),
+    };
+
+    quote! {
+        #[allow(non_upper_case_globals)]
+        #vis const #ident: #handler_ty<#ty> = {
+            #handler
+
+            unsafe extern "C" fn #raw(
+                dev: *mut ::rvvm::ffi::rvvm_mmio_dev_t,
+            ) {
+                #call::<#dev_ty>(dev, #ident)
+            }
+
+            // Explicit `ty` must match the device data
+            ::rvvm::dev::type_::__assert_data_type::<#dev_ty, #ty>();
+            unsafe { #handler_ty::new(#raw) }
+        };
+    }
+    .into()
+}
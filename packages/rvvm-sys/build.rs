@@ -12,20 +12,40 @@ const BUILDDIR_SUFFIX: &str = "rvvm-lib";
 const LIB_NAME: &str = "rvvm";
 static RVVM_PATH: &str = "rvvm-git";
 
+// Internal structures (harts, machine) depend on these, so
+// with the `internals` feature both library and bindings
+// are built with the same set. Otherwise RVVM defaults are
+// used
+const RVVM_FEATURES: &[&str] = &["USE_RV64", "USE_FPU"];
+
+// Architectures supported by the RVVM's JIT backend
+const JIT_ARCHES: &[&str] =
+    &["x86_64", "x86", "aarch64", "arm", "riscv64", "riscv32"];
+
 fn main() {
     let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
     let build_dir: PathBuf = out_path.join(BUILDDIR_SUFFIX);
 
     let is_dynamic = env::var("CARGO_FEATURE_DYNAMIC").is_ok();
+    let internals = env::var("CARGO_FEATURE_INTERNALS").is_ok();
+    if is_dynamic && internals {
+        panic!(
+            "`internals` can't be used with `dynamic`: layout of the \
+             shared library structures is unknown"
+        );
+    }
+
+    let flags = if internals { features() } else { Vec::new() };
     let kind = if is_dynamic {
         "dylib"
     } else {
-        build_static(&build_dir);
+        build_static(&build_dir, &flags);
         // ty: Nebulka <arapun@proton.me>
         "static"
     };
 
     println!("cargo:rerun-if-changed={RVVM_PATH}/src/rvvmlib.h");
+    println!("cargo:rerun-if-changed={RVVM_PATH}/src/rvvm.h");
     println!("cargo:rustc-link-lib={kind}={LIB_NAME}");
 
     if !is_dynamic {
@@ -35,8 +55,20 @@ fn main() {
         );
     }
 
-    let bindings = bindgen::Builder::default()
+    // RVVM checks the flags with `#ifdef`, so disabled ones are
+    // not defined at all
+    let mut bindings = bindgen::Builder::default()
         .header("wrapper.h")
+        .clang_args(
+            flags
+                .iter()
+                .filter(|(_, enabled)| *enabled)
+                .map(|(name, _)| format!("-D{name}=1")),
+        );
+    if internals {
+        bindings = bindings.clang_arg("-DRVVM_SYS_INTERNALS=1");
+    }
+    let bindings = bindings
         .parse_callbacks(Box::new(bindgen::CargoCallbacks))
         .generate()
         .expect("Failed to generate bindings");
@@ -46,13 +78,29 @@ fn main() {
         .expect("Failed to write bindings");
 }
 
-fn build_static(build_dir: &Path) {
-    let status = Command::new("make")
-        .arg("lib")
-        .env("BUILDDIR", build_dir)
-        .current_dir(RVVM_PATH)
-        .status()
-        .expect("Failed to spawn make command");
+/// `USE_*` flags passed to both the make and the bindgen
+fn features() -> Vec<(&'static str, bool)> {
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap();
+    let jit = JIT_ARCHES.contains(&arch.as_str());
+
+    RVVM_FEATURES
+        .iter()
+        .map(|&name| (name, true))
+        .chain([("USE_JIT", jit)])
+        .collect()
+}
+
+fn build_static(build_dir: &Path, flags: &[(&str, bool)]) {
+    let status =
+        Command::new("make")
+            .arg("lib")
+            .args(flags.iter().map(|&(name, enabled)| {
+                format!("{name}={}", enabled as u8)
+            }))
+            .env("BUILDDIR", build_dir)
+            .current_dir(RVVM_PATH)
+            .status()
+            .expect("Failed to spawn make command");
 
     if !status.success() {
         panic!(
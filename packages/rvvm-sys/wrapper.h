@@ -1,2 +1,11 @@
 #include "rvvm-git/src/rvvmlib.h"
 #include "rvvm-git/src/fdtlib.h"
+#include "rvvm-git/src/devices/plic.h"
+#include "rvvm-git/src/devices/ns16550a.h"
+
+// Internal headers, layouts depend on the USE_* flags
+// passed by the build script
+#ifdef RVVM_SYS_INTERNALS
+#include "rvvm-git/src/rvvm.h"
+#include "rvvm-git/src/riscv_csr.h"
+#endif
@@ -1,11 +1,22 @@
+use std::ffi::CString;
+
 use rvvm_sys::RVVM_DEFAULT_MEMBASE;
 
+#[cfg(feature = "internals")]
+use crate::trace::TraceConfig;
 use crate::{
     error::InstanceCreateError,
-    instance::Instance,
+    instance::{
+        Instance,
+        MachineOpt,
+    },
 };
 
+/// Builder of the `Instance`, fields may be added by the
+/// crate features, so it's created with the
+/// `Instance::builder` or `InstanceBuilder::default`
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct InstanceBuilder {
     pub harts: usize,
 
@@ -13,20 +24,65 @@ pub struct InstanceBuilder {
     pub mem_size: usize,
 
     pub rv64: bool,
+
+    /// Kernel command line that replaces the default one
+    pub cmdline: Option<CString>,
+    /// Arguments appended to the kernel command line
+    pub cmdline_append: Vec<CString>,
+
+    /// Machine options applied in order
+    pub opts: Vec<MachineOpt>,
+
+    /// Trace mode, disables the JIT. Set through the
+    /// `InstanceBuilder::trace`
+    #[cfg(feature = "internals")]
+    trace: Option<TraceConfig>,
 }
 
 impl InstanceBuilder {
     pub fn try_build(self) -> Result<Instance, InstanceCreateError> {
-        Instance::try_new(
+        let mut instance = Instance::try_new(
             self.harts,
             self.mem_base,
             self.mem_size,
             self.rv64,
-        )
+        )?;
+
+        if let Some(cmdline) = &self.cmdline {
+            instance.set_cmdline(cmdline);
+        }
+        for cmdline in &self.cmdline_append {
+            instance.append_cmdline(cmdline);
+        }
+
+        for opt in self.opts {
+            instance
+                .set_opt(opt)
+                .map_err(|_| InstanceCreateError::OptionRejected)?;
+        }
+
+        #[cfg(feature = "internals")]
+        if let Some(trace) = self.trace {
+            instance
+                .set_opt(MachineOpt::Jit(false))
+                .map_err(|_| InstanceCreateError::OptionRejected)?;
+            instance
+                .set_trace(Some(trace))
+                .map_err(|_| InstanceCreateError::TraceRejected)?;
+        }
+
+        Ok(instance)
     }
 
+    /// Builds the instance.
+    ///
+    /// # Panics
+    ///
+    /// Panics if underlying call to the `try_build`
+    /// returned an `Err`.
     pub fn build(self) -> Instance {
-        Instance::new(self.harts, self.mem_base, self.mem_size, self.rv64)
+        self.try_build()
+            .expect("Failed to create the machine")
     }
 }
 
@@ -50,6 +106,75 @@ impl InstanceBuilder {
         self.harts = harts;
         self
     }
+
+    /// Replaces the kernel command line.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cmdline` contains nul-byte character
+    pub fn cmdline(mut self, cmdline: impl AsRef<str>) -> Self {
+        self.cmdline = Some(
+            CString::new(cmdline.as_ref())
+                .expect("cmdline contains nul-byte character"),
+        );
+        self
+    }
+
+    /// Appends to the kernel command line.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cmdline` contains nul-byte character
+    pub fn append_cmdline(mut self, cmdline: impl AsRef<str>) -> Self {
+        self.cmdline_append.push(
+            CString::new(cmdline.as_ref())
+                .expect("cmdline contains nul-byte character"),
+        );
+        self
+    }
+
+    /// Sets the machine option, see `MachineOpt`
+    pub fn opt(mut self, opt: MachineOpt) -> Self {
+        self.opts.push(opt);
+        self
+    }
+
+    /// Enables the JIT compilation, enabled by default if
+    /// RVVM is built with the JIT
+    pub fn jit(self, enable: bool) -> Self {
+        self.opt(MachineOpt::Jit(enable))
+    }
+
+    /// Sets the per-core JIT cache size in bytes, RVVM
+    /// picks 16MiB by default
+    pub fn jit_cache(self, size: usize) -> Self {
+        self.opt(MachineOpt::JitCache(size))
+    }
+
+    /// Sets the RVVM logging level: 0 is silent, 1 errors,
+    /// 2 warnings (default) and 3 info messages
+    pub fn verbosity(self, level: u32) -> Self {
+        self.opt(MachineOpt::Verbosity(level))
+    }
+
+    /// Limits the CPU load in percents of a host core per
+    /// hart, 100 (no limit) by default
+    pub fn max_cpu_percent(self, percent: u32) -> Self {
+        self.opt(MachineOpt::MaxCpuPercent(percent))
+    }
+
+    /// Imitates the identity of the physical hardware,
+    /// disabled by default
+    pub fn hw_imitate(self, enable: bool) -> Self {
+        self.opt(MachineOpt::HwImitate(enable))
+    }
+
+    /// Enables the trace mode, see `Instance::set_trace`
+    #[cfg(feature = "internals")]
+    pub fn trace(mut self, config: TraceConfig) -> Self {
+        self.trace = Some(config);
+        self
+    }
 }
 
 impl Default for InstanceBuilder {
@@ -59,6 +184,13 @@ impl Default for InstanceBuilder {
             mem_base: RVVM_DEFAULT_MEMBASE as _,
             mem_size: 4096,
             rv64: false,
+
+            cmdline: None,
+            cmdline_append: Vec::new(),
+
+            opts: Vec::new(),
+            #[cfg(feature = "internals")]
+            trace: None,
         }
     }
 }
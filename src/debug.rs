@@ -0,0 +1,1194 @@
+use std::{
+    collections::HashMap,
+    ffi::c_void,
+    ops::Range,
+    ptr::NonNull,
+    sync::{
+        atomic::{
+            AtomicBool,
+            AtomicUsize,
+            Ordering,
+        },
+        mpsc::{
+            self,
+            Receiver,
+            Sender,
+        },
+        Arc,
+        Mutex,
+        MutexGuard,
+        PoisonError,
+    },
+    thread::{
+        self,
+        JoinHandle,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use rvvm_sys::{
+    rvvm_get_opt,
+    rvvm_hart_t,
+    rvvm_machine_powered_on,
+    rvvm_machine_t,
+    rvvm_pause_machine,
+    rvvm_read_ram,
+    rvvm_start_machine,
+    rvvm_write_ram,
+    REGISTER_PC,
+    RVVM_OPT_JIT,
+};
+
+use crate::{
+    error::{
+        DebugError,
+        MemoryAccessError,
+    },
+    hart::{
+        csr_read,
+        Privilege,
+    },
+    insn::*,
+    instance::Instance,
+    mmu::{
+        translate_with,
+        Access,
+        Satp,
+    },
+    trace::{
+        TraceEntry,
+        Tracer,
+    },
+};
+
+/// `jal x0, 0`, keeps the hart spinning at the address
+const SPIN: u32 = 0x0000_006f;
+/// `c.j 0`
+const C_SPIN: u16 = 0xa001;
+
+/// Steps made by the trace mode per monitor iteration
+const TRACE_BATCH: usize = 256;
+
+/// First delay between the start and the pause of the
+/// machine while waiting for the stop. Every step takes at
+/// least this long, which limits the trace mode to about
+/// 20k instructions per second.
+const MIN_POLL: Duration = Duration::from_micros(50);
+const MAX_POLL: Duration = Duration::from_millis(5);
+
+/// Time given to the hart to execute single instruction,
+/// e.g. `wfi` may never complete
+const STEP_TIMEOUT: Duration = Duration::from_millis(100);
+
+const CSR_SEPC: u16 = 0x141;
+const CSR_MEPC: u16 = 0x341;
+const CSR_STVEC: u16 = 0x105;
+const CSR_MTVEC: u16 = 0x305;
+const CSR_SATP: u16 = 0x180;
+
+/// Copyable view of the machine used for the execution
+/// control. Unlike the `Instance` it can be sent to the
+/// other threads.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MachineView {
+    ptr: NonNull<rvvm_machine_t>,
+    harts: usize,
+    rv64: bool,
+}
+
+// SAFETY: RVVM machine control functions are thread-safe,
+// hart state is accessed only while machine is paused
+unsafe impl Send for MachineView {}
+unsafe impl Sync for MachineView {}
+
+impl Instance {
+    /// Get view of the machine for the execution control,
+    /// view must not outlive the instance
+    pub(crate) fn view(&self) -> MachineView {
+        MachineView {
+            ptr: NonNull::new(self.as_ptr()).unwrap(),
+            harts: self.harts(),
+            rv64: self.is_rv64(),
+        }
+    }
+}
+
+/// Starts and pauses the machine for the `run_until`
+pub(crate) trait ExecutionControl {
+    fn start(&mut self) -> bool;
+    fn pause(&mut self) -> bool;
+}
+
+/// Leaves the `running` flag of the `Instance` as is, used
+/// by the monitor, which keeps machine running from the
+/// user's point of view
+impl ExecutionControl for MachineView {
+    fn start(&mut self) -> bool {
+        MachineView::start(self)
+    }
+
+    fn pause(&mut self) -> bool {
+        MachineView::pause(self)
+    }
+}
+
+impl ExecutionControl for Instance {
+    fn start(&mut self) -> bool {
+        Instance::start(self).is_ok()
+    }
+
+    fn pause(&mut self) -> bool {
+        Instance::pause(self).is_ok()
+    }
+}
+
+impl Instance {
+    /// Check whether JIT is enabled, patched code is not
+    /// noticed by the JIT
+    pub(crate) fn jit_enabled(&self) -> bool {
+        // SAFETY: `self.as_ptr()` is obtained from
+        // `rvvm_create_machine`
+        unsafe { rvvm_get_opt(self.as_ptr(), RVVM_OPT_JIT) != 0 }
+    }
+}
+
+impl MachineView {
+    pub(crate) const fn harts(&self) -> usize {
+        self.harts
+    }
+
+    pub(crate) fn start(&self) -> bool {
+        // SAFETY: view doesn't outlive the machine
+        unsafe { rvvm_start_machine(self.ptr.as_ptr()) }
+    }
+
+    pub(crate) fn pause(&self) -> bool {
+        // SAFETY: view doesn't outlive the machine
+        unsafe { rvvm_pause_machine(self.ptr.as_ptr()) }
+    }
+
+    pub(crate) fn powered_on(&self) -> bool {
+        // SAFETY: view doesn't outlive the machine
+        unsafe { rvvm_machine_powered_on(self.ptr.as_ptr()) }
+    }
+
+    pub(crate) fn read(&self, paddr: u64, dest: &mut [u8]) -> bool {
+        // SAFETY: `dest` is valid for `dest.len()` bytes
+        unsafe {
+            rvvm_read_ram(
+                self.ptr.as_ptr(),
+                dest.as_mut_ptr() as *mut c_void,
+                paddr,
+                dest.len(),
+            )
+        }
+    }
+
+    pub(crate) fn write(&self, paddr: u64, data: &[u8]) -> bool {
+        // SAFETY: `data` is valid for `data.len()` bytes
+        unsafe {
+            rvvm_write_ram(
+                self.ptr.as_ptr(),
+                paddr,
+                data.as_ptr() as *mut c_void,
+                data.len(),
+            )
+        }
+    }
+
+    fn hart(&self, id: usize) -> *mut rvvm_hart_t {
+        assert!(id < self.harts, "hart id is out of range");
+
+        // SAFETY: machine is created with `harts` harts
+        unsafe { *(*self.ptr.as_ptr()).harts.data.add(id) }
+    }
+
+    /// Get `pc` of the hart, machine must be paused
+    pub(crate) fn pc(&self, hart: usize) -> u64 {
+        // SAFETY: hart is valid and paused
+        let pc =
+            unsafe { (*self.hart(hart)).registers[REGISTER_PC as usize] };
+        self.xlen(pc)
+    }
+
+    /// Reads the CSR of the paused hart, zero if it's
+    /// rejected
+    pub(crate) fn csr(&self, hart: usize, csr: u16) -> u64 {
+        // SAFETY: hart is valid and paused
+        unsafe { csr_read(self.hart(hart), csr) }.unwrap_or(0)
+    }
+
+    fn privilege(&self, hart: usize) -> Privilege {
+        // SAFETY: hart is valid and paused
+        Privilege::from_raw(unsafe { (*self.hart(hart)).priv_mode })
+    }
+
+    /// Translates virtual address as seen by the paused
+    /// hart. Permissions are ignored, since debugger may
+    /// access any mapped memory.
+    pub(crate) fn translate(
+        &self,
+        hart: usize,
+        vaddr: u64,
+    ) -> Option<u64> {
+        if self.privilege(hart) == Privilege::Machine {
+            return Some(vaddr);
+        }
+
+        self.translate_satp(self.csr(hart, CSR_SATP), vaddr)
+    }
+
+    fn translate_satp(&self, satp: u64, vaddr: u64) -> Option<u64> {
+        let satp = Satp::decode(satp, self.rv64).ok()?;
+        [Access::Read, Access::Execute, Access::Write]
+            .into_iter()
+            .find_map(|access| {
+                translate_with(satp, vaddr, access, |addr, size| {
+                    let mut pte = [0; 8];
+                    if self.read(addr, &mut pte[..size as usize]) {
+                        Ok(u64::from_le_bytes(pte))
+                    } else {
+                        Err(MemoryAccessError::OutOfBounds {
+                            start: addr,
+                            end: addr + size,
+                        })
+                    }
+                })
+                .ok()
+            })
+            .map(|page| page.phys_addr)
+    }
+
+    /// Fetches instruction at the `pc` of the hart
+    fn fetch(&self, hart: usize, pc: u64) -> Option<u32> {
+        let paddr = self.translate(hart, pc)?;
+        let mut insn = [0; 4];
+        if !self.read(paddr, &mut insn[..2]) {
+            return None;
+        }
+
+        let len = insn_len(u16::from_le_bytes([insn[0], insn[1]]));
+        if len == 4 && !self.read(paddr + 2, &mut insn[2..]) {
+            return None;
+        }
+
+        Some(u32::from_le_bytes(insn))
+    }
+
+    const fn xlen(&self, value: u64) -> u64 {
+        if self.rv64 {
+            value
+        } else {
+            value as u32 as u64
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StopKind {
+    /// Hart reached the breakpoint
+    Breakpoint,
+    /// Hart executed single instruction
+    Step,
+    /// Execution was interrupted by the caller
+    Interrupted,
+    PoweredOff,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Stop {
+    pub hart: usize,
+    pub pc: u64,
+    pub kind: StopKind,
+}
+
+#[derive(Debug)]
+struct Patch {
+    paddr: u64,
+    original: Vec<u8>,
+}
+
+impl Patch {
+    /// Replaces instruction at the `paddr` with the jump to
+    /// itself
+    fn apply(m: &MachineView, paddr: u64) -> Option<Self> {
+        let mut low = [0; 2];
+        if !m.read(paddr, &mut low) {
+            return None;
+        }
+
+        let mut original =
+            vec![0; insn_len(u16::from_le_bytes(low)) as usize];
+        if !m.read(paddr, &mut original) {
+            return None;
+        }
+
+        let written = if original.len() == 4 {
+            m.write(paddr, &SPIN.to_le_bytes())
+        } else {
+            m.write(paddr, &C_SPIN.to_le_bytes())
+        };
+        written.then_some(Self { paddr, original })
+    }
+
+    fn revert(&self, m: &MachineView) {
+        m.write(self.paddr, &self.original);
+    }
+}
+
+/// Software breakpoints and execution control.
+///
+/// Breakpoint replaces the instruction with the jump to
+/// itself, so hart spins at the address until the
+/// breakpoint is removed. Machine is periodically paused
+/// to check whether some hart reached the breakpoint.
+/// Single-step places such spins at every possible next
+/// instruction, including trap vectors.
+///
+/// Breakpoints are keyed by the physical address, so they
+/// are hit regardless of the address space.
+///
+/// JIT must be disabled, since patched code is not
+/// recompiled.
+#[derive(Debug, Default)]
+pub(crate) struct Breakpoints {
+    patches: HashMap<u64, Patch>,
+}
+
+impl Breakpoints {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.patches.is_empty()
+    }
+
+    pub(crate) fn contains_phys(&self, paddr: u64) -> bool {
+        self.patches.contains_key(&paddr)
+    }
+
+    /// Check whether there is a breakpoint at the virtual
+    /// address as seen by the paused `hart`
+    pub(crate) fn contains(
+        &self,
+        m: &MachineView,
+        hart: usize,
+        vaddr: u64,
+    ) -> bool {
+        !self.is_empty()
+            && m.translate(hart, vaddr)
+                .is_some_and(|paddr| self.contains_phys(paddr))
+    }
+
+    /// Inserts breakpoint at the physical address, machine
+    /// must be paused.
+    ///
+    /// Returns `false` if address is outside of the RAM.
+    pub(crate) fn insert_phys(
+        &mut self,
+        m: &MachineView,
+        paddr: u64,
+    ) -> bool {
+        if self.contains_phys(paddr) {
+            return true;
+        }
+
+        match Patch::apply(m, paddr) {
+            Some(patch) => {
+                self.patches.insert(paddr, patch);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes breakpoint at the physical address, machine
+    /// must be paused.
+    ///
+    /// Returns `false` if there was no breakpoint.
+    pub(crate) fn remove_phys(
+        &mut self,
+        m: &MachineView,
+        paddr: u64,
+    ) -> bool {
+        match self.patches.remove(&paddr) {
+            Some(patch) => {
+                patch.revert(m);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Puts the original instructions back into the copy
+    /// of the guest memory that starts at the `base`
+    pub(crate) fn lift(&self, base: u64, data: &mut [u8]) {
+        let end = base + data.len() as u64;
+        for patch in self.patches.values() {
+            let patch_end = patch.paddr + patch.original.len() as u64;
+            let (start, stop) =
+                (patch.paddr.max(base), patch_end.min(end));
+            if start >= stop {
+                continue;
+            }
+
+            let offset = (start - patch.paddr) as usize;
+            let len = (stop - start) as usize;
+            data[(start - base) as usize..][..len]
+                .copy_from_slice(&patch.original[offset..offset + len]);
+        }
+    }
+
+    /// Patches the instructions again once the RAM is
+    /// overwritten, original instructions are read from the
+    /// new contents. Machine must be paused.
+    fn reapply(&mut self, m: &MachineView) {
+        self.patches.retain(|&paddr, patch| {
+            match Patch::apply(m, paddr) {
+                Some(applied) => {
+                    *patch = applied;
+                    true
+                }
+                None => false,
+            }
+        });
+    }
+
+    /// Executes single instruction on the `hart`, other
+    /// harts are running meanwhile. Step is completed once
+    /// the hart leaves the instruction, even if it went
+    /// somewhere unpredicted (e.g. vectored trap) and ran
+    /// further. Stepping is given up once `interrupt`
+    /// returns `true` or after the `STEP_TIMEOUT`. Machine
+    /// must be paused and is left paused.
+    pub(crate) fn step(
+        &mut self,
+        m: &MachineView,
+        exec: &mut impl ExecutionControl,
+        hart: usize,
+        mut interrupt: impl FnMut() -> bool,
+    ) -> Stop {
+        let deadline = Instant::now() + STEP_TIMEOUT;
+        let pc = m.pc(hart);
+        let initial: Vec<u64> =
+            (0..m.harts()).map(|id| m.pc(id)).collect();
+
+        let lifted = m
+            .translate(hart, pc)
+            .and_then(|paddr| self.patches.remove(&paddr));
+        if let Some(patch) = &lifted {
+            patch.revert(m);
+        }
+
+        let mut targets = match m.fetch(hart, pc) {
+            Some(insn) => successors(
+                insn,
+                pc,
+                m.rv64,
+                |reg| m.xlen(read_x(m, hart, reg)),
+                |csr| m.csr(hart, csr),
+            ),
+            None => Vec::new(),
+        };
+        // Jump to itself never leaves the instruction
+        let spins = !targets.is_empty()
+            && targets.iter().all(|&target| target == pc);
+
+        // Exceptions and interrupts lead to the trap vectors
+        let mtvec = m.csr(hart, CSR_MTVEC) & !0b11;
+        let stvec = m.csr(hart, CSR_STVEC) & !0b11;
+        targets.extend([mtvec, stvec]);
+        targets.retain(|&target| target != pc && target != 0);
+        targets.sort_unstable();
+        targets.dedup();
+
+        let mut temps = Vec::new();
+        for &target in &targets {
+            let paddr = if target == mtvec {
+                Some(target)
+            } else if target == stvec {
+                m.translate_satp(m.csr(hart, CSR_SATP), target)
+            } else {
+                m.translate(hart, target)
+            };
+            let paddr = paddr.filter(|&paddr| !self.contains_phys(paddr));
+            temps.extend(paddr.and_then(|paddr| Patch::apply(m, paddr)));
+        }
+
+        let stop = if spins || targets.is_empty() {
+            Stop {
+                hart,
+                pc,
+                kind: StopKind::Step,
+            }
+        } else {
+            run_until(
+                m,
+                exec,
+                |id, at| {
+                    if id == hart && at != pc {
+                        Some(StopKind::Step)
+                    } else if at != initial[id] && self.contains(m, id, at)
+                    {
+                        Some(StopKind::Breakpoint)
+                    } else {
+                        None
+                    }
+                },
+                || interrupt() || Instant::now() >= deadline,
+            )
+        };
+
+        for patch in temps.iter().rev() {
+            patch.revert(m);
+        }
+        if let Some(patch) = lifted {
+            if let Some(patch) = Patch::apply(m, patch.paddr) {
+                self.patches.insert(patch.paddr, patch);
+            }
+        }
+
+        stop
+    }
+
+    /// Runs the machine until some hart reaches the
+    /// breakpoint or `interrupt` returns `true`. Harts
+    /// standing at the breakpoints are stepped over first.
+    /// Machine must be paused and is left paused.
+    pub(crate) fn resume(
+        &mut self,
+        m: &MachineView,
+        exec: &mut impl ExecutionControl,
+        mut interrupt: impl FnMut() -> bool,
+    ) -> Stop {
+        for hart in 0..m.harts() {
+            if self.contains(m, hart, m.pc(hart)) {
+                let stop = self.step(m, exec, hart, &mut interrupt);
+                if stop.kind != StopKind::Step {
+                    return stop;
+                }
+            }
+        }
+
+        run_until(
+            m,
+            exec,
+            |id, at| {
+                self.contains(m, id, at)
+                    .then_some(StopKind::Breakpoint)
+            },
+            interrupt,
+        )
+    }
+}
+
+fn read_x(m: &MachineView, hart: usize, reg: usize) -> u64 {
+    // SAFETY: hart is valid and paused
+    unsafe { (*m.hart(hart)).registers[reg] }
+}
+
+/// Starts the machine through the `exec` and polls harts
+/// until `check` returns the stop reason, machine is left
+/// paused
+pub(crate) fn run_until(
+    m: &MachineView,
+    exec: &mut impl ExecutionControl,
+    mut check: impl FnMut(usize, u64) -> Option<StopKind>,
+    mut interrupt: impl FnMut() -> bool,
+) -> Stop {
+    let mut delay = MIN_POLL;
+
+    loop {
+        exec.start();
+        thread::sleep(delay);
+        exec.pause();
+        delay = (delay * 2).min(MAX_POLL);
+
+        if !m.powered_on() {
+            return Stop {
+                hart: 0,
+                pc: m.pc(0),
+                kind: StopKind::PoweredOff,
+            };
+        }
+
+        for hart in 0..m.harts() {
+            let pc = m.pc(hart);
+            if let Some(kind) = check(hart, pc) {
+                return Stop { hart, pc, kind };
+            }
+        }
+
+        if interrupt() {
+            return Stop {
+                hart: 0,
+                pc: m.pc(0),
+                kind: StopKind::Interrupted,
+            };
+        }
+    }
+}
+
+/// Predicts addresses of the next instruction after the
+/// `insn` at the `pc`. Both branch outcomes are returned,
+/// traps are not taken into account.
+pub(crate) fn successors(
+    insn: u32,
+    pc: u64,
+    rv64: bool,
+    x: impl Fn(usize) -> u64,
+    csr: impl Fn(u16) -> u64,
+) -> Vec<u64> {
+    let offset = |imm: i64| pc.wrapping_add(imm as u64);
+    let next = pc.wrapping_add(insn_len(insn as u16));
+
+    let targets = if insn_len(insn as u16) == 2 {
+        let quadrant = insn & 0b11;
+        let funct3 = (insn >> 13) & 0b111;
+
+        match (quadrant, funct3) {
+            // c.j, c.jal
+            (0b01, 0b101) => vec![offset(c_imm_j(insn))],
+            (0b01, 0b001) if !rv64 => vec![offset(c_imm_j(insn))],
+            // c.beqz, c.bnez
+            (0b01, 0b110 | 0b111) => vec![next, offset(c_imm_b(insn))],
+            (0b10, 0b100) => {
+                let rs1 = ((insn >> 7) & 0x1f) as usize;
+                let rs2 = (insn >> 2) & 0x1f;
+
+                match (rs1, rs2) {
+                    // c.jr, c.jalr
+                    (1.., 0) => vec![x(rs1) & !1],
+                    // c.ebreak
+                    (0, 0) if insn & (1 << 12) != 0 => Vec::new(),
+                    _ => vec![next],
+                }
+            }
+            _ => vec![next],
+        }
+    } else {
+        match opcode(insn) {
+            // jal
+            0x6f => vec![offset(imm_j(insn))],
+            // jalr
+            0x67 => {
+                vec![x(rs1(insn)).wrapping_add(imm_i(insn) as u64) & !1]
+            }
+            // branches
+            0x63 => vec![next, offset(imm_b(insn))],
+            0x73 => match insn {
+                // mret, sret
+                0x3020_0073 => vec![csr(CSR_MEPC)],
+                0x1020_0073 => vec![csr(CSR_SEPC)],
+                // ecall, ebreak
+                0x0000_0073 | 0x0010_0073 => Vec::new(),
+                _ => vec![next],
+            },
+            _ => vec![next],
+        }
+    };
+
+    targets
+        .into_iter()
+        .map(|target| if rv64 { target } else { target as u32 as u64 })
+        .collect()
+}
+
+/// Kind of the guest memory access caught by the
+/// watchpoint. Only writes are supported, since guest
+/// reads can't be observed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WatchKind {
+    Write,
+    /// Rejected by the `Instance::add_watchpoint`
+    Read,
+    /// Reads or writes, rejected by the
+    /// `Instance::add_watchpoint`
+    Access,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    /// Hart reached the breakpoint at the physical address
+    Breakpoint(u64),
+    /// Contents of the watched physical range changed
+    Watchpoint { range: Range<u64>, kind: WatchKind },
+}
+
+/// Sent when the machine is paused by the breakpoint or
+/// the watchpoint, see `Instance::stop_events`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StopEvent {
+    /// Hart that reached the breakpoint, `None` for the
+    /// watchpoints, since writer of the memory is unknown
+    pub hart: Option<usize>,
+    /// `pc` of the hart, `None` for the watchpoints
+    pub pc: Option<u64>,
+    pub reason: StopReason,
+}
+
+#[derive(Debug)]
+struct Watchpoint {
+    range: Range<u64>,
+    kind: WatchKind,
+    /// Contents of the range as of the last check
+    value: Vec<u8>,
+}
+
+/// Breakpoints and watchpoints set from the host
+#[derive(Debug, Default)]
+pub(crate) struct Points {
+    pub(crate) breakpoints: Breakpoints,
+    watchpoints: Vec<Watchpoint>,
+    events: Option<Sender<StopEvent>>,
+    pub(crate) tracer: Option<Tracer>,
+    /// Machine is left paused by the monitor between the
+    /// trace batches, while `Instance` treats it as running
+    pub(crate) trace_paused: bool,
+}
+
+impl Points {
+    fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty()
+            && self.watchpoints.is_empty()
+            && self.tracer.is_none()
+    }
+
+    /// Steps the hart, recording the instruction if the
+    /// hart is traced and the step is completed
+    fn step(
+        &mut self,
+        m: &MachineView,
+        hart: usize,
+        interrupt: impl FnMut() -> bool,
+    ) -> Stop {
+        let entry = self
+            .tracer
+            .as_ref()
+            .filter(|tracer| tracer.hart() == hart)
+            .and_then(|_| {
+                let pc = m.pc(hart);
+                let insn = m.fetch(hart, pc)?;
+                Some(TraceEntry { hart, pc, insn })
+            });
+
+        let stop = self
+            .breakpoints
+            .step(m, &mut { *m }, hart, interrupt);
+        if let (Some(tracer), Some(entry)) = (&mut self.tracer, entry) {
+            if stop.kind == StopKind::Step {
+                tracer.record(entry);
+            }
+        }
+
+        stop
+    }
+
+    /// Steps the traced hart for a while, stops early if
+    /// there is a stop reason or `cancel` returns `true`
+    fn trace(
+        &mut self,
+        m: &MachineView,
+        mut cancel: impl FnMut() -> bool,
+    ) -> Option<StopEvent> {
+        for _ in 0..TRACE_BATCH {
+            if let Some(event) = self.check(m) {
+                return Some(event);
+            }
+            if cancel() {
+                break;
+            }
+
+            let hart = self.tracer.as_ref()?.hart();
+            let stop = self.step(m, hart, &mut cancel);
+            if matches!(
+                stop.kind,
+                StopKind::PoweredOff | StopKind::Interrupted
+            ) {
+                break;
+            }
+        }
+
+        None
+    }
+
+    /// Finds the stop reason, machine must be paused
+    pub(crate) fn check(&mut self, m: &MachineView) -> Option<StopEvent> {
+        for hart in 0..m.harts() {
+            let pc = m.pc(hart);
+            let paddr = m
+                .translate(hart, pc)
+                .filter(|&paddr| self.breakpoints.contains_phys(paddr));
+
+            if let Some(paddr) = paddr {
+                return Some(StopEvent {
+                    hart: Some(hart),
+                    pc: Some(pc),
+                    reason: StopReason::Breakpoint(paddr),
+                });
+            }
+        }
+
+        for watchpoint in &mut self.watchpoints {
+            let start = watchpoint.range.start;
+            let mut value = vec![0; watchpoint.value.len()];
+            if !m.read(start, &mut value) {
+                continue;
+            }
+
+            // Value is taken with the breakpoints lifted
+            self.breakpoints.lift(start, &mut value);
+            if value != watchpoint.value {
+                watchpoint.value = value;
+                return Some(StopEvent {
+                    hart: None,
+                    pc: None,
+                    reason: StopReason::Watchpoint {
+                        range: watchpoint.range.clone(),
+                        kind: watchpoint.kind,
+                    },
+                });
+            }
+        }
+
+        None
+    }
+}
+
+/// Background thread, which pauses the running machine
+/// every `MAX_POLL` to check the `Points`
+#[derive(Debug)]
+pub(crate) struct Monitor {
+    points: Arc<Mutex<Points>>,
+    running: Arc<AtomicBool>,
+    /// Set while the machine is paused by the monitor,
+    /// which is going to start it again
+    paused: Arc<AtomicBool>,
+    quit: Arc<AtomicBool>,
+    /// Number of threads waiting for the `points` lock,
+    /// monitor gives up stepping while there are any
+    waiters: Arc<AtomicUsize>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Monitor {
+    /// Spawns the monitor thread. `running` is the flag of
+    /// the `Instance`, monitor resets it when machine is
+    /// stopped. `paused` is set while the monitor keeps the
+    /// machine paused.
+    pub(crate) fn spawn(
+        m: MachineView,
+        running: Arc<AtomicBool>,
+        paused: Arc<AtomicBool>,
+    ) -> Self {
+        let points = Arc::new(Mutex::new(Points::default()));
+        let quit = Arc::new(AtomicBool::new(false));
+        let waiters = Arc::new(AtomicUsize::new(0));
+
+        let thread = {
+            let points = Arc::clone(&points);
+            let running = Arc::clone(&running);
+            let paused = Arc::clone(&paused);
+            let quit = Arc::clone(&quit);
+            let waiters = Arc::clone(&waiters);
+
+            thread::Builder::new()
+                .name("rvvm-monitor".to_owned())
+                .spawn(move || {
+                    let cancel = || {
+                        quit.load(Ordering::Acquire)
+                            || waiters.load(Ordering::Acquire) != 0
+                    };
+                    monitor_loop(
+                        m, &points, &running, &paused, &quit, cancel,
+                    )
+                })
+                .expect("Failed to spawn the monitor thread")
+        };
+
+        Self {
+            points,
+            running,
+            paused,
+            quit,
+            waiters,
+            thread: Some(thread),
+        }
+    }
+
+    /// Locks the points, monitor doesn't touch the machine
+    /// while the guard is alive. Monitor is asked to give
+    /// up stepping, so the lock is not held for long.
+    pub(crate) fn lock(&self) -> MutexGuard<'_, Points> {
+        self.waiters.fetch_add(1, Ordering::AcqRel);
+        let points = self
+            .points
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.waiters.fetch_sub(1, Ordering::AcqRel);
+
+        points
+    }
+
+    /// Steps harts over the breakpoints they stand at, so
+    /// they are not hit again. Hart that doesn't leave the
+    /// breakpoint in time (e.g. waits for an interrupt) is
+    /// left there and hits it again once the machine is
+    /// started. Machine must be started while the guard is
+    /// alive, unless it's paused between the trace batches
+    /// (see `Points::trace_paused`).
+    pub(crate) fn prepare_start(
+        &self,
+        m: &MachineView,
+    ) -> MutexGuard<'_, Points> {
+        let mut points = self.lock();
+        if self.running.load(Ordering::Acquire) || !m.powered_on() {
+            return points;
+        }
+
+        for hart in 0..m.harts() {
+            if points.breakpoints.contains(m, hart, m.pc(hart)) {
+                // Other stop reasons are caught by the monitor once
+                // machine is started
+                points.step(m, hart, || false);
+            }
+        }
+
+        points
+    }
+}
+
+impl Drop for Monitor {
+    fn drop(&mut self) {
+        self.quit.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        // Machine is not started again, e.g. between the trace
+        // batches
+        self.paused.store(false, Ordering::Release);
+    }
+}
+
+fn monitor_loop(
+    m: MachineView,
+    points: &Mutex<Points>,
+    running: &AtomicBool,
+    paused_by_monitor: &AtomicBool,
+    quit: &AtomicBool,
+    mut cancel: impl FnMut() -> bool,
+) {
+    let mut tracing = false;
+
+    while !quit.load(Ordering::Acquire) {
+        // Tracing keeps the hart stepping without delays
+        if tracing {
+            thread::yield_now();
+        } else {
+            thread::sleep(MAX_POLL);
+        }
+
+        let mut points = points
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let paused = std::mem::take(&mut points.trace_paused);
+        let active = running.load(Ordering::Acquire) && m.powered_on();
+        tracing = false;
+        if points.is_empty() || !active {
+            // Tracing was disabled between the batches
+            if paused && active {
+                m.start();
+            }
+            paused_by_monitor.store(false, Ordering::Release);
+            continue;
+        }
+        // Set before pausing, so the event loop that exits
+        // meanwhile is entered again, see the `Instance::run`
+        paused_by_monitor.store(true, Ordering::Release);
+        if !paused && !m.pause() {
+            paused_by_monitor.store(false, Ordering::Release);
+            continue;
+        }
+
+        tracing = points.tracer.is_some();
+        let event = if tracing {
+            points.trace(&m, &mut cancel)
+        } else {
+            points.check(&m)
+        };
+
+        match event {
+            Some(event) => {
+                running.store(false, Ordering::Release);
+                if let Some(events) = &points.events {
+                    let _ = events.send(event);
+                }
+                paused_by_monitor.store(false, Ordering::Release);
+            }
+            // Other harts run only while the traced one is
+            // stepped
+            None if tracing => points.trace_paused = true,
+            None => {
+                m.start();
+                paused_by_monitor.store(false, Ordering::Release);
+            }
+        }
+    }
+}
+
+impl Instance {
+    /// Get receiver of the `StopEvent`s, previously
+    /// obtained receiver is disconnected. Events are
+    /// dropped while there is no receiver.
+    pub fn stop_events(&mut self) -> Receiver<StopEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.monitor().lock().events = Some(sender);
+
+        receiver
+    }
+
+    /// Sets breakpoint at the guest physical address, e.g.
+    /// the address of the firmware symbol. Machine is
+    /// paused once some hart reaches it and `StopEvent`
+    /// is sent, execution is continued with the
+    /// `Instance::start`.
+    ///
+    /// Breakpoint patches the guest code, so JIT must be
+    /// disabled. RAM reads and snapshots show the original
+    /// instruction, except for the borrowed views (see the
+    /// `Instance::ram`), and breakpoints are patched again
+    /// after the RAM is restored. Hart spins at the
+    /// breakpoint until it's noticed.
+    ///
+    /// While any breakpoint or watchpoint is set, running
+    /// machine is paused and resumed every 5 ms to check
+    /// them, which stops all harts for the duration of the
+    /// check.
+    ///
+    /// - Returns `Ok` if breakpoint is set
+    /// - Returns `DebugError::JitEnabled` if JIT is enabled
+    /// - Returns `DebugError` if address is outside of RAM
+    pub fn add_breakpoint(&mut self, addr: u64) -> Result<(), DebugError> {
+        if self.with_points(|m, points| {
+            points.breakpoints.insert_phys(m, addr)
+        })? {
+            Ok(())
+        } else {
+            Err(DebugError::OutOfRam)
+        }
+    }
+
+    /// Returns `false` if there was no breakpoint at the
+    /// address
+    pub fn remove_breakpoint(&mut self, addr: u64) -> bool {
+        self.with_points(|m, points| {
+            points.breakpoints.remove_phys(m, addr)
+        })
+        .unwrap_or(false)
+    }
+
+    /// Watches the guest physical memory range. Machine is
+    /// paused once contents of the range change and
+    /// `StopEvent` is sent.
+    ///
+    /// Writes are not trapped: contents of the range are
+    /// compared every 5 ms while the machine is paused, see
+    /// the `Instance::add_breakpoint`. So guest runs for up
+    /// to 5 ms after the write, writes of the same value or
+    /// reverted in between are not noticed, and writer is
+    /// unknown.
+    ///
+    /// - Returns `Ok` if watchpoint is set
+    /// - Returns `DebugError::JitEnabled` if JIT is enabled
+    /// - Returns `DebugError::UnsupportedWatchKind` for
+    ///   anything but the `WatchKind::Write`
+    /// - Returns `DebugError` if range is empty or outside
+    ///   of RAM
+    pub fn add_watchpoint(
+        &mut self,
+        range: Range<u64>,
+        kind: WatchKind,
+    ) -> Result<(), DebugError> {
+        if kind != WatchKind::Write {
+            return Err(DebugError::UnsupportedWatchKind);
+        }
+        if range.is_empty() {
+            return Err(DebugError::EmptyRange);
+        }
+        let ram_end = self
+            .mem_base()
+            .checked_add(self.mem_size() as u64)
+            .ok_or(DebugError::OutOfRam)?;
+        if range.start < self.mem_base() || range.end > ram_end {
+            return Err(DebugError::OutOfRam);
+        }
+
+        let mut value = vec![0; (range.end - range.start) as usize];
+        self.read_ram_to(range.start, &mut value)
+            .map_err(|_| DebugError::OutOfRam)?;
+
+        self.with_points(|_, points| {
+            points
+                .watchpoints
+                .retain(|watchpoint| watchpoint.range != range);
+            points
+                .watchpoints
+                .push(Watchpoint { range, kind, value });
+        })
+    }
+
+    /// Returns `false` if there was no watchpoint with the
+    /// range
+    pub fn remove_watchpoint(&mut self, range: Range<u64>) -> bool {
+        self.with_points(|_, points| {
+            let len = points.watchpoints.len();
+            points
+                .watchpoints
+                .retain(|watchpoint| watchpoint.range != range);
+
+            points.watchpoints.len() != len
+        })
+        .unwrap_or(false)
+    }
+
+    /// Calls `f` with the points locked and machine paused.
+    /// Points can't be set while JIT is enabled, so
+    /// `DebugError::JitEnabled` is returned instead.
+    fn with_points<R>(
+        &mut self,
+        f: impl FnOnce(&MachineView, &mut Points) -> R,
+    ) -> Result<R, DebugError> {
+        if self.jit_enabled() {
+            return Err(DebugError::JitEnabled);
+        }
+
+        let m = self.view();
+        let running = self.is_running();
+        let mut points = self.monitor().lock();
+
+        let paused = running && m.pause();
+        let result = f(&m, &mut points);
+        if paused {
+            m.start();
+        }
+
+        Ok(result)
+    }
+
+    /// Puts the original instructions in place of the
+    /// breakpoints into the `data` read from the `paddr`
+    pub(crate) fn lift_breakpoints(&self, paddr: u64, data: &mut [u8]) {
+        if let Some(monitor) = self.spawned_monitor() {
+            monitor.lock().breakpoints.lift(paddr, data);
+        }
+    }
+
+    /// Patches the breakpoints again after the guest RAM is
+    /// overwritten, machine must be paused
+    pub(crate) fn repatch_breakpoints(&mut self) {
+        let m = self.view();
+        if let Some(monitor) = self.spawned_monitor() {
+            monitor.lock().breakpoints.reapply(&m);
+        }
+    }
+}
@@ -24,7 +24,7 @@ macro_rules! __ts_handler {
 
             /// Type-safe wrapper around the C-ABI handler
             #[repr(transparent)]
-            #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+            #[derive(Debug, Clone, Copy)]
             pub struct $name<T> {
                 pub(crate) inner: Option<$raw>,
                 _phantom: core::marker::PhantomData<T>,
@@ -52,6 +52,17 @@ macro_rules! __ts_handler {
                     }
                 }
             }
+
+            // Derives would require `T: PartialEq`, handlers are
+            // compared by address
+            impl<T> PartialEq for $name<T> {
+                fn eq(&self, other: &Self) -> bool {
+                    self.inner.map(|f| f as usize)
+                        == other.inner.map(|f| f as usize)
+                }
+            }
+
+            impl<T> Eq for $name<T> {}
         }
     };
 }
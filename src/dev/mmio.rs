@@ -1,3 +1,10 @@
+use std::{
+    ffi::c_void,
+    slice,
+};
+
+use rvvm_sys::rvvm_mmio_dev_t;
+
 pub trait DeviceExt {
     type DataTy;
 
@@ -13,7 +20,8 @@ pub trait DeviceExt {
 ///
 /// This trait is unsafe due to untyped raw pointer logic
 /// inside the implementation. It should be used only by the
-/// library
+/// library: implementors must be `repr(transparent)` to the
+/// `rvvm_mmio_dev_t` whose data is `Box<Self::Ty>`
 pub unsafe trait DeviceData {
     type Ty: Send + Sync;
 
@@ -37,4 +45,105 @@ pub trait Device<T>: DeviceData<Ty = T> {
         size: u8,
         offset: usize,
     ) -> Result<(), Self::Error>;
+
+    /// Serializes device state for the
+    /// `Instance::save_state`. Devices returning `None`
+    /// are not saved, which is the default.
+    fn save_state(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Restores device state produced by the
+    /// `Device::save_state`, machine is paused for the time
+    /// of the call.
+    ///
+    /// Returns `false` if the state is rejected.
+    fn load_state(&mut self, state: &[u8]) -> bool {
+        let _ = state;
+        false
+    }
+}
+
+/// C-ABI read handler, routes guest reads to the
+/// `Device::read` of the `Dev`.
+///
+/// # Safety
+///
+/// `dev` must point to the `rvvm_mmio_dev_t` that was
+/// created from the `Dev` and `dest` must be valid for
+/// `size` bytes.
+pub(crate) unsafe extern "C" fn read_trampoline<Ty, Dev>(
+    dev: *mut rvvm_mmio_dev_t,
+    dest: *mut c_void,
+    offset: usize,
+    size: u8,
+) -> bool
+where
+    Ty: Send + Sync,
+    Dev: Device<Ty>,
+{
+    // SAFETY: `Dev` is `repr(transparent)` to the
+    // `rvvm_mmio_dev_t`, see `Instance::try_attach_device`
+    let dev = &*(dev as *const Dev);
+    let dest = slice::from_raw_parts_mut(dest as *mut u8, size as usize);
+
+    dev.read(dest, size, offset).is_ok()
+}
+
+/// C-ABI write handler, routes guest writes to the
+/// `Device::write` of the `Dev`.
+///
+/// # Safety
+///
+/// Same as the `read_trampoline`
+pub(crate) unsafe extern "C" fn write_trampoline<Ty, Dev>(
+    dev: *mut rvvm_mmio_dev_t,
+    dest: *mut c_void,
+    offset: usize,
+    size: u8,
+) -> bool
+where
+    Ty: Send + Sync,
+    Dev: Device<Ty>,
+{
+    // SAFETY: see `read_trampoline`
+    let dev = &*(dev as *const Dev);
+    let dest = slice::from_raw_parts_mut(dest as *mut u8, size as usize);
+
+    dev.write(dest, size, offset).is_ok()
+}
+
+/// Calls the `Device::save_state` of the `Dev`.
+///
+/// # Safety
+///
+/// Same as the `read_trampoline`
+pub(crate) unsafe fn save_state_trampoline<Ty, Dev>(
+    dev: *mut rvvm_mmio_dev_t,
+) -> Option<Vec<u8>>
+where
+    Ty: Send + Sync,
+    Dev: Device<Ty>,
+{
+    // SAFETY: see `read_trampoline`
+    (*(dev as *const Dev)).save_state()
+}
+
+/// Calls the `Device::load_state` of the `Dev`.
+///
+/// # Safety
+///
+/// Same as the `read_trampoline`, also machine must be
+/// paused
+pub(crate) unsafe fn load_state_trampoline<Ty, Dev>(
+    dev: *mut rvvm_mmio_dev_t,
+    state: &[u8],
+) -> bool
+where
+    Ty: Send + Sync,
+    Dev: Device<Ty>,
+{
+    // SAFETY: see `read_trampoline`, nobody else accesses the
+    // device while machine is paused
+    (*(dev as *mut Dev)).load_state(state)
 }
@@ -1,2 +1,5 @@
 pub mod mmio;
+pub mod plic;
 pub mod type_;
+pub mod uart;
+pub mod uart16550;
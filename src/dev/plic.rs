@@ -0,0 +1,75 @@
+use std::ptr::NonNull;
+
+use rvvm_sys::{
+    plic_ctx_t,
+    plic_init_auto,
+    plic_send_irq,
+    rvvm_get_plic,
+};
+
+use crate::{
+    error::DeviceAttachError,
+    instance::Instance,
+};
+
+/// Handle to the PLIC of the machine, devices use it to
+/// deliver their interrupts.
+///
+/// Handle doesn't own the PLIC, it is valid while the
+/// machine is alive. Devices may safely keep it in their
+/// data, since the data never outlives the machine.
+#[derive(Debug, Clone, Copy)]
+pub struct Plic {
+    ptr: NonNull<plic_ctx_t>,
+}
+
+// SAFETY: RVVM's PLIC is synchronized internally,
+// interrupts are sent from any thread
+unsafe impl Send for Plic {}
+unsafe impl Sync for Plic {}
+
+impl Plic {
+    /// Sends the interrupt `irq` to the harts. Interrupt is
+    /// edge-triggered: it is pending until claimed by the
+    /// guest, no matter what happens to its source.
+    ///
+    /// - Returns `true` if interrupt is accepted
+    /// - Returns `false` if `irq` is out of range
+    ///
+    /// # Safety
+    ///
+    /// Machine the handle is obtained from must be alive
+    pub unsafe fn send_irq(&self, irq: u32) -> bool {
+        plic_send_irq(self.ptr.as_ptr(), irq)
+    }
+}
+
+impl Instance {
+    /// Get the PLIC of the machine.
+    ///
+    /// Returns `None` if machine has no PLIC.
+    pub fn plic(&self) -> Option<Plic> {
+        // SAFETY: `self.ptr` is valid
+        NonNull::new(unsafe { rvvm_get_plic(self.as_ptr()) })
+            .map(|ptr| Plic { ptr })
+    }
+
+    /// Attaches RVVM's PLIC at its default address, FDT
+    /// node is added by RVVM. Devices attached
+    /// afterwards deliver their interrupts through it.
+    ///
+    /// - Returns the handle of the attached PLIC
+    /// - Returns `DeviceAttachError` if machine already has
+    ///   a PLIC or its region is occupied
+    pub fn attach_plic(&mut self) -> Result<Plic, DeviceAttachError> {
+        if self.plic().is_some() {
+            return Err(DeviceAttachError::RegionIsOccupied);
+        }
+
+        // SAFETY: `self.ptr` is valid, PLIC is owned by the
+        // machine
+        NonNull::new(unsafe { plic_init_auto(self.as_ptr()) })
+            .map(|ptr| Plic { ptr })
+            .ok_or(DeviceAttachError::RegionIsOccupied)
+    }
+}
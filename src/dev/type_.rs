@@ -1,21 +1,216 @@
-use std::ffi::CStr;
+use std::{
+    ffi::{
+        CStr,
+        CString,
+    },
+    marker::PhantomData,
+    ptr,
+};
 
-/// Unsafe functions of the DeviceType
+use rvvm_sys::{
+    rvvm_mmio_dev_t,
+    rvvm_mmio_type_t,
+};
+
+use crate::{
+    __ts_handler,
+    c_str,
+    dev::mmio::DeviceData,
+    internal_utils::free_boxed_voidptr,
+};
+
+__ts_handler!(
+    name = TypeHandler,
+    raw = unsafe extern "C" fn(dev: *mut rvvm_mmio_dev_t)
+);
+__ts_handler!(
+    name = RemoveHandler,
+    raw = unsafe extern "C" fn(dev: *mut rvvm_mmio_dev_t)
+);
+
+/// Type of the MMIO device: name and callbacks that RVVM
+/// calls during the device lifetime.
+///
+/// - `reset` is called on the machine reset
+/// - `update` is called periodically by the event loop
+/// - `remove` is called when device is removed from the
+///   machine, it is responsible for dropping the device
+///   data. Empty remove handler just drops the data
+///
+/// Handlers are made with the `#[type_handler]` and
+/// `#[on_remove]` macros, explicit data type must match the
+/// device:
+///
+/// ```compile_fail
+/// use rvvm::{
+///     macros::type_handler,
+///     types::UnsafeDevice,
+/// };
+///
+/// #[type_handler(ty = "u32")]
+/// fn on_reset(_dev: &UnsafeDevice<String>) {}
+/// ```
+pub struct DeviceType<T> {
+    name: CString,
+
+    remove: RemoveHandler<T>,
+    update: TypeHandler<T>,
+    reset: TypeHandler<T>,
+}
+
+impl<T: Send + Sync> DeviceType<T> {
+    /// Creates device type with the specified callbacks.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` contains nul-byte character
+    pub fn custom(
+        name: impl AsRef<str>,
+        remove: RemoveHandler<T>,
+        update: TypeHandler<T>,
+        reset: TypeHandler<T>,
+    ) -> Self {
+        Self {
+            name: CString::new(name.as_ref())
+                .expect("name contains nul-byte character"),
+            remove,
+            update,
+            reset,
+        }
+    }
+
+    /// Creates device type without callbacks, device data
+    /// will be just dropped on removal.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` contains nul-byte character
+    pub fn new(name: impl AsRef<str>) -> Self {
+        Self::custom(
+            name,
+            RemoveHandler::none(),
+            TypeHandler::none(),
+            TypeHandler::none(),
+        )
+    }
+
+    /// Get name of the device type
+    pub fn name(&self) -> &CStr {
+        &self.name
+    }
+
+    /// Replace remove handler
+    pub fn on_remove(mut self, remove: RemoveHandler<T>) -> Self {
+        self.remove = remove;
+        self
+    }
+
+    /// Replace update handler
+    pub fn on_update(mut self, update: TypeHandler<T>) -> Self {
+        self.update = update;
+        self
+    }
+
+    /// Replace reset handler
+    pub fn on_reset(mut self, reset: TypeHandler<T>) -> Self {
+        self.reset = reset;
+        self
+    }
+
+    pub(crate) fn into_raw(self) -> Box<RawDeviceType> {
+        let raw = rvvm_mmio_type_t {
+            remove: self.remove.inner.or(Some(remove_trampoline::<T>)),
+            update: self.update.inner,
+            reset: self.reset.inner,
+            // Heap buffer of the `CString` is not moved along with
+            // the `RawDeviceType`, so pointer stays valid
+            name: self.name.as_ptr(),
+        };
+
+        Box::new(RawDeviceType {
+            raw,
+            _name: self.name,
+        })
+    }
+}
+
+/// Owned `rvvm_mmio_type_t`, must outlive every device it
+/// is linked to.
+pub(crate) struct RawDeviceType {
+    raw: rvvm_mmio_type_t,
+    _name: CString,
+}
+
+impl RawDeviceType {
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut rvvm_mmio_type_t {
+        &mut self.raw
+    }
+}
+
+/// C-ABI remove handler, frees the data of the device
+///
+/// # Safety
+///
+/// `dev` must point to the `rvvm_mmio_dev_t` whose data was
+/// allocated as `Box<Ty>`
+pub(crate) unsafe extern "C" fn remove_trampoline<Ty>(
+    dev: *mut rvvm_mmio_dev_t,
+) {
+    let dev = &mut *dev;
+    if !dev.data.is_null() {
+        free_boxed_voidptr::<Ty>(dev.data);
+        dev.data = ptr::null_mut();
+    }
+}
+
+/// Fails to compile unless `T` is the data type of the
+/// `Dev`, used by the `#[type_handler]` and `#[on_remove]`
+/// macros to check the explicit `ty`.
+#[doc(hidden)]
+pub const fn __assert_data_type<Dev: DeviceData<Ty = T>, T>() {}
+
+/// Calls type handler with the typed device, used by the
+/// `#[type_handler]` macro.
 ///
 /// # Safety
 ///
-/// This trait is meant to be unsafe due to internal
-/// implementation
-pub unsafe trait UnsafeDeviceType {
-    fn name(&self) -> &CStr;
+/// `dev` must point to the `rvvm_mmio_dev_t` whose data is
+/// of type `Dev::Ty`
+#[doc(hidden)]
+pub unsafe fn __call_type_handler<Dev: DeviceData>(
+    dev: *mut rvvm_mmio_dev_t,
+    handler: fn(&Dev),
+) {
+    // SAFETY: `Dev` is `repr(transparent)` to the
+    // `rvvm_mmio_dev_t`, see `DeviceData`
+    handler(&*(dev as *const Dev))
 }
 
-pub trait DeviceType: UnsafeDeviceType {
-    type Device;
+/// Calls remove handler with the typed device and then
+/// drops the device data, used by the `#[on_remove]` macro.
+///
+/// # Safety
+///
+/// Same as the `__call_type_handler`
+#[doc(hidden)]
+pub unsafe fn __call_remove_handler<Dev: DeviceData>(
+    dev: *mut rvvm_mmio_dev_t,
+    handler: fn(&mut Dev),
+) {
+    handler(&mut *(dev as *mut Dev));
+    remove_trampoline::<Dev::Ty>(dev);
+}
 
-    /// TODO
-    fn reset(&mut self, dev: &mut Self::Device);
+/// Device type that is used for devices attached without
+/// the explicit type, only frees the device data on
+/// removal
+pub(crate) struct DefaultType<Ty>(PhantomData<Ty>);
 
-    /// TODO
-    fn update(&mut self, dev: &mut Self::Device);
+impl<Ty: Send + Sync> DefaultType<Ty> {
+    pub(crate) const RAW: &'static rvvm_mmio_type_t = &rvvm_mmio_type_t {
+        remove: Some(remove_trampoline::<Ty>),
+        update: None,
+        reset: None,
+        name: c_str!("rust_device").as_ptr(),
+    };
 }
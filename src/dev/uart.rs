@@ -0,0 +1,374 @@
+use std::{
+    collections::VecDeque,
+    ffi::{
+        c_void,
+        CStr,
+    },
+    io::{
+        self,
+        Read,
+        Write,
+    },
+    mem,
+    slice,
+    sync::{
+        Arc,
+        Condvar,
+        Mutex,
+        MutexGuard,
+        PoisonError,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use rvvm_sys::{
+    chardev_t,
+    ns16550a_init,
+    rvvm_get_plic,
+    CHARDEV_RX,
+    CHARDEV_TX,
+};
+
+use crate::{
+    error::DeviceAttachError,
+    fdt::*,
+    instance::Instance,
+};
+
+/// Size of the NS16550A register window
+pub const UART_MMIO_SIZE: u64 = 8;
+
+/// Guest output kept while nobody reads it, older bytes
+/// are dropped
+const OUTPUT_LIMIT: usize = 1 << 20;
+
+#[derive(Debug, Default)]
+struct ConsoleState {
+    /// Host -> guest
+    input: VecDeque<u8>,
+    /// Guest -> host
+    output: VecDeque<u8>,
+    /// Device is removed, no more output will appear
+    closed: bool,
+}
+
+/// Byte queues between the UART device and the host
+/// streams
+#[derive(Debug, Default)]
+pub(crate) struct Console {
+    state: Mutex<ConsoleState>,
+    output_ready: Condvar,
+}
+
+impl Console {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::default()
+    }
+
+    /// Creates host side streams of the console
+    pub(crate) fn streams(self: &Arc<Self>) -> (UartReader, UartWriter) {
+        (
+            UartReader {
+                console: Arc::clone(self),
+                timeout: None,
+            },
+            UartWriter {
+                console: Arc::clone(self),
+            },
+        )
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ConsoleState> {
+        self.state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn has_input(&self) -> bool {
+        !self.lock().input.is_empty()
+    }
+
+    /// Moves pending input to the `buf`, returns amount of
+    /// the moved bytes
+    pub(crate) fn take_input(&self, buf: &mut [u8]) -> usize {
+        let mut state = self.lock();
+        let len = buf.len().min(state.input.len());
+        for (dest, byte) in buf.iter_mut().zip(state.input.drain(..len)) {
+            *dest = byte;
+        }
+
+        len
+    }
+
+    pub(crate) fn push_output(&self, data: &[u8]) {
+        let mut state = self.lock();
+        state.output.extend(data);
+        let excess = state.output.len().saturating_sub(OUTPUT_LIMIT);
+        state.output.drain(..excess);
+
+        self.output_ready.notify_all();
+    }
+
+    /// Marks the console as closed, readers get EOF once
+    /// the remaining output is consumed
+    pub(crate) fn close(&self) {
+        self.lock().closed = true;
+        self.output_ready.notify_all();
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.lock().closed
+    }
+}
+
+/// Reads the guest output of the UART.
+///
+/// Reads block until some output is available, EOF is
+/// returned after the device is removed.
+#[derive(Debug)]
+pub struct UartReader {
+    console: Arc<Console>,
+    timeout: Option<Duration>,
+}
+
+impl UartReader {
+    /// Sets the timeout of the blocking reads, `None` means
+    /// forever. Timed out read fails with the
+    /// `io::ErrorKind::TimedOut`.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Get amount of the output that can be read without
+    /// blocking
+    pub fn available(&self) -> usize {
+        self.console.lock().output.len()
+    }
+}
+
+impl Read for UartReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let deadline = self
+            .timeout
+            .map(|timeout| Instant::now() + timeout);
+        let mut state = self.console.lock();
+
+        while state.output.is_empty() && !state.closed {
+            state = match deadline {
+                Some(deadline) => {
+                    let left =
+                        deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        return Err(io::ErrorKind::TimedOut.into());
+                    }
+
+                    self.console
+                        .output_ready
+                        .wait_timeout(state, left)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+                None => self
+                    .console
+                    .output_ready
+                    .wait(state)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+
+        let len = buf.len().min(state.output.len());
+        for (dest, byte) in buf.iter_mut().zip(state.output.drain(..len)) {
+            *dest = byte;
+        }
+
+        Ok(len)
+    }
+}
+
+/// Writes the guest input of the UART, e.g. keystrokes.
+///
+/// Writes never block, input is queued until the guest
+/// reads it.
+#[derive(Debug, Clone)]
+pub struct UartWriter {
+    console: Arc<Console>,
+}
+
+impl Write for UartWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.console.lock();
+        if state.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+
+        state.input.extend(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// # Safety
+///
+/// `dev` must be the chardev created by the
+/// `Instance::attach_uart`
+unsafe fn console<'a>(dev: *mut chardev_t) -> &'a Console {
+    &*((*dev).data as *const Console)
+}
+
+// SAFETY: callbacks below are called by the UART with the
+// chardev created by the `Instance::attach_uart`, buffers
+// are valid for `nbytes` bytes
+
+unsafe extern "C" fn chardev_poll(dev: *mut chardev_t) -> u32 {
+    if console(dev).has_input() {
+        CHARDEV_RX | CHARDEV_TX
+    } else {
+        CHARDEV_TX
+    }
+}
+
+unsafe extern "C" fn chardev_read(
+    dev: *mut chardev_t,
+    buf: *mut c_void,
+    nbytes: usize,
+) -> usize {
+    let buf = slice::from_raw_parts_mut(buf as *mut u8, nbytes);
+    console(dev).take_input(buf)
+}
+
+unsafe extern "C" fn chardev_write(
+    dev: *mut chardev_t,
+    buf: *const c_void,
+    nbytes: usize,
+) -> usize {
+    let buf = slice::from_raw_parts(buf as *const u8, nbytes);
+    console(dev).push_output(buf);
+    nbytes
+}
+
+/// Called periodically by the UART, notifies it about the
+/// pending input
+unsafe extern "C" fn chardev_update(dev: *mut chardev_t) {
+    if let Some(notify) = (*dev).notify {
+        if console(dev).has_input() {
+            notify((*dev).io_dev, CHARDEV_RX);
+        }
+    }
+}
+
+unsafe extern "C" fn chardev_remove(dev: *mut chardev_t) {
+    // SAFETY: chardev and console are leaked by the
+    // `Instance::attach_uart`, RVVM calls remove only once
+    let dev = Box::from_raw(dev);
+    let console = Arc::from_raw(dev.data as *const Console);
+    console.close();
+}
+
+fn cstr(bytes: &'static [u8]) -> &'static CStr {
+    CStr::from_bytes_with_nul(bytes).expect("missing nul-terminator")
+}
+
+impl Instance {
+    /// Attaches RVVM's NS16550A UART at the `addr`, guest
+    /// console is exposed through the returned streams.
+    ///
+    /// Interrupt `irq` is delivered through the machine's
+    /// PLIC, UART works in the polled mode if there is no
+    /// PLIC. FDT node is added under the `fdt_soc_mut`
+    /// unless RVVM has already added one.
+    ///
+    /// - Returns the reader of the guest output and the
+    ///   writer of the guest input
+    /// - Returns `DeviceAttachError` if region is occupied
+    pub fn attach_uart(
+        &mut self,
+        addr: u64,
+        irq: u32,
+    ) -> Result<(UartReader, UartWriter), DeviceAttachError> {
+        let console = Console::new();
+        let streams = console.streams();
+
+        // SAFETY: zeroed chardev is valid, callbacks are `None`
+        let mut chardev: Box<chardev_t> =
+            Box::new(unsafe { mem::zeroed() });
+        chardev.poll = Some(chardev_poll);
+        chardev.read = Some(chardev_read);
+        chardev.write = Some(chardev_write);
+        chardev.update = Some(chardev_update);
+        chardev.remove = Some(chardev_remove);
+        chardev.data = Arc::into_raw(Arc::clone(&console)) as *mut c_void;
+
+        // SAFETY: `self.ptr` is valid, chardev is owned by the
+        // UART from now on and is freed through the
+        // `chardev_remove`, also when attaching fails
+        unsafe {
+            let plic = rvvm_get_plic(self.as_ptr());
+            let irq = if plic.is_null() { 0 } else { irq };
+            ns16550a_init(
+                self.as_ptr(),
+                Box::into_raw(chardev),
+                addr,
+                plic,
+                irq,
+            );
+        }
+
+        // `ns16550a_init` doesn't report the failure, but
+        // RVVM removes the rejected device right away, which
+        // frees its chardev through the `chardev_remove`
+        if console.is_closed() {
+            return Err(DeviceAttachError::RegionIsOccupied);
+        }
+
+        if self
+            .fdt_soc()
+            .find(Region(cstr(b"uart\0"), addr))
+            .is_none()
+        {
+            self.add_uart_node(addr, irq);
+        }
+
+        Ok(streams)
+    }
+
+    pub(super) fn add_uart_node(&mut self, addr: u64, irq: u32) {
+        let has_plic = self.plic().is_some();
+        let plic = self
+            .fdt_soc_mut()
+            .find_mut(AnyRegion(cstr(b"plic\0")))
+            .filter(|_| has_plic)
+            .map(|plic| plic.phandle());
+
+        let mut node = NodeBuf::new_region("uart", addr);
+        node.prop(
+            cstr(b"reg\0"),
+            [
+                (addr >> 32) as u32,
+                addr as u32,
+                (UART_MMIO_SIZE >> 32) as u32,
+                UART_MMIO_SIZE as u32,
+            ],
+        )
+        .prop(cstr(b"compatible\0"), cstr(b"ns16550a\0"))
+        .prop(cstr(b"clock-frequency\0"), 0x0262_5a00u32)
+        .prop(cstr(b"fifo-size\0"), 16u32)
+        .prop(cstr(b"status\0"), cstr(b"okay\0"));
+
+        if let Some(phandle) = plic {
+            node.prop(cstr(b"interrupt-parent\0"), phandle)
+                .prop(cstr(b"interrupts\0"), irq);
+        }
+
+        self.fdt_soc_mut().child(node);
+    }
+}
@@ -0,0 +1,610 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc,
+        Mutex,
+        MutexGuard,
+        PoisonError,
+    },
+};
+
+use crate::{
+    dev::{
+        mmio::{
+            Device,
+            DeviceData,
+            DeviceExt,
+        },
+        plic::Plic,
+        type_::DeviceType,
+        uart::{
+            Console,
+            UartReader,
+            UartWriter,
+            UART_MMIO_SIZE,
+        },
+    },
+    error::DeviceAttachError,
+    instance::Instance,
+    macros::{
+        device,
+        type_handler,
+    },
+    types::{
+        DeviceHandle,
+        UnsafeDevice,
+    },
+};
+
+/// Depth of the receiver FIFO
+const FIFO_SIZE: usize = 16;
+
+// Register offsets. DLL/DLM replace the RBR/THR and IER
+// while the LCR.DLAB is set, IIR is read-only and FCR is
+// write-only
+const REG_DATA: usize = 0;
+const REG_IER: usize = 1;
+const REG_IIR: usize = 2;
+const REG_LCR: usize = 3;
+const REG_MCR: usize = 4;
+const REG_LSR: usize = 5;
+const REG_MSR: usize = 6;
+const REG_SCR: usize = 7;
+
+const IER_RDA: u8 = 1 << 0;
+const IER_THRE: u8 = 1 << 1;
+const IER_RLS: u8 = 1 << 2;
+const IER_MS: u8 = 1 << 3;
+const IER_MASK: u8 = 0x0f;
+
+// Interrupt identifications in the priority order
+const IIR_RLS: u8 = 0x06;
+const IIR_RDA: u8 = 0x04;
+const IIR_TIMEOUT: u8 = 0x0c;
+const IIR_THRE: u8 = 0x02;
+const IIR_MS: u8 = 0x00;
+const IIR_NONE: u8 = 0x01;
+const IIR_FIFO: u8 = 0xc0;
+
+const FCR_ENABLE: u8 = 1 << 0;
+const FCR_CLEAR_RX: u8 = 1 << 1;
+const FCR_TRIGGER: u8 = 0xc0;
+/// Bits of the FCR that are kept, the rest are actions
+const FCR_MASK: u8 = FCR_ENABLE | FCR_TRIGGER;
+
+const LCR_DLAB: u8 = 1 << 7;
+
+const MCR_DTR: u8 = 1 << 0;
+const MCR_RTS: u8 = 1 << 1;
+const MCR_OUT1: u8 = 1 << 2;
+const MCR_OUT2: u8 = 1 << 3;
+const MCR_LOOP: u8 = 1 << 4;
+const MCR_MASK: u8 = 0x1f;
+
+const LSR_DR: u8 = 1 << 0;
+const LSR_OE: u8 = 1 << 1;
+const LSR_THRE: u8 = 1 << 5;
+const LSR_TEMT: u8 = 1 << 6;
+const LSR_FIFO_ERR: u8 = 1 << 7;
+/// OE, PE, FE and BI, cleared by the LSR read
+const LSR_ERRORS: u8 = 0x1e;
+
+const MSR_TERI: u8 = 1 << 2;
+const MSR_CTS: u8 = 1 << 4;
+const MSR_DSR: u8 = 1 << 5;
+const MSR_RI: u8 = 1 << 6;
+const MSR_DCD: u8 = 1 << 7;
+const MSR_DELTAS: u8 = 0x0f;
+
+/// Size of the serialized `Registers` without the FIFO
+const STATE_HEADER: usize = 11;
+
+/// Register model of the 16550, knows nothing about the
+/// machine.
+///
+/// Transmission is instantaneous, so THR is always empty.
+/// Received bytes are pulled from the console only when
+/// there is a room in the FIFO, thus host input never
+/// overruns, only the loopback can.
+#[derive(Debug, Clone)]
+pub(crate) struct Registers {
+    rx: VecDeque<u8>,
+
+    ier: u8,
+    fcr: u8,
+    lcr: u8,
+    mcr: u8,
+    /// Only the error bits, the rest is computed
+    lsr: u8,
+    msr: u8,
+    scr: u8,
+    dll: u8,
+    dlm: u8,
+
+    /// THR empty interrupt is pending
+    thre: bool,
+    /// Character timeout interrupt is pending
+    timeout: bool,
+    /// No receiver activity since the last tick
+    rx_idle: bool,
+
+    /// Interrupt level seen by the PLIC
+    irq: bool,
+    /// New interrupt condition arose since the last
+    /// `irq_edge`
+    armed: bool,
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Self {
+            rx: VecDeque::with_capacity(FIFO_SIZE),
+            ier: 0,
+            fcr: 0,
+            lcr: 0,
+            mcr: 0,
+            lsr: 0,
+            msr: MSR_CTS | MSR_DSR | MSR_DCD,
+            scr: 0,
+            dll: 0,
+            dlm: 0,
+            thre: false,
+            timeout: false,
+            rx_idle: false,
+            irq: false,
+            armed: false,
+        }
+    }
+}
+
+impl Registers {
+    fn dlab(&self) -> bool {
+        self.lcr & LCR_DLAB != 0
+    }
+
+    fn fifo_enabled(&self) -> bool {
+        self.fcr & FCR_ENABLE != 0
+    }
+
+    fn loopback(&self) -> bool {
+        self.mcr & MCR_LOOP != 0
+    }
+
+    fn rx_capacity(&self) -> usize {
+        if self.fifo_enabled() {
+            FIFO_SIZE
+        } else {
+            1
+        }
+    }
+
+    fn rx_trigger(&self) -> usize {
+        if !self.fifo_enabled() {
+            return 1;
+        }
+
+        match self.fcr >> 6 {
+            0 => 1,
+            1 => 4,
+            2 => 8,
+            _ => 14,
+        }
+    }
+
+    /// Identification of the highest priority pending
+    /// interrupt
+    fn pending(&self) -> u8 {
+        if self.ier & IER_RLS != 0 && self.lsr & LSR_ERRORS != 0 {
+            IIR_RLS
+        } else if self.ier & IER_RDA != 0
+            && self.rx.len() >= self.rx_trigger()
+        {
+            IIR_RDA
+        } else if self.ier & IER_RDA != 0 && self.timeout {
+            IIR_TIMEOUT
+        } else if self.ier & IER_THRE != 0 && self.thre {
+            IIR_THRE
+        } else if self.ier & IER_MS != 0 && self.msr & MSR_DELTAS != 0 {
+            IIR_MS
+        } else {
+            IIR_NONE
+        }
+    }
+
+    fn line_status(&self) -> u8 {
+        let mut lsr = self.lsr | LSR_THRE | LSR_TEMT;
+        if !self.rx.is_empty() {
+            lsr |= LSR_DR;
+        }
+        if self.fifo_enabled() && self.lsr & LSR_ERRORS != 0 {
+            lsr |= LSR_FIFO_ERR;
+        }
+
+        lsr
+    }
+
+    /// Modem inputs, wired to the modem outputs in the
+    /// loopback mode
+    fn modem_inputs(&self) -> u8 {
+        if !self.loopback() {
+            return MSR_CTS | MSR_DSR | MSR_DCD;
+        }
+
+        [
+            (MCR_RTS, MSR_CTS),
+            (MCR_DTR, MSR_DSR),
+            (MCR_OUT1, MSR_RI),
+            (MCR_OUT2, MSR_DCD),
+        ]
+        .into_iter()
+        .filter(|(out, _)| self.mcr & out != 0)
+        .fold(0, |msr, (_, input)| msr | input)
+    }
+
+    fn set_modem_inputs(&mut self, inputs: u8) {
+        let old = self.msr & !MSR_DELTAS;
+        // DCTS, DDSR and DDCD are set on any change, TERI only
+        // on the trailing edge of the RI
+        let mut deltas =
+            ((old ^ inputs) & (MSR_CTS | MSR_DSR | MSR_DCD)) >> 4;
+        if old & MSR_RI != 0 && inputs & MSR_RI == 0 {
+            deltas |= MSR_TERI;
+        }
+
+        if deltas != 0 {
+            self.armed = true;
+        }
+        self.msr = inputs | (self.msr & MSR_DELTAS) | deltas;
+    }
+
+    /// Moves the host input to the FIFO while there is a
+    /// room
+    fn receive(&mut self, console: &Console) {
+        if self.loopback() {
+            return;
+        }
+
+        let mut buf = [0; FIFO_SIZE];
+        let room = self.rx_capacity().saturating_sub(self.rx.len());
+        let len = console.take_input(&mut buf[..room]);
+        if len != 0 {
+            self.rx.extend(&buf[..len]);
+            self.rx_idle = false;
+            self.armed = true;
+        }
+    }
+
+    fn transmit(&mut self, byte: u8, console: &Console) {
+        if !self.loopback() {
+            console.push_output(&[byte]);
+        } else if self.rx.len() < self.rx_capacity() {
+            self.rx.push_back(byte);
+            self.rx_idle = false;
+        } else {
+            self.lsr |= LSR_OE;
+        }
+
+        // Transmitter is empty right away
+        self.thre = true;
+        self.armed = true;
+    }
+
+    pub(crate) fn read(&mut self, offset: usize, console: &Console) -> u8 {
+        match offset {
+            REG_DATA if self.dlab() => self.dll,
+            REG_DATA => {
+                let byte = self.rx.pop_front().unwrap_or(0);
+                self.timeout = false;
+                self.rx_idle = false;
+                self.receive(console);
+
+                byte
+            }
+            REG_IER if self.dlab() => self.dlm,
+            REG_IER => self.ier,
+            REG_IIR => {
+                let id = self.pending();
+                if id == IIR_THRE {
+                    self.thre = false;
+                }
+
+                if self.fifo_enabled() {
+                    id | IIR_FIFO
+                } else {
+                    id
+                }
+            }
+            REG_LCR => self.lcr,
+            REG_MCR => self.mcr,
+            REG_LSR => {
+                let lsr = self.line_status();
+                self.lsr &= !LSR_ERRORS;
+                lsr
+            }
+            REG_MSR => {
+                let msr = self.msr;
+                self.msr &= !MSR_DELTAS;
+                msr
+            }
+            REG_SCR => self.scr,
+            _ => 0,
+        }
+    }
+
+    pub(crate) fn write(
+        &mut self,
+        offset: usize,
+        value: u8,
+        console: &Console,
+    ) {
+        match offset {
+            REG_DATA if self.dlab() => self.dll = value,
+            REG_DATA => self.transmit(value, console),
+            REG_IER if self.dlab() => self.dlm = value,
+            REG_IER => {
+                // Enabling the THRE interrupt while THR is empty
+                // raises it immediately
+                if value & IER_THRE != 0 && self.ier & IER_THRE == 0 {
+                    self.thre = true;
+                }
+
+                self.ier = value & IER_MASK;
+                self.armed = true;
+            }
+            REG_IIR => {
+                // Toggling the FIFO mode clears the FIFOs
+                if (value ^ self.fcr) & FCR_ENABLE != 0
+                    || value & FCR_CLEAR_RX != 0
+                {
+                    self.rx.clear();
+                    self.timeout = false;
+                }
+
+                self.fcr = value & FCR_MASK;
+                self.receive(console);
+            }
+            REG_LCR => self.lcr = value,
+            REG_MCR => {
+                self.mcr = value & MCR_MASK;
+                self.set_modem_inputs(self.modem_inputs());
+                self.receive(console);
+            }
+            REG_SCR => self.scr = value,
+            // LSR and MSR writes are for the factory testing
+            _ => {}
+        }
+    }
+
+    /// Periodic update: pulls the host input and detects
+    /// the character timeout, which is reported if the
+    /// receiver is idle for the whole tick
+    pub(crate) fn tick(&mut self, console: &Console) {
+        self.receive(console);
+
+        if self.fifo_enabled() && !self.rx.is_empty() && self.rx_idle {
+            self.timeout = true;
+            self.armed = true;
+        }
+        self.rx_idle = true;
+    }
+
+    /// Whether the interrupt must be sent to the PLIC.
+    /// Since PLIC is edge-triggered, it is sent on
+    /// every new condition while the line is high, and
+    /// on every `redeliver` to emulate the
+    /// level-triggered line.
+    pub(crate) fn irq_edge(&mut self, redeliver: bool) -> bool {
+        let level = self.pending() != IIR_NONE;
+        let edge = level && (!self.irq || self.armed || redeliver);
+
+        self.irq = level;
+        self.armed = false;
+        edge
+    }
+
+    pub(crate) fn save(&self) -> Vec<u8> {
+        let flags = self.thre as u8 | (self.timeout as u8) << 1;
+        let mut state = Vec::with_capacity(STATE_HEADER + self.rx.len());
+        state.extend([
+            self.ier,
+            self.fcr,
+            self.lcr,
+            self.mcr,
+            self.lsr,
+            self.msr,
+            self.scr,
+            self.dll,
+            self.dlm,
+            flags,
+            self.rx.len() as u8,
+        ]);
+        state.extend(&self.rx);
+
+        state
+    }
+
+    /// Restores registers saved by the `Registers::save`,
+    /// returns `None` if the state is malformed
+    pub(crate) fn load(state: &[u8]) -> Option<Self> {
+        if state.len() < STATE_HEADER {
+            return None;
+        }
+
+        let (header, rx) = state.split_at(STATE_HEADER);
+        if rx.len() != header[10] as usize || rx.len() > FIFO_SIZE {
+            return None;
+        }
+        // Such values can't be written by the guest
+        if header[0] & !IER_MASK != 0
+            || header[1] & !FCR_MASK != 0
+            || header[3] & !MCR_MASK != 0
+            || header[4] & !LSR_ERRORS != 0
+        {
+            return None;
+        }
+
+        Some(Self {
+            rx: rx.iter().copied().collect(),
+            ier: header[0],
+            fcr: header[1],
+            lcr: header[2],
+            mcr: header[3],
+            lsr: header[4],
+            msr: header[5],
+            scr: header[6],
+            dll: header[7],
+            dlm: header[8],
+            thre: header[9] & 1 != 0,
+            timeout: header[9] & 2 != 0,
+            ..Self::default()
+        })
+    }
+}
+
+/// Data of the `Uart16550`
+pub struct Uart16550Data {
+    regs: Mutex<Registers>,
+    console: Arc<Console>,
+    irq: Option<(Plic, u32)>,
+}
+
+impl Uart16550Data {
+    fn lock(&self) -> MutexGuard<'_, Registers> {
+        self.regs
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Runs `f` on the registers, then sends the interrupt
+    /// if needed
+    fn with_regs<R>(
+        &self,
+        redeliver: bool,
+        f: impl FnOnce(&mut Registers, &Console) -> R,
+    ) -> R {
+        let mut regs = self.lock();
+        let ret = f(&mut regs, &self.console);
+        let edge = regs.irq_edge(redeliver);
+        drop(regs);
+
+        if let (true, Some((plic, irq))) = (edge, self.irq) {
+            // SAFETY: device data doesn't outlive the machine
+            unsafe { plic.send_irq(irq) };
+        }
+
+        ret
+    }
+}
+
+impl Drop for Uart16550Data {
+    fn drop(&mut self) {
+        self.console.close();
+    }
+}
+
+/// 16550-compatible UART written in Rust.
+///
+/// Implements the whole register set (THR/RBR, IER, IIR,
+/// FCR, LCR, MCR, LSR, MSR, SCR and DLL/DLM), the 16-byte
+/// receiver FIFO with the trigger levels and the loopback
+/// mode. Interrupts are delivered through the machine's
+/// PLIC. Also serves as the reference implementation of
+/// the `Device`, refer to the `Instance::attach_uart16550`.
+#[device]
+pub struct Uart16550(Uart16550Data);
+
+impl Device<Uart16550Data> for Uart16550 {
+    type Error = ();
+
+    fn read(
+        &self,
+        dest: &mut [u8],
+        _size: u8,
+        offset: usize,
+    ) -> Result<(), Self::Error> {
+        dest[0] = self
+            .data()
+            .with_regs(false, |regs, console| regs.read(offset, console));
+        Ok(())
+    }
+
+    fn write(
+        &self,
+        dest: &mut [u8],
+        _size: u8,
+        offset: usize,
+    ) -> Result<(), Self::Error> {
+        self.data().with_regs(false, |regs, console| {
+            regs.write(offset, dest[0], console)
+        });
+        Ok(())
+    }
+
+    fn save_state(&self) -> Option<Vec<u8>> {
+        Some(self.data().lock().save())
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> bool {
+        match Registers::load(state) {
+            Some(regs) => {
+                *self.data().lock() = regs;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[type_handler(ty = "Uart16550Data")]
+fn uart16550_update(dev: &UnsafeDevice<Uart16550Data>) {
+    dev.data()
+        .with_regs(true, |regs, console| regs.tick(console));
+}
+
+#[type_handler(ty = "Uart16550Data")]
+fn uart16550_reset(dev: &UnsafeDevice<Uart16550Data>) {
+    dev.data()
+        .with_regs(false, |regs, _| *regs = Registers::default());
+}
+
+impl Instance {
+    /// Attaches the `Uart16550` at the `addr`, guest
+    /// console is exposed through the returned streams.
+    ///
+    /// Interrupt `irq` is delivered through the machine's
+    /// PLIC, UART works in the polled mode if there is no
+    /// PLIC. FDT node is added under the `fdt_soc_mut`.
+    ///
+    /// - Returns the handle of the device, the reader of
+    ///   the guest output and the writer of the guest input
+    /// - Returns `DeviceAttachError` if region is occupied
+    pub fn attach_uart16550(
+        &mut self,
+        addr: u64,
+        irq: u32,
+    ) -> Result<
+        (DeviceHandle<Uart16550Data>, UartReader, UartWriter),
+        DeviceAttachError,
+    > {
+        let console = Console::new();
+        let (reader, writer) = console.streams();
+        let plic = self.plic();
+        let irq = if plic.is_some() { irq } else { 0 };
+
+        let data = Uart16550Data {
+            regs: Mutex::default(),
+            console,
+            irq: plic.map(|plic| (plic, irq)),
+        };
+        let dev =
+            Uart16550::new(addr, UART_MMIO_SIZE as usize, 1..=1, data);
+        let ty = DeviceType::new("ns16550a")
+            .on_update(uart16550_update)
+            .on_reset(uart16550_reset);
+
+        let handle = self.try_attach_device_with_type(dev, ty)?;
+        self.add_uart_node(addr, irq);
+
+        Ok((handle, reader, writer))
+    }
+}
@@ -0,0 +1,654 @@
+use crate::{
+    elf::SymbolTable,
+    insn::*,
+};
+
+/// ABI names of the integer registers
+pub(crate) const X_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "fp", "s1", "a0",
+    "a1", "a2", "a3", "a4", "a5", "a6", "a7", "s2", "s3", "s4", "s5",
+    "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
+
+/// ABI names of the floating-point registers
+pub(crate) const F_NAMES: [&str; 32] = [
+    "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7", "fs0", "fs1",
+    "fa0", "fa1", "fa2", "fa3", "fa4", "fa5", "fa6", "fa7", "fs2", "fs3",
+    "fs4", "fs5", "fs6", "fs7", "fs8", "fs9", "fs10", "fs11", "ft8",
+    "ft9", "ft10", "ft11",
+];
+
+const CSR_NAMES: [(u16, &str); 39] = [
+    (0x001, "fflags"),
+    (0x002, "frm"),
+    (0x003, "fcsr"),
+    (0x100, "sstatus"),
+    (0x104, "sie"),
+    (0x105, "stvec"),
+    (0x106, "scounteren"),
+    (0x140, "sscratch"),
+    (0x141, "sepc"),
+    (0x142, "scause"),
+    (0x143, "stval"),
+    (0x144, "sip"),
+    (0x180, "satp"),
+    (0x300, "mstatus"),
+    (0x301, "misa"),
+    (0x302, "medeleg"),
+    (0x303, "mideleg"),
+    (0x304, "mie"),
+    (0x305, "mtvec"),
+    (0x306, "mcounteren"),
+    (0x310, "mstatush"),
+    (0x340, "mscratch"),
+    (0x341, "mepc"),
+    (0x342, "mcause"),
+    (0x343, "mtval"),
+    (0x344, "mip"),
+    (0x3a0, "pmpcfg0"),
+    (0x3b0, "pmpaddr0"),
+    (0xb00, "mcycle"),
+    (0xb02, "minstret"),
+    (0xc00, "cycle"),
+    (0xc01, "time"),
+    (0xc02, "instret"),
+    (0xc80, "cycleh"),
+    (0xc81, "timeh"),
+    (0xf11, "mvendorid"),
+    (0xf12, "marchid"),
+    (0xf13, "mimpid"),
+    (0xf14, "mhartid"),
+];
+
+/// RISC-V instruction pretty-printer for the RV32/RV64
+/// IMAFDC with the Zicsr and Zifencei.
+///
+/// Output follows the `objdump` style: ABI register
+/// names, compressed instructions are shown expanded and
+/// jump targets are absolute. Unknown instructions are
+/// shown as the `.2byte`/`.4byte` directives.
+#[derive(Debug, Clone, Copy)]
+pub struct Disassembler<'a> {
+    rv64: bool,
+    symbols: Option<&'a SymbolTable>,
+}
+
+impl<'a> Disassembler<'a> {
+    pub const fn new(rv64: bool) -> Self {
+        Self {
+            rv64,
+            symbols: None,
+        }
+    }
+
+    /// Decodes addresses against the symbol table, e.g. the
+    /// one of the `ElfImage`
+    pub const fn with_symbols(mut self, symbols: &'a SymbolTable) -> Self {
+        self.symbols = Some(symbols);
+        self
+    }
+
+    pub const fn is_rv64(&self) -> bool {
+        self.rv64
+    }
+
+    /// Formats address as `80000010 <_start+0x10>`, symbol
+    /// is omitted if it's unknown
+    pub fn describe(&self, addr: u64) -> String {
+        match self
+            .symbols
+            .and_then(|symbols| symbols.lookup(addr))
+        {
+            Some((symbol, 0)) => format!("{addr:x} <{}>", symbol.name),
+            Some((symbol, offset)) => {
+                format!("{addr:x} <{}+{offset:#x}>", symbol.name)
+            }
+            None => format!("{addr:x}"),
+        }
+    }
+
+    /// Disassembles instruction located at the `pc`, only
+    /// the lowest halfword is used for the compressed ones
+    pub fn disassemble(&self, insn: u32, pc: u64) -> String {
+        let decoded = if insn_len(insn as u16) == 2 {
+            self.compressed(insn & 0xffff, pc)
+        } else {
+            self.uncompressed(insn, pc)
+        };
+
+        match decoded {
+            Some(text) => text,
+            None if insn_len(insn as u16) == 2 => {
+                format!(".2byte {:#06x}", insn & 0xffff)
+            }
+            None => format!(".4byte {insn:#010x}"),
+        }
+    }
+
+    fn target(&self, pc: u64, offset: i64) -> String {
+        let target = pc.wrapping_add(offset as u64);
+        if self.rv64 {
+            self.describe(target)
+        } else {
+            self.describe(target as u32 as u64)
+        }
+    }
+
+    fn uncompressed(&self, insn: u32, pc: u64) -> Option<String> {
+        let (rd, rs1, rs2) = (rd(insn), rs1(insn), rs2(insn));
+        let (xd, xs1, xs2) = (X_NAMES[rd], X_NAMES[rs1], X_NAMES[rs2]);
+        let funct3 = funct3(insn);
+        let funct7 = funct7(insn);
+
+        let text = match opcode(insn) {
+            0x37 => format!("lui {xd},{:#x}", insn >> 12),
+            0x17 => format!("auipc {xd},{:#x}", insn >> 12),
+            0x6f => match rd {
+                0 => format!("j {}", self.target(pc, imm_j(insn))),
+                _ => format!("jal {xd},{}", self.target(pc, imm_j(insn))),
+            },
+            0x67 if funct3 == 0 => match (rd, rs1, imm_i(insn)) {
+                (0, 1, 0) => "ret".to_owned(),
+                (0, _, 0) => format!("jr {xs1}"),
+                (_, _, imm) => format!("jalr {xd},{imm}({xs1})"),
+            },
+            0x63 => {
+                let name = match funct3 {
+                    0 => "beq",
+                    1 => "bne",
+                    4 => "blt",
+                    5 => "bge",
+                    6 => "bltu",
+                    7 => "bgeu",
+                    _ => return None,
+                };
+                format!(
+                    "{name} {xs1},{xs2},{}",
+                    self.target(pc, imm_b(insn))
+                )
+            }
+            0x03 => {
+                let name = match (funct3, self.rv64) {
+                    (0, _) => "lb",
+                    (1, _) => "lh",
+                    (2, _) => "lw",
+                    (3, true) => "ld",
+                    (4, _) => "lbu",
+                    (5, _) => "lhu",
+                    (6, true) => "lwu",
+                    _ => return None,
+                };
+                format!("{name} {xd},{}({xs1})", imm_i(insn))
+            }
+            0x23 => {
+                let name = match (funct3, self.rv64) {
+                    (0, _) => "sb",
+                    (1, _) => "sh",
+                    (2, _) => "sw",
+                    (3, true) => "sd",
+                    _ => return None,
+                };
+                format!("{name} {xs2},{}({xs1})", imm_s(insn))
+            }
+            0x13 => self.op_imm(insn, false)?,
+            0x1b if self.rv64 => self.op_imm(insn, true)?,
+            0x33 => self.op(insn, false)?,
+            0x3b if self.rv64 => self.op(insn, true)?,
+            0x0f => match (funct3, insn) {
+                (0, 0x8330_000f) => "fence.tso".to_owned(),
+                (0, 0x0100_000f) => "pause".to_owned(),
+                (0, _) => format!(
+                    "fence {},{}",
+                    fence_set(bits(insn, 27, 24)),
+                    fence_set(bits(insn, 23, 20))
+                ),
+                (1, _) => "fence.i".to_owned(),
+                _ => return None,
+            },
+            0x73 => self.system(insn)?,
+            0x2f => self.atomic(insn)?,
+            0x07 | 0x27 => {
+                let name = match (opcode(insn), funct3) {
+                    (0x07, 2) => "flw",
+                    (0x07, 3) => "fld",
+                    (0x27, 2) => "fsw",
+                    (0x27, 3) => "fsd",
+                    _ => return None,
+                };
+                if opcode(insn) == 0x07 {
+                    format!(
+                        "{name} {},{}({xs1})",
+                        F_NAMES[rd],
+                        imm_i(insn)
+                    )
+                } else {
+                    format!(
+                        "{name} {},{}({xs1})",
+                        F_NAMES[rs2],
+                        imm_s(insn)
+                    )
+                }
+            }
+            0x43 | 0x47 | 0x4b | 0x4f => {
+                let name = match opcode(insn) {
+                    0x43 => "fmadd",
+                    0x47 => "fmsub",
+                    0x4b => "fnmsub",
+                    _ => "fnmadd",
+                };
+                format!(
+                    "{name}.{} {},{},{},{}",
+                    fp_fmt(funct7 & 0b11)?,
+                    F_NAMES[rd],
+                    F_NAMES[rs1],
+                    F_NAMES[rs2],
+                    F_NAMES[rs3(insn)]
+                )
+            }
+            0x53 => self.op_fp(insn)?,
+            _ => return None,
+        };
+
+        Some(text)
+    }
+
+    fn op_imm(&self, insn: u32, word: bool) -> Option<String> {
+        let (xd, xs1) = (X_NAMES[rd(insn)], X_NAMES[rs1(insn)]);
+        let imm = imm_i(insn);
+        let w = if word { "w" } else { "" };
+
+        // Shift amount is 6 bits wide on the RV64
+        let shamt_bits = if self.rv64 && !word { 6 } else { 5 };
+        let shamt = bits(insn, 19 + shamt_bits, 20);
+        let shift_kind = insn >> (20 + shamt_bits);
+
+        let text = match (funct3(insn), word) {
+            (0, _)
+                if rd(insn) == 0
+                    && rs1(insn) == 0
+                    && imm == 0
+                    && !word =>
+            {
+                "nop".to_owned()
+            }
+            (0, false) if rs1(insn) == 0 => format!("li {xd},{imm}"),
+            (0, _) if imm == 0 && word => format!("sext.w {xd},{xs1}"),
+            (0, _) if imm == 0 => format!("mv {xd},{xs1}"),
+            (0, _) => format!("addi{w} {xd},{xs1},{imm}"),
+            (2, false) => format!("slti {xd},{xs1},{imm}"),
+            (3, false) => format!("sltiu {xd},{xs1},{imm}"),
+            (4, false) => format!("xori {xd},{xs1},{imm}"),
+            (6, false) => format!("ori {xd},{xs1},{imm}"),
+            (7, false) => format!("andi {xd},{xs1},{imm}"),
+            (1, _) if shift_kind == 0 => {
+                format!("slli{w} {xd},{xs1},{shamt:#x}")
+            }
+            (5, _) if shift_kind == 0 => {
+                format!("srli{w} {xd},{xs1},{shamt:#x}")
+            }
+            (5, _) if shift_kind << shamt_bits == 0x400 => {
+                format!("srai{w} {xd},{xs1},{shamt:#x}")
+            }
+            _ => return None,
+        };
+
+        Some(text)
+    }
+
+    fn op(&self, insn: u32, word: bool) -> Option<String> {
+        let (xd, xs1, xs2) =
+            (X_NAMES[rd(insn)], X_NAMES[rs1(insn)], X_NAMES[rs2(insn)]);
+
+        let name = match (funct7(insn), funct3(insn), word) {
+            (0x00, 0, _) => "add",
+            (0x20, 0, _) => "sub",
+            (0x00, 1, _) => "sll",
+            (0x00, 2, false) => "slt",
+            (0x00, 3, false) => "sltu",
+            (0x00, 4, false) => "xor",
+            (0x00, 5, _) => "srl",
+            (0x20, 5, _) => "sra",
+            (0x00, 6, false) => "or",
+            (0x00, 7, false) => "and",
+            (0x01, 0, _) => "mul",
+            (0x01, 1, false) => "mulh",
+            (0x01, 2, false) => "mulhsu",
+            (0x01, 3, false) => "mulhu",
+            (0x01, 4, _) => "div",
+            (0x01, 5, _) => "divu",
+            (0x01, 6, _) => "rem",
+            (0x01, 7, _) => "remu",
+            _ => return None,
+        };
+
+        let w = if word { "w" } else { "" };
+        Some(format!("{name}{w} {xd},{xs1},{xs2}"))
+    }
+
+    fn system(&self, insn: u32) -> Option<String> {
+        let (xd, xs1) = (X_NAMES[rd(insn)], X_NAMES[rs1(insn)]);
+        let csr = csr_name((insn >> 20) as u16);
+
+        let text = match funct3(insn) {
+            0 => match insn {
+                0x0000_0073 => "ecall".to_owned(),
+                0x0010_0073 => "ebreak".to_owned(),
+                0x1020_0073 => "sret".to_owned(),
+                0x3020_0073 => "mret".to_owned(),
+                0x1050_0073 => "wfi".to_owned(),
+                _ if funct7(insn) == 0x09 && rd(insn) == 0 => {
+                    match (rs1(insn), rs2(insn)) {
+                        (0, 0) => "sfence.vma".to_owned(),
+                        (_, 0) => format!("sfence.vma {xs1}"),
+                        (_, rs2) => {
+                            format!("sfence.vma {xs1},{}", X_NAMES[rs2])
+                        }
+                    }
+                }
+                _ => return None,
+            },
+            funct3 @ 1..=3 => {
+                let name =
+                    ["csrrw", "csrrs", "csrrc"][funct3 as usize - 1];
+                match (funct3, rd(insn), rs1(insn)) {
+                    (2, _, 0) => format!("csrr {xd},{csr}"),
+                    (_, 0, _) => {
+                        let name =
+                            ["csrw", "csrs", "csrc"][funct3 as usize - 1];
+                        format!("{name} {csr},{xs1}")
+                    }
+                    _ => format!("{name} {xd},{csr},{xs1}"),
+                }
+            }
+            funct3 @ 5..=7 => {
+                let name =
+                    ["csrrwi", "csrrsi", "csrrci"][funct3 as usize - 5];
+                format!("{name} {xd},{csr},{}", rs1(insn))
+            }
+            _ => return None,
+        };
+
+        Some(text)
+    }
+
+    fn atomic(&self, insn: u32) -> Option<String> {
+        let (xd, xs1, xs2) =
+            (X_NAMES[rd(insn)], X_NAMES[rs1(insn)], X_NAMES[rs2(insn)]);
+        let width = match (funct3(insn), self.rv64) {
+            (2, _) => "w",
+            (3, true) => "d",
+            _ => return None,
+        };
+        let order = match bits(insn, 26, 25) {
+            0b00 => "",
+            0b01 => ".rl",
+            0b10 => ".aq",
+            _ => ".aqrl",
+        };
+
+        let name = match bits(insn, 31, 27) {
+            0x02 if rs2(insn) == 0 => {
+                return Some(format!("lr.{width}{order} {xd},({xs1})"));
+            }
+            0x03 => "sc",
+            0x01 => "amoswap",
+            0x00 => "amoadd",
+            0x04 => "amoxor",
+            0x0c => "amoand",
+            0x08 => "amoor",
+            0x10 => "amomin",
+            0x14 => "amomax",
+            0x18 => "amominu",
+            0x1c => "amomaxu",
+            _ => return None,
+        };
+
+        Some(format!("{name}.{width}{order} {xd},{xs2},({xs1})"))
+    }
+
+    fn op_fp(&self, insn: u32) -> Option<String> {
+        let (rd, rs1, rs2) = (rd(insn), rs1(insn), rs2(insn));
+        let (fd, fs1, fs2) = (F_NAMES[rd], F_NAMES[rs1], F_NAMES[rs2]);
+        let (xd, xs1) = (X_NAMES[rd], X_NAMES[rs1]);
+        let funct3 = funct3(insn);
+        let fmt = fp_fmt(funct7(insn) & 0b11)?;
+
+        // Integer conversions, `l`/`lu` are RV64 only
+        let int = |kind: usize| match (kind, self.rv64) {
+            (0, _) => Some("w"),
+            (1, _) => Some("wu"),
+            (2, true) => Some("l"),
+            (3, true) => Some("lu"),
+            _ => None,
+        };
+
+        let text = match funct7(insn) >> 2 {
+            0x00 => format!("fadd.{fmt} {fd},{fs1},{fs2}"),
+            0x01 => format!("fsub.{fmt} {fd},{fs1},{fs2}"),
+            0x02 => format!("fmul.{fmt} {fd},{fs1},{fs2}"),
+            0x03 => format!("fdiv.{fmt} {fd},{fs1},{fs2}"),
+            0x0b if rs2 == 0 => format!("fsqrt.{fmt} {fd},{fs1}"),
+            0x04 => match funct3 {
+                0 if rs1 == rs2 => format!("fmv.{fmt} {fd},{fs1}"),
+                1 if rs1 == rs2 => format!("fneg.{fmt} {fd},{fs1}"),
+                2 if rs1 == rs2 => format!("fabs.{fmt} {fd},{fs1}"),
+                0 => format!("fsgnj.{fmt} {fd},{fs1},{fs2}"),
+                1 => format!("fsgnjn.{fmt} {fd},{fs1},{fs2}"),
+                2 => format!("fsgnjx.{fmt} {fd},{fs1},{fs2}"),
+                _ => return None,
+            },
+            0x05 => match funct3 {
+                0 => format!("fmin.{fmt} {fd},{fs1},{fs2}"),
+                1 => format!("fmax.{fmt} {fd},{fs1},{fs2}"),
+                _ => return None,
+            },
+            0x08 => {
+                let from = fp_fmt(rs2 as u32)?;
+                if from == fmt {
+                    return None;
+                }
+                format!("fcvt.{fmt}.{from} {fd},{fs1}")
+            }
+            0x14 => match funct3 {
+                0 => format!("fle.{fmt} {xd},{fs1},{fs2}"),
+                1 => format!("flt.{fmt} {xd},{fs1},{fs2}"),
+                2 => format!("feq.{fmt} {xd},{fs1},{fs2}"),
+                _ => return None,
+            },
+            0x18 => format!("fcvt.{}.{fmt} {xd},{fs1}", int(rs2)?),
+            0x1a => format!("fcvt.{fmt}.{} {fd},{xs1}", int(rs2)?),
+            0x1c if rs2 == 0 => match (funct3, fmt) {
+                (0, "s") => format!("fmv.x.w {xd},{fs1}"),
+                (0, _) if self.rv64 => format!("fmv.x.d {xd},{fs1}"),
+                (1, _) => format!("fclass.{fmt} {xd},{fs1}"),
+                _ => return None,
+            },
+            0x1e if rs2 == 0 && funct3 == 0 => match fmt {
+                "s" => format!("fmv.w.x {fd},{xs1}"),
+                _ if self.rv64 => format!("fmv.d.x {fd},{xs1}"),
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        Some(text)
+    }
+
+    fn compressed(&self, insn: u32, pc: u64) -> Option<String> {
+        if insn == 0 {
+            return None;
+        }
+
+        // Full and 3-bit register fields
+        let rd = bits(insn, 11, 7) as usize;
+        let rs2 = bits(insn, 6, 2) as usize;
+        let rd_ = 8 + bits(insn, 4, 2) as usize;
+        let rs1_ = 8 + bits(insn, 9, 7) as usize;
+        let (xd, xs2) = (X_NAMES[rd], X_NAMES[rs2]);
+        let (xd_, xs1_) = (X_NAMES[rd_], X_NAMES[rs1_]);
+
+        let imm6 = sext(bits(insn, 12, 12) << 5 | bits(insn, 6, 2), 6);
+        let shamt = bits(insn, 12, 12) << 5 | bits(insn, 6, 2);
+        // Offsets of the word and double-word loads/stores
+        let off_w = bits(insn, 5, 5) << 6
+            | bits(insn, 12, 10) << 3
+            | bits(insn, 6, 6) << 2;
+        let off_d = bits(insn, 6, 5) << 6 | bits(insn, 12, 10) << 3;
+        let lwsp = bits(insn, 3, 2) << 6
+            | bits(insn, 12, 12) << 5
+            | bits(insn, 6, 4) << 2;
+        let ldsp = bits(insn, 4, 2) << 6
+            | bits(insn, 12, 12) << 5
+            | bits(insn, 6, 5) << 3;
+        let swsp = bits(insn, 8, 7) << 6 | bits(insn, 12, 9) << 2;
+        let sdsp = bits(insn, 9, 7) << 6 | bits(insn, 12, 10) << 3;
+
+        let text = match (insn & 0b11, bits(insn, 15, 13)) {
+            (0b00, 0b000) => {
+                let imm = bits(insn, 10, 7) << 6
+                    | bits(insn, 12, 11) << 4
+                    | bits(insn, 5, 5) << 3
+                    | bits(insn, 6, 6) << 2;
+                if imm == 0 {
+                    return None;
+                }
+                format!("addi {xd_},sp,{imm}")
+            }
+            (0b00, 0b001) => {
+                format!("fld {},{off_d}({xs1_})", F_NAMES[rd_])
+            }
+            (0b00, 0b010) => format!("lw {xd_},{off_w}({xs1_})"),
+            (0b00, 0b011) if self.rv64 => {
+                format!("ld {xd_},{off_d}({xs1_})")
+            }
+            (0b00, 0b011) => {
+                format!("flw {},{off_w}({xs1_})", F_NAMES[rd_])
+            }
+            (0b00, 0b101) => {
+                format!("fsd {},{off_d}({xs1_})", F_NAMES[rd_])
+            }
+            (0b00, 0b110) => format!("sw {xd_},{off_w}({xs1_})"),
+            (0b00, 0b111) if self.rv64 => {
+                format!("sd {xd_},{off_d}({xs1_})")
+            }
+            (0b00, 0b111) => {
+                format!("fsw {},{off_w}({xs1_})", F_NAMES[rd_])
+            }
+
+            (0b01, 0b000) if rd == 0 => "nop".to_owned(),
+            (0b01, 0b000) => format!("addi {xd},{xd},{imm6}"),
+            (0b01, 0b001) if self.rv64 && rd != 0 => {
+                format!("addiw {xd},{xd},{imm6}")
+            }
+            (0b01, 0b001) if !self.rv64 => {
+                format!("jal {}", self.target(pc, c_imm_j(insn)))
+            }
+            (0b01, 0b010) => format!("li {xd},{imm6}"),
+            (0b01, 0b011) if rd == 2 => {
+                let imm = sext(
+                    bits(insn, 12, 12) << 9
+                        | bits(insn, 4, 3) << 7
+                        | bits(insn, 5, 5) << 6
+                        | bits(insn, 2, 2) << 5
+                        | bits(insn, 6, 6) << 4,
+                    10,
+                );
+                if imm == 0 {
+                    return None;
+                }
+                format!("addi sp,sp,{imm}")
+            }
+            (0b01, 0b011) if rd != 0 && imm6 != 0 => {
+                format!("lui {xd},{:#x}", imm6 as u32 & 0xfffff)
+            }
+            (0b01, 0b100) => {
+                match (bits(insn, 11, 10), bits(insn, 12, 12)) {
+                    (0b00, _) => format!("srli {xs1_},{xs1_},{shamt:#x}"),
+                    (0b01, _) => format!("srai {xs1_},{xs1_},{shamt:#x}"),
+                    (0b10, _) => format!("andi {xs1_},{xs1_},{imm6}"),
+                    (_, 0) => {
+                        let name = ["sub", "xor", "or", "and"]
+                            [bits(insn, 6, 5) as usize];
+                        format!("{name} {xs1_},{xs1_},{xd_}")
+                    }
+                    (..) if self.rv64 => {
+                        let name = match bits(insn, 6, 5) {
+                            0b00 => "subw",
+                            0b01 => "addw",
+                            _ => return None,
+                        };
+                        format!("{name} {xs1_},{xs1_},{xd_}")
+                    }
+                    _ => return None,
+                }
+            }
+            (0b01, 0b101) => {
+                format!("j {}", self.target(pc, c_imm_j(insn)))
+            }
+            (0b01, 0b110) => {
+                format!("beqz {xs1_},{}", self.target(pc, c_imm_b(insn)))
+            }
+            (0b01, 0b111) => {
+                format!("bnez {xs1_},{}", self.target(pc, c_imm_b(insn)))
+            }
+
+            (0b10, 0b000) => format!("slli {xd},{xd},{shamt:#x}"),
+            (0b10, 0b001) => format!("fld {},{ldsp}(sp)", F_NAMES[rd]),
+            (0b10, 0b010) if rd != 0 => format!("lw {xd},{lwsp}(sp)"),
+            (0b10, 0b011) if self.rv64 && rd != 0 => {
+                format!("ld {xd},{ldsp}(sp)")
+            }
+            (0b10, 0b011) if !self.rv64 => {
+                format!("flw {},{lwsp}(sp)", F_NAMES[rd])
+            }
+            (0b10, 0b100) => match (bits(insn, 12, 12), rd, rs2) {
+                (0, 1, 0) => "ret".to_owned(),
+                (0, 1.., 0) => format!("jr {xd}"),
+                (0, ..) => format!("mv {xd},{xs2}"),
+                (_, 0, 0) => "ebreak".to_owned(),
+                (_, _, 0) => format!("jalr {xd}"),
+                _ => format!("add {xd},{xd},{xs2}"),
+            },
+            (0b10, 0b101) => format!("fsd {},{sdsp}(sp)", F_NAMES[rs2]),
+            (0b10, 0b110) => format!("sw {xs2},{swsp}(sp)"),
+            (0b10, 0b111) if self.rv64 => format!("sd {xs2},{sdsp}(sp)"),
+            (0b10, 0b111) => format!("fsw {},{swsp}(sp)", F_NAMES[rs2]),
+            _ => return None,
+        };
+
+        Some(text)
+    }
+}
+
+const fn fp_fmt(fmt: u32) -> Option<&'static str> {
+    match fmt {
+        0 => Some("s"),
+        1 => Some("d"),
+        _ => None,
+    }
+}
+
+fn fence_set(set: u32) -> String {
+    let set: String = "iorw"
+        .chars()
+        .enumerate()
+        .filter(|&(bit, _)| set & (0b1000 >> bit) != 0)
+        .map(|(_, name)| name)
+        .collect();
+
+    if set.is_empty() {
+        "0".to_owned()
+    } else {
+        set
+    }
+}
+
+fn csr_name(csr: u16) -> String {
+    CSR_NAMES
+        .iter()
+        .find(|&&(id, _)| id == csr)
+        .map_or_else(
+            || format!("{csr:#x}"),
+            |(_, name)| (*name).to_owned(),
+        )
+}
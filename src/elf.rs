@@ -0,0 +1,472 @@
+use std::{
+    io::{
+        self,
+        Read,
+    },
+    ops::Range,
+    path::Path,
+};
+
+use crate::{
+    error::{
+        ElfError,
+        LoadError,
+    },
+    instance::Instance,
+};
+
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHN_UNDEF: u16 = 0;
+
+const OVERFLOW: ElfError = ElfError::Malformed("offset overflows usize");
+
+/// Kind of the symbol, taken from the `STT_*` type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SymbolKind {
+    Function,
+    Object,
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Symbol {
+    pub name: String,
+    pub addr: u64,
+    /// Size of the symbol in bytes, zero if unknown
+    pub size: u64,
+    pub kind: SymbolKind,
+}
+
+impl Symbol {
+    /// Check whether `addr` belongs to the symbol. Symbols
+    /// of unknown size contain only their own address.
+    pub const fn contains(&self, addr: u64) -> bool {
+        addr >= self.addr
+            && (addr - self.addr < self.size || addr == self.addr)
+    }
+}
+
+/// Defined symbols of the ELF image sorted by address
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    /// Parses `.symtab` of the ELF image without loading it
+    pub fn from_elf(data: &[u8]) -> Result<Self, ElfError> {
+        Elf::parse(data)?.symbols()
+    }
+
+    /// Find symbol containing the `addr`, returns the
+    /// symbol and offset of the `addr` from its start.
+    /// Symbols of unknown size match any address up to the
+    /// next symbol.
+    pub fn lookup(&self, addr: u64) -> Option<(&Symbol, u64)> {
+        let idx = self
+            .symbols
+            .partition_point(|sym| sym.addr <= addr);
+        let symbol = self.symbols[..idx].last()?;
+
+        if symbol.size == 0 || symbol.contains(addr) {
+            Some((symbol, addr - symbol.addr))
+        } else {
+            None
+        }
+    }
+
+    /// Find symbol by its name
+    pub fn get(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|sym| sym.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
+
+/// Loaded ELF image information
+#[derive(Debug, Clone)]
+pub struct ElfImage {
+    /// Physical address of the entry point, translated
+    /// through the segment that contains it
+    pub entry: u64,
+    /// Physical address ranges occupied by the segments
+    pub segments: Vec<Range<u64>>,
+    /// Symbols at their link-time virtual addresses
+    pub symbols: SymbolTable,
+}
+
+struct Segment {
+    vaddr: u64,
+    paddr: u64,
+    file: Range<usize>,
+    mem_size: u64,
+}
+
+struct Elf<'a> {
+    data: &'a [u8],
+    is_64: bool,
+}
+
+impl<'a> Elf<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        if data.len() < 16 || &data[..4] != b"\x7fELF" {
+            return Err(ElfError::NotElf);
+        }
+
+        let is_64 = match data[4] {
+            1 => false,
+            2 => true,
+            _ => return Err(ElfError::Malformed("unknown ELF class")),
+        };
+        if data[5] != 1 {
+            return Err(ElfError::BigEndian);
+        }
+
+        let elf = Self { data, is_64 };
+        let machine = elf.u16(18)?;
+        if machine != EM_RISCV {
+            return Err(ElfError::NotRiscV { machine });
+        }
+
+        Ok(elf)
+    }
+
+    const fn bits(&self) -> u8 {
+        if self.is_64 {
+            64
+        } else {
+            32
+        }
+    }
+
+    fn bytes(&self, range: Range<usize>) -> Result<&'a [u8], ElfError> {
+        self.data
+            .get(range)
+            .ok_or(ElfError::Malformed("truncated image"))
+    }
+
+    /// Returns `len` bytes starting at the `offset`
+    fn slice(
+        &self,
+        offset: usize,
+        len: usize,
+    ) -> Result<&'a [u8], ElfError> {
+        self.bytes(offset..span(offset, len)?)
+    }
+
+    fn u8(&self, offset: usize) -> Result<u8, ElfError> {
+        Ok(self.slice(offset, 1)?[0])
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, ElfError> {
+        let bytes = self.slice(offset, 2)?;
+        Ok(u16::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, ElfError> {
+        let bytes = self.slice(offset, 4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u64(&self, offset: usize) -> Result<u64, ElfError> {
+        let bytes = self.slice(offset, 8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// Reads class-sized word, `off64` is used for the
+    /// ELF64 and `off32` for the ELF32
+    fn word(&self, off64: usize, off32: usize) -> Result<u64, ElfError> {
+        if self.is_64 {
+            self.u64(off64)
+        } else {
+            self.u32(off32).map(u64::from)
+        }
+    }
+
+    fn usize(
+        &self,
+        off64: usize,
+        off32: usize,
+    ) -> Result<usize, ElfError> {
+        self.word(off64, off32)?
+            .try_into()
+            .map_err(|_| OVERFLOW)
+    }
+
+    fn entry(&self) -> Result<u64, ElfError> {
+        self.word(24, 24)
+    }
+
+    /// Returns offsets of the program or section header
+    /// table entries. The whole table is checked to fit
+    /// into the image, so the entry fields are in bounds.
+    fn table(
+        &self,
+        section: bool,
+    ) -> Result<impl Iterator<Item = usize>, ElfError> {
+        let (offset, entsize, num) = match (self.is_64, section) {
+            (true, false) => (self.u64(32)?, self.u16(54)?, self.u16(56)?),
+            (true, true) => (self.u64(40)?, self.u16(58)?, self.u16(60)?),
+            (false, false) => {
+                (self.u32(28)?.into(), self.u16(42)?, self.u16(44)?)
+            }
+            (false, true) => {
+                (self.u32(32)?.into(), self.u16(46)?, self.u16(48)?)
+            }
+        };
+        let min_entsize = match (self.is_64, section) {
+            (true, false) => 56,
+            (true, true) => 64,
+            (false, false) => 32,
+            (false, true) => 40,
+        };
+        if num != 0 && entsize < min_entsize {
+            return Err(ElfError::Malformed(
+                "header entry size too small",
+            ));
+        }
+
+        let offset = usize::try_from(offset).map_err(|_| OVERFLOW)?;
+        let entsize = entsize as usize;
+        let size = entsize
+            .checked_mul(num as usize)
+            .ok_or(OVERFLOW)?;
+        self.slice(offset, size)?;
+
+        Ok((0..num as usize).map(move |i| offset + i * entsize))
+    }
+
+    fn segments(&self) -> Result<Vec<Segment>, ElfError> {
+        let mut segments = Vec::new();
+        for ph in self.table(false)? {
+            if self.u32(ph)? != PT_LOAD {
+                continue;
+            }
+
+            let offset = self.usize(ph + 8, ph + 4)?;
+            let file_size = self.usize(ph + 32, ph + 16)?;
+            let mem_size = self.word(ph + 40, ph + 20)?;
+            if (file_size as u64) > mem_size {
+                return Err(ElfError::Malformed(
+                    "segment file size exceeds its memory size",
+                ));
+            }
+
+            let file = offset..span(offset, file_size)?;
+            self.bytes(file.clone())?;
+            segments.push(Segment {
+                vaddr: self.word(ph + 16, ph + 8)?,
+                paddr: self.word(ph + 24, ph + 12)?,
+                file,
+                mem_size,
+            });
+        }
+
+        Ok(segments)
+    }
+
+    /// Returns type, file range and link of the section
+    fn section(
+        &self,
+        index: usize,
+    ) -> Result<(u32, Range<usize>, u32), ElfError> {
+        let sh = self
+            .table(true)?
+            .nth(index)
+            .ok_or(ElfError::Malformed("section index out of range"))?;
+        let offset = self.usize(sh + 24, sh + 16)?;
+        let size = self.usize(sh + 32, sh + 20)?;
+
+        Ok((
+            self.u32(sh + 4)?,
+            offset..span(offset, size)?,
+            self.u32(if self.is_64 { sh + 40 } else { sh + 24 })?,
+        ))
+    }
+
+    fn symbols(&self) -> Result<SymbolTable, ElfError> {
+        let mut symbols = Vec::new();
+
+        for (index, _) in self.table(true)?.enumerate() {
+            let (kind, range, link) = self.section(index)?;
+            if kind != SHT_SYMTAB {
+                continue;
+            }
+
+            let (_, strtab, _) = self.section(link as usize)?;
+            let strtab = self.bytes(strtab)?;
+            let entsize = if self.is_64 { 24 } else { 16 };
+            self.bytes(range.clone())?;
+
+            for sym in range.clone().step_by(entsize) {
+                if range.end - sym < entsize {
+                    break;
+                }
+
+                let info =
+                    self.u8(sym + if self.is_64 { 4 } else { 12 })?;
+                let shndx =
+                    self.u16(sym + if self.is_64 { 6 } else { 14 })?;
+                let kind = match info & 0xf {
+                    0 => SymbolKind::Other,
+                    1 => SymbolKind::Object,
+                    2 => SymbolKind::Function,
+                    _ => continue,
+                };
+                if shndx == SHN_UNDEF {
+                    continue;
+                }
+
+                let name = self.u32(sym)? as usize;
+                let name = strtab
+                    .get(name..)
+                    .and_then(|s| s.split(|&b| b == 0).next())
+                    .ok_or(ElfError::Malformed(
+                        "symbol name out of range",
+                    ))?;
+                if name.is_empty() {
+                    continue;
+                }
+
+                symbols.push(Symbol {
+                    name: String::from_utf8_lossy(name).into_owned(),
+                    addr: self.word(sym + 8, sym + 4)?,
+                    size: self.word(sym + 16, sym + 8)?,
+                    kind,
+                });
+            }
+        }
+
+        symbols.sort_by_key(|sym| (sym.addr, sym.size));
+        Ok(SymbolTable { symbols })
+    }
+}
+
+impl Instance {
+    /// Load ELF file into machine's RAM. Same as the
+    /// `Instance::load_elf_bytes`.
+    ///
+    /// - Returns `Ok` if load was successful
+    /// - Returns `ElfError` otherwise
+    pub fn try_load_elf(
+        &mut self,
+        path: impl AsRef<Path>,
+    ) -> Result<ElfImage, ElfError> {
+        let (mut file, size) = Self::open_image(path.as_ref())?;
+
+        // Sections besides the loaded segments don't have to
+        // fit into RAM, but the whole file has to fit into the
+        // host memory
+        let mut data = Vec::new();
+        data.try_reserve_exact(size).map_err(|e| {
+            LoadError::Io(io::Error::new(io::ErrorKind::OutOfMemory, e))
+        })?;
+        file.read_to_end(&mut data)
+            .map_err(LoadError::from)?;
+
+        self.load_elf_bytes(&data)
+    }
+
+    /// Load RISC-V ELF image from memory, `PT_LOAD`
+    /// segments are placed at their physical addresses.
+    /// Images linked below the `Instance::mem_base` (e.g.
+    /// at zero) are placed relative to the `mem_base`
+    /// instead. Entry point is translated to the physical
+    /// address, symbols are left virtual.
+    ///
+    /// - Returns `ElfImage` with the entry point and
+    ///   symbols if load was successful
+    /// - Returns `ElfError::ClassMismatch` if ELF class
+    ///   doesn't match the `Instance::is_rv64`
+    /// - Returns `ElfError` otherwise
+    pub fn load_elf_bytes(
+        &mut self,
+        data: &[u8],
+    ) -> Result<ElfImage, ElfError> {
+        let elf = Elf::parse(data)?;
+        if elf.is_64 != self.is_rv64() {
+            return Err(ElfError::ClassMismatch {
+                elf_bits: elf.bits(),
+                machine_bits: if self.is_rv64() { 64 } else { 32 },
+            });
+        }
+        self.check_not_running()?;
+
+        let segments = elf.segments()?;
+        let symbols = elf.symbols()?;
+        let base = self.mem_base();
+        let bias = match segments.iter().map(|seg| seg.paddr).min() {
+            Some(lowest) if lowest < base => base,
+            _ => 0,
+        };
+
+        let ram = base..base + self.mem_size() as u64;
+        let mut ranges = Vec::with_capacity(segments.len());
+        for seg in &segments {
+            let start = seg.paddr.wrapping_add(bias);
+            let range = start..start.saturating_add(seg.mem_size);
+            if range.start < ram.start || range.end > ram.end {
+                return Err(ElfError::SegmentOutOfRam {
+                    addr: start,
+                    size: seg.mem_size,
+                });
+            }
+
+            ranges.push(range);
+        }
+
+        for (seg, range) in segments.iter().zip(&ranges) {
+            let file = &data[seg.file.clone()];
+            self.write_ram(range.start, file)?;
+            self.zero_ram(range.start + file.len() as u64, range.end)?;
+        }
+
+        // Entry point is virtual, while harts start with the
+        // translation disabled
+        let entry = elf.entry()?;
+        let entry = segments
+            .iter()
+            .find(|seg| entry.wrapping_sub(seg.vaddr) < seg.mem_size)
+            .map_or(entry, |seg| entry - seg.vaddr + seg.paddr)
+            .wrapping_add(bias);
+
+        Ok(ElfImage {
+            entry,
+            segments: ranges,
+            symbols,
+        })
+    }
+
+    fn zero_ram(
+        &mut self,
+        mut from: u64,
+        to: u64,
+    ) -> Result<(), ElfError> {
+        const ZEROES: [u8; 4096] = [0; 4096];
+
+        while from < to {
+            let len = (to - from).min(ZEROES.len() as u64);
+            self.write_ram(from, &ZEROES[..len as usize])?;
+            from += len;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns end of the `len` bytes long span at the `offset`
+fn span(offset: usize, len: usize) -> Result<usize, ElfError> {
+    offset.checked_add(len).ok_or(OVERFLOW)
+}
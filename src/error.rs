@@ -1,11 +1,31 @@
+use std::{
+    io,
+    path::PathBuf,
+    string::FromUtf8Error,
+};
+
 use integral_enum::IntegralEnum;
 use thiserror::Error;
 
+use crate::mmu::{
+    Access,
+    PteFlags,
+};
+
 #[derive(IntegralEnum, Error)]
 #[enum_disable(display)]
 pub enum InstanceCreateError {
     #[error("Failed to allocate memory for the instance")]
     FailedToAllocate,
+
+    #[error("Machine option was rejected by RVVM")]
+    OptionRejected,
+
+    #[error("Trace mode was rejected, see `Instance::set_trace`")]
+    TraceRejected,
+
+    #[error("Layout of the RVVM internal structures doesn't match")]
+    LayoutMismatch,
 }
 
 #[derive(IntegralEnum, Error)]
@@ -15,11 +35,71 @@ pub enum InstancePauseError {
     NotRunning,
 }
 
+#[derive(IntegralEnum, Error)]
+#[enum_disable(display)]
+pub enum InstanceResetError {
+    #[error("Instance is not running")]
+    NotRunning,
+}
+
 #[derive(IntegralEnum, Error)]
 #[enum_disable(display)]
 pub enum InstanceStartError {
     #[error("Instance is already running")]
     AlreadyRunning,
+
+    #[error("RVVM rejected the reset watcher")]
+    ResetWatcherRejected,
+}
+
+#[derive(IntegralEnum, Error)]
+#[enum_disable(display)]
+pub enum SetOptError {
+    #[error("Machine option was rejected by RVVM")]
+    Rejected,
+}
+
+#[derive(IntegralEnum, Error)]
+#[enum_disable(display)]
+pub enum HartAccessError {
+    #[error("Hart index is out of range")]
+    OutOfRange,
+
+    #[error("Machine is running")]
+    MachineRunning,
+}
+
+#[derive(IntegralEnum, Error)]
+#[enum_disable(display)]
+pub enum CsrAccessError {
+    #[error("CSR doesn't exist or is read-only")]
+    Rejected,
+}
+
+#[derive(IntegralEnum, Error)]
+#[enum_disable(display)]
+pub enum DebugError {
+    #[error("Address is outside of the guest RAM")]
+    OutOfRam,
+
+    #[error("Watched range is empty")]
+    EmptyRange,
+
+    #[error("Guest code can't be patched while JIT is enabled")]
+    JitEnabled,
+
+    #[error("Only write watchpoints are supported")]
+    UnsupportedWatchKind,
+}
+
+#[derive(IntegralEnum, Error)]
+#[enum_disable(display)]
+pub enum TraceError {
+    #[error("Hart index is out of range")]
+    HartOutOfRange,
+
+    #[error("Guest code can't be stepped while JIT is enabled")]
+    JitEnabled,
 }
 
 #[derive(IntegralEnum, Error)]
@@ -29,11 +109,220 @@ pub enum DtbDumpError {
     FailedToOpenFile,
 }
 
-#[derive(IntegralEnum, Error)]
-#[enum_disable(display)]
+/// Not an `IntegralEnum` anymore, since the variants carry
+/// the faulting address. Stays `Copy`, so the range is
+/// stored as the bounds instead of the `Range<u64>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Error)]
 pub enum MemoryAccessError {
-    #[error("Requested range is out-of-bounds")]
-    OutOfBounds,
+    #[error("Requested range {start:#x}..{end:#x} is out-of-bounds")]
+    OutOfBounds { start: u64, end: u64 },
+
+    #[error("Address {addr:#x} is not aligned to {align} bytes")]
+    Misaligned { addr: u64, align: usize },
+
+    #[error("Machine is running")]
+    MachineRunning,
+}
+
+#[derive(Debug, Error)]
+pub enum StringReadError {
+    #[error("String is not nul-terminated within {max_len} bytes")]
+    Unterminated { max_len: usize },
+
+    #[error(transparent)]
+    Utf8(#[from] FromUtf8Error),
+
+    #[error(transparent)]
+    Memory(#[from] MemoryAccessError),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum PageFault {
+    #[error("Unsupported satp translation mode {0}")]
+    UnsupportedMode(u8),
+
+    #[error("Virtual address {vaddr:#x} is not canonical")]
+    NonCanonical { vaddr: u64 },
+
+    #[error(
+        "Virtual address {vaddr:#x} is not mapped (PTE at {pte_addr:#x}, \
+         level {level})"
+    )]
+    NotPresent {
+        vaddr: u64,
+        pte_addr: u64,
+        level: u32,
+    },
+
+    #[error(
+        "Malformed PTE at {pte_addr:#x} (level {level}) while \
+         translating {vaddr:#x}"
+    )]
+    InvalidPte {
+        vaddr: u64,
+        pte_addr: u64,
+        level: u32,
+    },
+
+    #[error(
+        "Misaligned superpage PTE at {pte_addr:#x} (level {level}) while \
+         translating {vaddr:#x}"
+    )]
+    MisalignedSuperpage {
+        vaddr: u64,
+        pte_addr: u64,
+        level: u32,
+    },
+
+    #[error(
+        "{access:?} access to {vaddr:#x} is not permitted by {flags:?}"
+    )]
+    PermissionDenied {
+        vaddr: u64,
+        access: Access,
+        flags: PteFlags,
+    },
+
+    #[error("Access fault: {0}")]
+    AccessFault(#[from] MemoryAccessError),
+}
+
+#[derive(Debug, Error)]
+pub enum LoadError {
+    #[error("Image file {} is not found", .0.display())]
+    NotFound(PathBuf),
+
+    #[error("Failed to read the image: {0}")]
+    Io(#[from] io::Error),
+
+    /// `size` is the amount of bytes read so far for the
+    /// streamed images
+    #[error(
+        "Image of {size} bytes doesn't fit into {available} bytes of RAM"
+    )]
+    TooLarge { size: usize, available: usize },
+
+    #[error(transparent)]
+    Memory(#[from] MemoryAccessError),
+
+    #[error("DTB address: {0}")]
+    DtbAddr(#[from] SetOptError),
+}
+
+#[derive(Debug, Error)]
+pub enum ElfError {
+    #[error("Image is not an ELF file")]
+    NotElf,
+
+    #[error("Malformed ELF image: {0}")]
+    Malformed(&'static str),
+
+    #[error("Big-endian ELF images are not supported")]
+    BigEndian,
+
+    #[error("ELF image is built for machine {machine}, not RISC-V")]
+    NotRiscV { machine: u16 },
+
+    #[error(
+        "ELF{elf_bits} image can't be loaded into the RV{machine_bits} \
+         machine"
+    )]
+    ClassMismatch { elf_bits: u8, machine_bits: u8 },
+
+    #[error(
+        "Segment of {size} bytes at {addr:#x} doesn't fit into the RAM"
+    )]
+    SegmentOutOfRam { addr: u64, size: u64 },
+
+    #[error(transparent)]
+    Load(#[from] LoadError),
+
+    #[error(transparent)]
+    Memory(#[from] MemoryAccessError),
+}
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("Not a RAM snapshot")]
+    InvalidMagic,
+
+    #[error("Unsupported snapshot version {0}")]
+    UnsupportedVersion(u32),
+
+    #[error("Corrupted snapshot: {0}")]
+    Corrupted(&'static str),
+
+    #[error(
+        "Snapshot of {mem_size} bytes RAM at {mem_base:#x} (rv64: \
+         {rv64}) doesn't match the machine"
+    )]
+    ConfigMismatch {
+        mem_base: u64,
+        mem_size: usize,
+        rv64: bool,
+    },
+
+    #[error("Failed to read the snapshot: {0}")]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Memory(#[from] MemoryAccessError),
+}
+
+#[derive(Debug, Error)]
+pub enum StateError {
+    #[error("Not a machine state")]
+    InvalidMagic,
+
+    #[error("Unsupported machine state version {0}")]
+    UnsupportedVersion(u32),
+
+    #[error("Corrupted machine state: {0}")]
+    Corrupted(&'static str),
+
+    #[error(
+        "Machine state of {harts} harts and {mem_size} bytes RAM at \
+         {mem_base:#x} (rv64: {rv64}) doesn't match the machine"
+    )]
+    ConfigMismatch {
+        harts: usize,
+        mem_base: u64,
+        mem_size: usize,
+        rv64: bool,
+    },
+
+    #[error("CSR {csr:#x} of the hart {hart} was rejected")]
+    CsrRejected { hart: usize, csr: u16 },
+
+    #[error("No matching device at {addr:#x} to restore the state")]
+    DeviceNotFound { addr: u64 },
+
+    #[error("Device at {addr:#x} rejected the saved state")]
+    DeviceRejected { addr: u64 },
+
+    #[error(transparent)]
+    Snapshot(#[from] SnapshotError),
+
+    #[error("Failed to read the machine state: {0}")]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Memory(#[from] MemoryAccessError),
+}
+
+#[derive(Debug, Error)]
+pub enum FdtCommitError {
+    #[error(
+        "Serialized FDT of {size} bytes doesn't fit into {mem_size} \
+         bytes of RAM"
+    )]
+    TooLarge { size: usize, mem_size: usize },
+
+    #[error(transparent)]
+    Memory(#[from] MemoryAccessError),
+
+    #[error("DTB address: {0}")]
+    DtbAddr(#[from] SetOptError),
 }
 
 #[derive(IntegralEnum, Error)]
@@ -42,3 +331,10 @@ pub enum DeviceAttachError {
     #[error("Tried to attach device to already occupied region")]
     RegionIsOccupied,
 }
+
+#[derive(IntegralEnum, Error)]
+#[enum_disable(display)]
+pub enum DeviceDetachError {
+    #[error("Device is not attached to this instance")]
+    NotAttached,
+}
@@ -10,7 +10,7 @@ use rvvm_sys::{
     fdt_node_find_reg_any,
 };
 
-pub struct Region<'a>(pub &'a CStr, u64);
+pub struct Region<'a>(pub &'a CStr, pub u64);
 pub struct AnyRegion<'a>(pub &'a CStr);
 pub struct Name<'a>(pub &'a CStr);
 
@@ -29,7 +29,6 @@ mod details {
     impl<T: AsRef<str>> Sealed for T {}
 }
 
-#[allow(private_in_public)]
 pub trait FdtFindExt: details::Sealed {
     /// Internal trait for fancy search interface
     ///
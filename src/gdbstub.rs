@@ -0,0 +1,799 @@
+use std::{
+    collections::HashSet,
+    fmt::Write as _,
+    io::{
+        self,
+        Read,
+        Write,
+    },
+    net::{
+        TcpListener,
+        TcpStream,
+        ToSocketAddrs,
+    },
+};
+
+use crate::{
+    debug::{
+        Stop,
+        StopKind,
+    },
+    disasm::{
+        F_NAMES,
+        X_NAMES,
+    },
+    error::DebugError,
+    instance::Instance,
+};
+
+const PACKET_SIZE: usize = 0x4000;
+
+/// GDB register number of the `pc`
+const REG_PC: usize = 32;
+/// GDB register number of the `f0`
+const REG_F0: usize = 33;
+/// GDB register number of the CSR `0`
+const REG_CSR0: usize = 65;
+/// GDB register number of the virtual `priv` register
+const REG_PRIV: usize = REG_CSR0 + 4096;
+
+const FPU_CSRS: [(&str, u16); 3] =
+    [("fflags", 0x001), ("frm", 0x002), ("fcsr", 0x003)];
+
+const CSRS: [(&str, u16); 21] = [
+    ("sstatus", 0x100),
+    ("sie", 0x104),
+    ("stvec", 0x105),
+    ("sscratch", 0x140),
+    ("sepc", 0x141),
+    ("scause", 0x142),
+    ("stval", 0x143),
+    ("sip", 0x144),
+    ("satp", 0x180),
+    ("mstatus", 0x300),
+    ("misa", 0x301),
+    ("medeleg", 0x302),
+    ("mideleg", 0x303),
+    ("mie", 0x304),
+    ("mtvec", 0x305),
+    ("mscratch", 0x340),
+    ("mepc", 0x341),
+    ("mcause", 0x342),
+    ("mtval", 0x343),
+    ("mip", 0x344),
+    ("mhartid", 0xf14),
+];
+
+/// Byte stream the GDB session is served over
+pub trait Connection: Read + Write {
+    /// Switches the stream to the non-blocking mode, used
+    /// to catch interrupt requests while guest is running
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for std::os::unix::net::UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+/// GDB remote serial protocol server.
+///
+/// Harts are exposed as threads with ids starting from
+/// `1`. Memory is accessed by the virtual addresses as seen
+/// by the selected hart. Breakpoints and stepping are
+/// implemented by patching the guest code, so machine must
+/// be built with the JIT disabled, see the
+/// `InstanceBuilder::jit`. Breakpoints are shared with the
+/// `Instance::add_breakpoint`, so the same instruction is
+/// never patched twice.
+pub struct GdbStub<'a> {
+    instance: &'a mut Instance,
+    /// Physical addresses of the breakpoints inserted by
+    /// the debugger, the others are left in place
+    inserted: HashSet<u64>,
+
+    /// Hart selected for register and memory access (`Hg`)
+    g_hart: usize,
+    /// Hart selected for stepping (`Hc`)
+    c_hart: Option<usize>,
+
+    no_ack: bool,
+}
+
+enum Action {
+    Reply(String),
+    Step(Option<usize>),
+    Continue,
+    Detach,
+    Kill,
+}
+
+impl<'a> GdbStub<'a> {
+    /// - Returns `GdbStub` if JIT is disabled
+    /// - Returns `DebugError::JitEnabled` otherwise
+    pub fn new(instance: &'a mut Instance) -> Result<Self, DebugError> {
+        if instance.jit_enabled() {
+            return Err(DebugError::JitEnabled);
+        }
+
+        Ok(Self {
+            instance,
+            inserted: HashSet::new(),
+            g_hart: 0,
+            c_hart: None,
+            no_ack: false,
+        })
+    }
+
+    /// Serves single GDB session over the connection.
+    ///
+    /// Machine is paused for the session. Breakpoints are
+    /// removed upon return and machine is started again if
+    /// it was running before or the debugger detached.
+    pub fn serve(&mut self, mut conn: impl Connection) -> io::Result<()> {
+        let was_running = self.instance.pause().is_ok();
+        let result = self.session(&mut conn);
+
+        let view = self.instance.view();
+        let mut points = self.instance.monitor().lock();
+        for paddr in self.inserted.drain() {
+            points.breakpoints.remove_phys(&view, paddr);
+        }
+        drop(points);
+
+        match result {
+            Ok(true) => {
+                let _ = self.instance.start();
+                Ok(())
+            }
+            Ok(false) => Ok(()),
+            Err(e) => {
+                if was_running {
+                    let _ = self.instance.start();
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns whether machine should be resumed
+    fn session(&mut self, conn: &mut impl Connection) -> io::Result<bool> {
+        loop {
+            let packet = match self.read_packet(conn)? {
+                Some(packet) => packet,
+                None => return Ok(true),
+            };
+
+            let reply = match self.handle(&packet) {
+                Action::Reply(reply) => reply,
+                Action::Step(hart) => {
+                    let hart = hart.or(self.c_hart).unwrap_or(self.g_hart);
+                    // Machine is run through the view, since it stays
+                    // paused for the `Instance`
+                    let view = self.instance.view();
+                    let mut exec = self.instance.view();
+                    conn.set_nonblocking(true)?;
+                    let stop =
+                        self.instance.monitor().lock().breakpoints.step(
+                            &view,
+                            &mut exec,
+                            hart,
+                            || interrupt_requested(conn),
+                        );
+                    conn.set_nonblocking(false)?;
+
+                    self.stop_reply(stop)
+                }
+                Action::Continue => {
+                    let view = self.instance.view();
+                    let mut exec = self.instance.view();
+                    conn.set_nonblocking(true)?;
+                    let stop =
+                        self.instance.monitor().lock().breakpoints.resume(
+                            &view,
+                            &mut exec,
+                            || interrupt_requested(conn),
+                        );
+                    conn.set_nonblocking(false)?;
+
+                    self.stop_reply(stop)
+                }
+                Action::Detach => {
+                    self.write_packet(conn, "OK")?;
+                    return Ok(true);
+                }
+                Action::Kill => return Ok(false),
+            };
+
+            self.write_packet(conn, &reply)?;
+        }
+    }
+
+    fn handle(&mut self, packet: &str) -> Action {
+        let reply = |reply: &str| Action::Reply(reply.to_owned());
+
+        let (cmd, args) = packet.split_at(packet.len().min(1));
+        match cmd {
+            "?" => Action::Reply(self.stop_reply(Stop {
+                hart: self.g_hart,
+                pc: 0,
+                kind: StopKind::Interrupted,
+            })),
+            "g" => Action::Reply(self.read_registers()),
+            "G" => reply(self.write_registers(args)),
+            "p" => Action::Reply(
+                parse_hex(args)
+                    .and_then(|reg| self.read_register(reg as usize))
+                    .unwrap_or_else(|| "E01".to_owned()),
+            ),
+            "P" => reply(self.write_register(args)),
+            "m" => Action::Reply(self.read_memory(args)),
+            "M" => reply(self.write_memory(args)),
+            "H" => reply(self.select_hart(args)),
+            "T" => match parse_thread(args) {
+                Some(Some(hart)) if hart < self.instance.harts() => {
+                    reply("OK")
+                }
+                _ => reply("E01"),
+            },
+            "c" => Action::Continue,
+            "s" => Action::Step(None),
+            "Z" | "z" => reply(self.breakpoint(cmd == "Z", args)),
+            "D" => Action::Detach,
+            "k" => Action::Kill,
+            "q" | "Q" => self.query(packet),
+            "v" => self.v_packet(packet),
+            _ => reply(""),
+        }
+    }
+
+    fn query(&mut self, packet: &str) -> Action {
+        let reply = match packet {
+            "QStartNoAckMode" => {
+                self.no_ack = true;
+                "OK".to_owned()
+            }
+            "qC" => format!("QC{:x}", self.g_hart + 1),
+            "qAttached" => "1".to_owned(),
+            "qfThreadInfo" => {
+                let ids: Vec<_> = (1..=self.instance.harts())
+                    .map(|id| format!("{id:x}"))
+                    .collect();
+                format!("m{}", ids.join(","))
+            }
+            "qsThreadInfo" => "l".to_owned(),
+            _ if packet.starts_with("qSupported") => format!(
+                "PacketSize={PACKET_SIZE:x};QStartNoAckMode+;qXfer:\
+                 features:read+;swbreak+;vContSupported+"
+            ),
+            _ if packet.starts_with("qThreadExtraInfo,") => {
+                match parse_thread(&packet[17..]) {
+                    Some(Some(hart)) => {
+                        hex(format!("hart {hart}").as_bytes())
+                    }
+                    _ => "E01".to_owned(),
+                }
+            }
+            _ if packet.starts_with("qXfer:features:read:target.xml:") => {
+                self.target_xml(&packet[31..])
+            }
+            _ => String::new(),
+        };
+
+        Action::Reply(reply)
+    }
+
+    fn v_packet(&mut self, packet: &str) -> Action {
+        if packet == "vCont?" {
+            return Action::Reply("vCont;c;C;s;S".to_owned());
+        }
+        let Some(actions) = packet.strip_prefix("vCont;") else {
+            return Action::Reply(String::new());
+        };
+
+        for action in actions.split(';') {
+            let (action, thread) = match action.split_once(':') {
+                Some((action, thread)) => (action, parse_thread(thread)),
+                None => (action, Some(None)),
+            };
+
+            if action.starts_with('s') || action.starts_with('S') {
+                return match thread {
+                    Some(hart) => Action::Step(hart),
+                    None => Action::Reply("E01".to_owned()),
+                };
+            }
+        }
+
+        Action::Continue
+    }
+
+    fn stop_reply(&mut self, stop: Stop) -> String {
+        self.g_hart = stop.hart;
+        let thread = stop.hart + 1;
+
+        match stop.kind {
+            StopKind::Breakpoint => {
+                format!("T05thread:{thread:x};swbreak:;")
+            }
+            StopKind::Step => format!("T05thread:{thread:x};"),
+            StopKind::Interrupted => format!("T02thread:{thread:x};"),
+            StopKind::PoweredOff => "W00".to_owned(),
+        }
+    }
+
+    fn select_hart(&mut self, args: &str) -> &'static str {
+        let (kind, thread) = args.split_at(args.len().min(1));
+        let hart = match parse_thread(thread) {
+            Some(Some(hart)) if hart < self.instance.harts() => Some(hart),
+            Some(None) => None,
+            _ => return "E01",
+        };
+
+        match kind {
+            "g" => self.g_hart = hart.unwrap_or(0),
+            "c" => self.c_hart = hart,
+            _ => return "E01",
+        }
+        "OK"
+    }
+
+    fn reg_bytes(&self) -> usize {
+        if self.instance.is_rv64() {
+            8
+        } else {
+            4
+        }
+    }
+
+    fn read_registers(&mut self) -> String {
+        (0..=REG_PC)
+            .filter_map(|reg| self.read_register(reg))
+            .collect()
+    }
+
+    fn write_registers(&mut self, args: &str) -> &'static str {
+        let bytes = match unhex(args) {
+            Some(bytes) => bytes,
+            None => return "E01",
+        };
+
+        for (reg, value) in
+            bytes.chunks_exact(self.reg_bytes()).enumerate()
+        {
+            if reg > REG_PC {
+                break;
+            }
+            if !self.set_register(reg, le_value(value)) {
+                return "E01";
+            }
+        }
+        "OK"
+    }
+
+    fn read_register(&mut self, reg: usize) -> Option<String> {
+        let reg_bytes = self.reg_bytes();
+        let mut hart = self.instance.hart_mut(self.g_hart).ok()?;
+
+        let (value, size) = match reg {
+            0..=31 => (hart.x(reg), reg_bytes),
+            REG_PC => (hart.pc(), reg_bytes),
+            REG_F0..=64 => (hart.f_bits(reg - REG_F0), 8),
+            REG_PRIV => (hart.privilege().raw() as u64, reg_bytes),
+            _ if (REG_CSR0..REG_PRIV).contains(&reg) => {
+                (hart.read_csr((reg - REG_CSR0) as u16).ok()?, reg_bytes)
+            }
+            _ => return None,
+        };
+
+        Some(hex(&value.to_le_bytes()[..size]))
+    }
+
+    fn write_register(&mut self, args: &str) -> &'static str {
+        let parsed = args.split_once('=').and_then(|(reg, value)| {
+            Some((parse_hex(reg)? as usize, le_value(&unhex(value)?)))
+        });
+
+        match parsed {
+            Some((reg, value)) if self.set_register(reg, value) => "OK",
+            _ => "E01",
+        }
+    }
+
+    fn set_register(&mut self, reg: usize, value: u64) -> bool {
+        let mut hart = match self.instance.hart_mut(self.g_hart) {
+            Ok(hart) => hart,
+            Err(_) => return false,
+        };
+
+        match reg {
+            0..=31 => hart.set_x(reg, value),
+            REG_PC => hart.set_pc(value),
+            REG_F0..=64 => hart.set_f_bits(reg - REG_F0, value),
+            REG_PRIV => hart.set_privilege(
+                crate::hart::Privilege::from_raw(value as u8),
+            ),
+            _ if (REG_CSR0..REG_PRIV).contains(&reg) => {
+                return hart
+                    .write_csr((reg - REG_CSR0) as u16, value)
+                    .is_ok()
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        let (addr, len) = match parse_addr_len(args) {
+            Some(range) => range,
+            None => return "E01".to_owned(),
+        };
+
+        // RAM reads hide the breakpoints from the debugger
+        let mut data = vec![0; len.min(PACKET_SIZE / 2)];
+        let read =
+            self.access_memory(addr, data.len(), |paddr, at, len| {
+                let chunk = &mut data[at..at + len];
+                self.instance.read_ram_to(paddr, chunk).is_ok()
+            });
+
+        if read == 0 && !data.is_empty() {
+            "E14".to_owned()
+        } else {
+            hex(&data[..read])
+        }
+    }
+
+    fn write_memory(&mut self, args: &str) -> &'static str {
+        let parsed = args.split_once(':').and_then(|(range, data)| {
+            Some((parse_addr_len(range)?, unhex(data)?))
+        });
+        let ((addr, len), data) = match parsed {
+            Some(parsed) => parsed,
+            None => return "E01",
+        };
+        if data.len() != len {
+            return "E01";
+        }
+
+        let view = self.instance.view();
+        let written = self.access_memory(addr, len, |paddr, at, len| {
+            view.write(paddr, &data[at..at + len])
+        });
+
+        if written == len {
+            "OK"
+        } else {
+            "E14"
+        }
+    }
+
+    /// Splits virtual memory range by pages and calls
+    /// `access(paddr, offset, len)` for each chunk until it
+    /// fails. Returns amount of the accessed bytes.
+    fn access_memory(
+        &self,
+        addr: u64,
+        len: usize,
+        mut access: impl FnMut(u64, usize, usize) -> bool,
+    ) -> usize {
+        let view = self.instance.view();
+        let mut done = 0;
+
+        while done < len {
+            let vaddr = addr.wrapping_add(done as u64);
+            let chunk =
+                (0x1000 - (vaddr & 0xfff) as usize).min(len - done);
+
+            match view.translate(self.g_hart, vaddr) {
+                Some(paddr) if access(paddr, done, chunk) => done += chunk,
+                _ => break,
+            }
+        }
+
+        done
+    }
+
+    fn breakpoint(&mut self, insert: bool, args: &str) -> &'static str {
+        let mut fields = args.split(',');
+        let kind = fields.next();
+        let addr = fields.next().and_then(parse_hex);
+
+        match (kind, addr) {
+            // Hardware breakpoints are emulated the same way
+            (Some("0" | "1"), Some(addr)) => {
+                let view = self.instance.view();
+                let Some(paddr) = view.translate(self.g_hart, addr) else {
+                    return "E01";
+                };
+
+                let mut points = self.instance.monitor().lock();
+                let breakpoints = &mut points.breakpoints;
+                let done = if insert {
+                    // Breakpoint set through the `Instance` is reused
+                    breakpoints.contains_phys(paddr)
+                        || breakpoints.insert_phys(&view, paddr)
+                            && self.inserted.insert(paddr)
+                } else if self.inserted.remove(&paddr) {
+                    breakpoints.remove_phys(&view, paddr)
+                } else {
+                    breakpoints.contains_phys(paddr)
+                };
+
+                if done {
+                    "OK"
+                } else {
+                    "E01"
+                }
+            }
+            _ => "",
+        }
+    }
+
+    fn target_xml(&self, range: &str) -> String {
+        let xml = self.target_description();
+        let (offset, len) = match parse_addr_len(range) {
+            Some((offset, len)) => (offset as usize, len),
+            None => return "E01".to_owned(),
+        };
+
+        let chunk = xml
+            .get(offset.min(xml.len())..)
+            .unwrap_or_default();
+        if chunk.len() > len {
+            format!("m{}", &chunk[..len])
+        } else {
+            format!("l{chunk}")
+        }
+    }
+
+    fn target_description(&self) -> String {
+        let xlen = self.reg_bytes() * 8;
+        let mut xml = format!(
+            "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \
+             \"gdb-target.dtd\"><target \
+             version=\"1.0\"><architecture>riscv:rv{xlen}</\
+             architecture><feature name=\"org.gnu.gdb.riscv.cpu\">"
+        );
+
+        let reg = |xml: &mut String,
+                   name: &str,
+                   bits: usize,
+                   ty: &str,
+                   num: usize| {
+            let _ = write!(
+                xml,
+                "<reg name=\"{name}\" bitsize=\"{bits}\" type=\"{ty}\" \
+                 regnum=\"{num}\"/>"
+            );
+        };
+
+        for (num, name) in X_NAMES.iter().enumerate() {
+            reg(&mut xml, name, xlen, "int", num);
+        }
+        reg(&mut xml, "pc", xlen, "code_ptr", REG_PC);
+
+        xml.push_str("</feature><feature name=\"org.gnu.gdb.riscv.fpu\">");
+        for (num, name) in F_NAMES.iter().enumerate() {
+            reg(&mut xml, name, 64, "ieee_double", REG_F0 + num);
+        }
+        for (name, csr) in FPU_CSRS {
+            reg(&mut xml, name, xlen, "int", REG_CSR0 + csr as usize);
+        }
+
+        xml.push_str("</feature><feature name=\"org.gnu.gdb.riscv.csr\">");
+        for (name, csr) in CSRS {
+            reg(&mut xml, name, xlen, "int", REG_CSR0 + csr as usize);
+        }
+
+        xml.push_str(
+            "</feature><feature name=\"org.gnu.gdb.riscv.virtual\">",
+        );
+        reg(&mut xml, "priv", xlen, "int", REG_PRIV);
+        xml.push_str("</feature></target>");
+
+        xml
+    }
+
+    /// Reads the next packet, acknowledging it unless
+    /// no-ack mode is enabled. Returns `None` on EOF.
+    fn read_packet(
+        &self,
+        conn: &mut impl Connection,
+    ) -> io::Result<Option<String>> {
+        loop {
+            // Skip acks and interrupt requests, target is already
+            // stopped
+            match read_byte(conn)? {
+                Some(b'$') => {}
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match read_byte(conn)? {
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                    None => return Ok(None),
+                }
+            }
+
+            let mut checksum = [0; 2];
+            conn.read_exact(&mut checksum)?;
+            let valid = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                == Some(sum(&data));
+
+            if !self.no_ack {
+                conn.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(
+                    String::from_utf8_lossy(&data).into_owned(),
+                ));
+            }
+        }
+    }
+
+    fn write_packet(
+        &self,
+        conn: &mut impl Connection,
+        data: &str,
+    ) -> io::Result<()> {
+        let packet = encode_packet(data);
+        conn.write_all(&packet)?;
+        conn.flush()?;
+
+        // Retransmit until acknowledged
+        if !self.no_ack {
+            loop {
+                match read_byte(conn)? {
+                    Some(b'+') | None => break,
+                    Some(b'-') => conn.write_all(&packet)?,
+                    Some(_) => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Accepts single GDB connection on the TCP address and
+/// serves it, see `GdbStub::serve`. Fails with the
+/// `io::ErrorKind::Unsupported` if JIT is enabled.
+pub fn serve_tcp(
+    instance: &mut Instance,
+    addr: impl ToSocketAddrs,
+) -> io::Result<()> {
+    let mut stub = stub(instance)?;
+    let listener = TcpListener::bind(addr)?;
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+
+    stub.serve(stream)
+}
+
+/// Accepts single GDB connection on the Unix socket and
+/// serves it, see `GdbStub::serve`. Socket file is removed
+/// after the session. Fails same as the `serve_tcp`.
+#[cfg(unix)]
+pub fn serve_unix(
+    instance: &mut Instance,
+    path: impl AsRef<std::path::Path>,
+) -> io::Result<()> {
+    let mut stub = stub(instance)?;
+    let listener = std::os::unix::net::UnixListener::bind(path.as_ref())?;
+    let result = listener
+        .accept()
+        .and_then(|(stream, _)| stub.serve(stream));
+
+    let _ = std::fs::remove_file(path);
+    result
+}
+
+fn stub(instance: &mut Instance) -> io::Result<GdbStub<'_>> {
+    GdbStub::new(instance)
+        .map_err(|e| io::Error::new(io::ErrorKind::Unsupported, e))
+}
+
+fn read_byte(conn: &mut impl Connection) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    loop {
+        return match conn.read(&mut byte) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(byte[0])),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => Err(e),
+        };
+    }
+}
+
+/// Checks for the `Ctrl-C` from the debugger, connection
+/// must be non-blocking
+fn interrupt_requested(conn: &mut impl Connection) -> bool {
+    let mut byte = [0];
+    matches!(conn.read(&mut byte), Ok(1) if byte[0] == 0x03)
+}
+
+/// Escapes the `data` and frames it with the `$` and the
+/// checksum
+pub(crate) fn encode_packet(data: &str) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for &byte in data.as_bytes() {
+        if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+            escaped.extend([b'}', byte ^ 0x20]);
+        } else {
+            escaped.push(byte);
+        }
+    }
+
+    let mut packet = Vec::with_capacity(escaped.len() + 4);
+    packet.push(b'$');
+    packet.extend_from_slice(&escaped);
+    packet.extend_from_slice(format!("#{:02x}", sum(&escaped)).as_bytes());
+    packet
+}
+
+fn sum(data: &[u8]) -> u8 {
+    data.iter()
+        .fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+pub(crate) fn unhex(hex: &str) -> Option<Vec<u8>> {
+    // `from_str_radix` also accepts the sign
+    if hex.len() % 2 != 0
+        || !hex.bytes().all(|byte| byte.is_ascii_hexdigit())
+    {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn le_value(bytes: &[u8]) -> u64 {
+    let mut value = [0; 8];
+    let len = bytes.len().min(8);
+    value[..len].copy_from_slice(&bytes[..len]);
+    u64::from_le_bytes(value)
+}
+
+fn parse_hex(hex: &str) -> Option<u64> {
+    u64::from_str_radix(hex, 16).ok()
+}
+
+fn parse_addr_len(args: &str) -> Option<(u64, usize)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((parse_hex(addr)?, parse_hex(len)? as usize))
+}
+
+/// Parses thread id into the hart index, `Some(None)` means
+/// any hart (`0` or `-1`)
+pub(crate) fn parse_thread(thread: &str) -> Option<Option<usize>> {
+    match thread {
+        "0" | "-1" => Some(None),
+        _ => parse_hex(thread)
+            .filter(|&id| id > 0)
+            .map(|id| Some(id as usize - 1)),
+    }
+}
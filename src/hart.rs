@@ -0,0 +1,467 @@
+use std::{
+    marker::PhantomData,
+    ops::Deref,
+    ptr::NonNull,
+};
+
+use rvvm_sys::{
+    riscv_csr_op,
+    rvvm_hart_t,
+    CSR_SETBITS,
+    CSR_SWAP,
+    PRIVILEGE_MACHINE,
+    REGISTER_PC,
+};
+
+use crate::{
+    error::{
+        CsrAccessError,
+        HartAccessError,
+    },
+    instance::Instance,
+};
+
+/// Hart privilege mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Privilege {
+    User,
+    Supervisor,
+    Machine,
+}
+
+impl Privilege {
+    /// Get privilege from its encoding, reserved `2` is
+    /// treated as the `Privilege::Machine`
+    pub const fn from_raw(raw: u8) -> Self {
+        match raw {
+            0 => Self::User,
+            1 => Self::Supervisor,
+            _ => Self::Machine,
+        }
+    }
+
+    pub const fn raw(self) -> u8 {
+        match self {
+            Self::User => 0,
+            Self::Supervisor => 1,
+            Self::Machine => 3,
+        }
+    }
+}
+
+/// Read-only handle to the hart of the paused machine,
+/// obtained through the `Instance::hart`.
+///
+/// Machine can't be started while the handle is alive.
+/// Values of the 32-bit harts are zero-extended. Hart can
+/// be modified only through the `Instance::hart_mut`:
+///
+/// ```compile_fail
+/// # use rvvm::instance::Instance;
+/// let instance = Instance::new(1, 0x8000_0000, 4096, true);
+/// instance.hart(0).unwrap().set_pc(0x8000_0000);
+/// ```
+#[derive(Debug)]
+pub struct Hart<'a> {
+    ptr: NonNull<rvvm_hart_t>,
+    id: usize,
+    rv64: bool,
+    _instance: PhantomData<&'a Instance>,
+}
+
+impl Hart<'_> {
+    pub const fn id(&self) -> usize {
+        self.id
+    }
+
+    pub const fn is_rv64(&self) -> bool {
+        self.rv64
+    }
+
+    pub fn pc(&self) -> u64 {
+        self.xlen(self.raw().registers[REGISTER_PC as usize])
+    }
+
+    /// Get the integer register `x{n}`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is not less than 32
+    pub fn x(&self, n: usize) -> u64 {
+        assert!(n < 32, "there are only 32 integer registers");
+        self.xlen(self.raw().registers[n])
+    }
+
+    /// Get all integer registers, `x0` included
+    pub fn xs(&self) -> [u64; 32] {
+        std::array::from_fn(|n| self.x(n))
+    }
+
+    /// Get the floating-point register `f{n}`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is not less than 32
+    pub fn f(&self, n: usize) -> f64 {
+        assert!(n < 32, "there are only 32 floating-point registers");
+        self.raw().fpu_registers[n]
+    }
+
+    /// Get raw bits of the floating-point register, single
+    /// precision values are NaN-boxed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is not less than 32
+    pub fn f_bits(&self, n: usize) -> u64 {
+        self.f(n).to_bits()
+    }
+
+    pub fn privilege(&self) -> Privilege {
+        Privilege::from_raw(self.raw().priv_mode)
+    }
+
+    const fn xlen(&self, value: u64) -> u64 {
+        if self.rv64 {
+            value
+        } else {
+            value as u32 as u64
+        }
+    }
+
+    fn raw(&self) -> &rvvm_hart_t {
+        // SAFETY: hart lives as long as the machine and is not
+        // running while `self` is alive, `HartMut` can't coexist
+        // with the other handles
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+/// Mutable handle to the hart of the paused machine,
+/// obtained through the `Instance::hart_mut`. Getters are
+/// available through the `Deref` to the `Hart`.
+#[derive(Debug)]
+pub struct HartMut<'a> {
+    hart: Hart<'a>,
+    _instance: PhantomData<&'a mut Instance>,
+}
+
+impl HartMut<'_> {
+    pub fn set_pc(&mut self, pc: u64) {
+        let pc = self.xlen(pc);
+        self.raw_mut().registers[REGISTER_PC as usize] = pc;
+    }
+
+    /// Set the integer register `x{n}`, writes to the `x0`
+    /// are ignored.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is not less than 32
+    pub fn set_x(&mut self, n: usize, value: u64) {
+        assert!(n < 32, "there are only 32 integer registers");
+        if n != 0 {
+            let value = self.xlen(value);
+            self.raw_mut().registers[n] = value;
+        }
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `n` is not less than 32
+    pub fn set_f(&mut self, n: usize, value: f64) {
+        assert!(n < 32, "there are only 32 floating-point registers");
+        self.raw_mut().fpu_registers[n] = value;
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `n` is not less than 32
+    pub fn set_f_bits(&mut self, n: usize, bits: u64) {
+        self.set_f(n, f64::from_bits(bits));
+    }
+
+    pub fn set_privilege(&mut self, privilege: Privilege) {
+        self.raw_mut().priv_mode = privilege.raw();
+    }
+
+    /// Reads CSR regardless of the current privilege mode.
+    /// Takes `&mut self`, since the hart is temporarily
+    /// switched to the machine mode for the access.
+    ///
+    /// - Returns value of the CSR if it exists
+    /// - Returns `CsrAccessError` otherwise
+    pub fn read_csr(&mut self, csr: u16) -> Result<u64, CsrAccessError> {
+        // SAFETY: machine is paused and exclusively borrowed while
+        // `self` is alive
+        unsafe { csr_read(self.hart.ptr.as_ptr(), csr) }
+            .map(|value| self.xlen(value))
+            .ok_or(CsrAccessError::Rejected)
+    }
+
+    /// Writes CSR regardless of the current privilege mode,
+    /// side effects of the write (e.g. TLB flush on the
+    /// `satp` write) are same as for the guest.
+    ///
+    /// - Returns `Ok` if CSR is written
+    /// - Returns `CsrAccessError` if it doesn't exist or is
+    ///   read-only
+    pub fn write_csr(
+        &mut self,
+        csr: u16,
+        value: u64,
+    ) -> Result<(), CsrAccessError> {
+        let value = self.xlen(value);
+        // SAFETY: machine is paused and exclusively borrowed while
+        // `self` is alive
+        if unsafe { csr_write(self.hart.ptr.as_ptr(), csr, value) } {
+            Ok(())
+        } else {
+            Err(CsrAccessError::Rejected)
+        }
+    }
+
+    fn raw_mut(&mut self) -> &mut rvvm_hart_t {
+        // SAFETY: instance is exclusively borrowed, so there are no
+        // other handles to the hart, see `Hart::raw`
+        unsafe { self.hart.ptr.as_mut() }
+    }
+}
+
+impl<'a> Deref for HartMut<'a> {
+    type Target = Hart<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.hart
+    }
+}
+
+impl Instance {
+    /// Get read-only handle to the hart `id` for inspecting
+    /// its state.
+    ///
+    /// - Returns `Hart` if machine is paused
+    /// - Returns `HartAccessError` otherwise
+    pub fn hart(&self, id: usize) -> Result<Hart<'_>, HartAccessError> {
+        if self.is_running() {
+            return Err(HartAccessError::MachineRunning);
+        }
+
+        Ok(Hart {
+            ptr: self
+                .hart_ptr(id)
+                .ok_or(HartAccessError::OutOfRange)?,
+            id,
+            rv64: self.is_rv64(),
+            _instance: PhantomData,
+        })
+    }
+
+    /// Get handle to the hart `id` for modifying its state.
+    /// See `Instance::hart`.
+    pub fn hart_mut(
+        &mut self,
+        id: usize,
+    ) -> Result<HartMut<'_>, HartAccessError> {
+        Ok(HartMut {
+            hart: self.hart(id)?,
+            _instance: PhantomData,
+        })
+    }
+}
+
+const CSR_FCSR: u16 = 0x003;
+const MSTATUS_FS: u64 = 0x3 << 13;
+
+/// CSRs that make up the architectural hart state, views
+/// like `sstatus` and read-only ones are omitted. `mstatus`
+/// goes first, since it controls access to the `fcsr`.
+pub(crate) const STATE_CSRS: &[u16] = &[
+    0x300,    // mstatus
+    CSR_FCSR, // fcsr
+    0x105,    // stvec
+    0x106,    // scounteren
+    0x140,    // sscratch
+    0x141,    // sepc
+    0x142,    // scause
+    0x143,    // stval
+    0x180,    // satp
+    0x302,    // medeleg
+    0x303,    // mideleg
+    0x304,    // mie
+    0x305,    // mtvec
+    0x306,    // mcounteren
+    0x340,    // mscratch
+    0x341,    // mepc
+    0x342,    // mcause
+    0x343,    // mtval
+    0x344,    // mip
+];
+
+/// Saved architectural state of the single hart
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct HartState {
+    /// `x0`-`x31` followed by the `pc`
+    pub regs: [u64; 33],
+    /// Raw bits of the `f0`-`f31`
+    pub fregs: [u64; 32],
+    pub priv_mode: u8,
+    /// Values of the `STATE_CSRS`
+    pub csrs: Vec<u64>,
+}
+
+impl Instance {
+    /// Get raw pointer to the hart, `None` if `id` is out
+    /// of range
+    pub(crate) fn hart_ptr(
+        &self,
+        id: usize,
+    ) -> Option<NonNull<rvvm_hart_t>> {
+        if id >= self.harts() {
+            return None;
+        }
+
+        // SAFETY: machine is created with `harts()` harts, which
+        // live as long as the machine itself
+        NonNull::new(unsafe { *(*self.as_ptr()).harts.data.add(id) })
+    }
+
+    /// Sanity check of the internal structures layout,
+    /// catches RVVM built with the different `USE_*`
+    /// flags: harts of the new machine must exist and
+    /// be in the machine mode
+    pub(crate) fn layout_matches(&self) -> bool {
+        (0..self.harts()).all(|id| {
+            self.hart_ptr(id).is_some_and(|hart| {
+                // SAFETY: hart lives as long as the machine
+                let mode = unsafe { hart.as_ref().priv_mode };
+                mode == PRIVILEGE_MACHINE as u8
+            })
+        })
+    }
+
+    /// Captures the hart state, machine must be paused.
+    /// `fcsr` is saved as zero while the FPU is off.
+    ///
+    /// Returns `Err` with the CSR number if it is rejected.
+    pub(crate) fn capture_hart(
+        &self,
+        id: usize,
+    ) -> Result<HartState, u16> {
+        let hart = self
+            .hart_ptr(id)
+            .expect("hart id is out of range");
+
+        // SAFETY: machine is paused, so hart is not running
+        unsafe {
+            let hart = hart.as_ptr();
+
+            let mut csrs = Vec::with_capacity(STATE_CSRS.len());
+            for &csr in STATE_CSRS {
+                let value = if fpu_off(csr, &csrs) {
+                    0
+                } else {
+                    csr_read(hart, csr).ok_or(csr)?
+                };
+                csrs.push(value);
+            }
+
+            // `maxlen_t` is `u64`, since RVVM is built with the
+            // `USE_RV64`
+            Ok(HartState {
+                regs: (*hart).registers,
+                fregs: (*hart).fpu_registers.map(f64::to_bits),
+                priv_mode: (*hart).priv_mode,
+                csrs,
+            })
+        }
+    }
+
+    /// Restores the hart state, machine must be paused.
+    /// `fcsr` is skipped if the restored FPU is off.
+    ///
+    /// Returns `Err` with the CSR number if it is rejected.
+    pub(crate) fn restore_hart(
+        &mut self,
+        id: usize,
+        state: &HartState,
+    ) -> Result<(), u16> {
+        let hart = self
+            .hart_ptr(id)
+            .expect("hart id is out of range");
+
+        // SAFETY: machine is paused, so hart is not running
+        unsafe {
+            let hart = hart.as_ptr();
+            for (&csr, &value) in STATE_CSRS.iter().zip(&state.csrs) {
+                if fpu_off(csr, &state.csrs) {
+                    continue;
+                }
+                if !csr_write(hart, csr, value) {
+                    return Err(csr);
+                }
+            }
+
+            (*hart).registers = state.regs;
+            (*hart).registers[0] = 0;
+            (*hart).fpu_registers = state.fregs.map(f64::from_bits);
+            (*hart).priv_mode = state.priv_mode;
+        }
+
+        Ok(())
+    }
+}
+
+/// Check whether `csr` is the `fcsr` and the FPU is off
+/// according to the `mstatus`, the first of `csrs`
+fn fpu_off(csr: u16, csrs: &[u64]) -> bool {
+    csr == CSR_FCSR
+        && csrs
+            .first()
+            .is_some_and(|mstatus| mstatus & MSTATUS_FS == 0)
+}
+
+/// Performs CSR operation in the machine mode, so any CSR
+/// is accessible regardless of the current hart privilege.
+///
+/// # Safety
+///
+/// `hart` must be valid and not running
+pub(crate) unsafe fn csr_op(
+    hart: *mut rvvm_hart_t,
+    csr: u16,
+    value: &mut u64,
+    op: u32,
+) -> bool {
+    let priv_mode = (*hart).priv_mode;
+
+    (*hart).priv_mode = PRIVILEGE_MACHINE as u8;
+    let result = riscv_csr_op(hart, csr as u32, value, op as u8);
+    (*hart).priv_mode = priv_mode;
+
+    result
+}
+
+/// # Safety
+///
+/// See `csr_op`
+pub(crate) unsafe fn csr_read(
+    hart: *mut rvvm_hart_t,
+    csr: u16,
+) -> Option<u64> {
+    // Setting no bits doesn't modify the CSR
+    let mut value = 0;
+    csr_op(hart, csr, &mut value, CSR_SETBITS).then_some(value)
+}
+
+/// # Safety
+///
+/// See `csr_op`
+pub(crate) unsafe fn csr_write(
+    hart: *mut rvvm_hart_t,
+    csr: u16,
+    value: u64,
+) -> bool {
+    let mut value = value;
+    csr_op(hart, csr, &mut value, CSR_SWAP)
+}
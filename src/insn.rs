@@ -0,0 +1,101 @@
+/// Get length of the instruction by its lowest halfword
+pub(crate) const fn insn_len(low: u16) -> u64 {
+    if low & 0b11 == 0b11 {
+        4
+    } else {
+        2
+    }
+}
+
+/// Sign-extends the lowest `bits` of the `value`
+pub(crate) const fn sext(value: u32, bits: u32) -> i64 {
+    ((value << (32 - bits)) as i32 >> (32 - bits)) as i64
+}
+
+/// Extracts bits `hi..=lo` of the instruction
+pub(crate) const fn bits(insn: u32, hi: u32, lo: u32) -> u32 {
+    (insn >> lo) & ((1 << (hi - lo + 1)) - 1)
+}
+
+pub(crate) const fn opcode(insn: u32) -> u32 {
+    insn & 0x7f
+}
+
+pub(crate) const fn rd(insn: u32) -> usize {
+    bits(insn, 11, 7) as usize
+}
+
+pub(crate) const fn rs1(insn: u32) -> usize {
+    bits(insn, 19, 15) as usize
+}
+
+pub(crate) const fn rs2(insn: u32) -> usize {
+    bits(insn, 24, 20) as usize
+}
+
+pub(crate) const fn rs3(insn: u32) -> usize {
+    bits(insn, 31, 27) as usize
+}
+
+pub(crate) const fn funct3(insn: u32) -> u32 {
+    bits(insn, 14, 12)
+}
+
+pub(crate) const fn funct7(insn: u32) -> u32 {
+    bits(insn, 31, 25)
+}
+
+pub(crate) const fn imm_i(insn: u32) -> i64 {
+    sext(insn >> 20, 12)
+}
+
+pub(crate) const fn imm_s(insn: u32) -> i64 {
+    sext(bits(insn, 31, 25) << 5 | bits(insn, 11, 7), 12)
+}
+
+pub(crate) const fn imm_b(insn: u32) -> i64 {
+    sext(
+        bits(insn, 31, 31) << 12
+            | bits(insn, 7, 7) << 11
+            | bits(insn, 30, 25) << 5
+            | bits(insn, 11, 8) << 1,
+        13,
+    )
+}
+
+pub(crate) const fn imm_j(insn: u32) -> i64 {
+    sext(
+        bits(insn, 31, 31) << 20
+            | bits(insn, 19, 12) << 12
+            | bits(insn, 20, 20) << 11
+            | bits(insn, 30, 21) << 1,
+        21,
+    )
+}
+
+/// Offset of the `c.j`/`c.jal`
+pub(crate) const fn c_imm_j(insn: u32) -> i64 {
+    sext(
+        bits(insn, 12, 12) << 11
+            | bits(insn, 8, 8) << 10
+            | bits(insn, 10, 9) << 8
+            | bits(insn, 6, 6) << 7
+            | bits(insn, 7, 7) << 6
+            | bits(insn, 2, 2) << 5
+            | bits(insn, 11, 11) << 4
+            | bits(insn, 5, 3) << 1,
+        12,
+    )
+}
+
+/// Offset of the `c.beqz`/`c.bnez`
+pub(crate) const fn c_imm_b(insn: u32) -> i64 {
+    sext(
+        bits(insn, 12, 12) << 8
+            | bits(insn, 6, 5) << 6
+            | bits(insn, 2, 2) << 5
+            | bits(insn, 11, 10) << 3
+            | bits(insn, 4, 3) << 1,
+        9,
+    )
+}
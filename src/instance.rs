@@ -1,61 +1,236 @@
 use std::{
-    ffi::CString,
+    any::TypeId,
+    collections::HashMap,
+    ffi::{
+        c_void,
+        CStr,
+    },
+    fs::File,
+    io::{
+        self,
+        Read,
+    },
     mem,
     path::Path,
-    ptr::NonNull,
+    ptr::{
+        self,
+        NonNull,
+    },
     slice,
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+    },
+    thread,
+    time::Duration,
 };
 
 use rvvm_sys::{
+    rvvm_append_cmdline,
     rvvm_attach_mmio,
     rvvm_create_machine,
+    rvvm_detach_mmio,
     rvvm_dump_dtb,
     rvvm_free_machine,
+    rvvm_get_dma_ptr,
     rvvm_get_fdt_root,
     rvvm_get_fdt_soc,
     rvvm_get_mmio,
-    rvvm_load_bootrom,
-    rvvm_load_dtb,
-    rvvm_load_kernel,
     rvvm_machine_powered_on,
+    rvvm_machine_running,
     rvvm_machine_t,
     rvvm_mmio_dev_t,
+    rvvm_mmio_type_t,
     rvvm_pause_machine,
     rvvm_read_ram,
+    rvvm_reset_machine,
+    rvvm_run_eventloop,
+    rvvm_set_cmdline,
+    rvvm_set_opt,
     rvvm_start_machine,
     rvvm_write_ram,
     RVVM_DEFAULT_MEMBASE,
+    RVVM_OPT_DTB_ADDR,
+    RVVM_OPT_HW_IMITATE,
+    RVVM_OPT_JIT,
+    RVVM_OPT_JIT_CACHE,
+    RVVM_OPT_MAX_CPU_CENT,
+    RVVM_OPT_VERBOSITY,
 };
 
+#[cfg(feature = "internals")]
+use crate::debug::Monitor;
 use crate::{
     builders::instance::InstanceBuilder,
+    c_str,
     dev::{
-        mmio::*,
-        type_::*,
+        mmio::{
+            load_state_trampoline,
+            read_trampoline,
+            save_state_trampoline,
+            write_trampoline,
+            Device,
+        },
+        type_::{
+            DefaultType,
+            DeviceType,
+            RawDeviceType,
+        },
     },
     error::{
         DeviceAttachError,
+        DeviceDetachError,
         DtbDumpError,
+        FdtCommitError,
         InstanceCreateError,
         InstancePauseError,
+        InstanceResetError,
         InstanceStartError,
+        LoadError,
         MemoryAccessError,
+        SetOptError,
     },
     fdt::*,
+    internal_utils::{
+        path_to_cstring,
+        take_boxed_voidptr,
+    },
+    memory::{
+        GuestMemory,
+        GuestMemoryMut,
+    },
     types::*,
 };
 
+/// Machine option, see `Instance::set_opt`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MachineOpt {
+    /// Enable JIT compilation
+    Jit(bool),
+
+    /// Amount of per-core JIT cache in bytes
+    JitCache(usize),
+
+    /// Verbosity level of the RVVM internal logic
+    Verbosity(u32),
+
+    /// Max CPU load percent per guest/host CPUs
+    MaxCpuPercent(u32),
+
+    /// Imitate traits or identity of the physical hardware
+    HwImitate(bool),
+
+    /// Physical address of the DTB passed to the kernel,
+    /// omits FDT generation if non-zero
+    DtbAddr(u64),
+}
+
+impl MachineOpt {
+    /// Get RVVM option id and its value
+    pub const fn raw(self) -> (u32, u64) {
+        match self {
+            Self::Jit(enable) => (RVVM_OPT_JIT, enable as _),
+            Self::JitCache(size) => (RVVM_OPT_JIT_CACHE, size as _),
+            Self::Verbosity(level) => (RVVM_OPT_VERBOSITY, level as _),
+            Self::MaxCpuPercent(cent) => {
+                (RVVM_OPT_MAX_CPU_CENT, cent as _)
+            }
+            Self::HwImitate(enable) => (RVVM_OPT_HW_IMITATE, enable as _),
+            Self::DtbAddr(addr) => (RVVM_OPT_DTB_ADDR, addr),
+        }
+    }
+}
+
 pub struct Instance {
     ptr: NonNull<rvvm_machine_t>,
+
+    // Attached devices, keyed by the raw handle. Used to
+    // validate `DeviceHandle`s
+    devices: HashMap<i32, AttachedDevice>,
+
+    // Whether CPUs are started through the `Instance::start`
+    // and not paused since then. Shared with the monitor,
+    // which pauses the machine on breakpoints
+    running: Arc<AtomicBool>,
+
+    // Set while the monitor keeps the machine paused to check
+    // the breakpoints, see the `run_eventloop`
+    monitor_paused: Arc<AtomicBool>,
+
+    // Observed by the internal device on the first run
+    events: Option<Arc<LoopEvents>>,
+
+    // Spawned on the first breakpoint or watchpoint
+    #[cfg(feature = "internals")]
+    monitor: Option<Monitor>,
+
+    harts: usize,
+    mem_base: u64,
+    mem_size: usize,
+    rv64: bool,
+}
+
+pub(crate) struct AttachedDevice {
+    data_ty: TypeId,
+
+    #[cfg_attr(not(feature = "internals"), allow(dead_code))]
+    pub(crate) save_state:
+        unsafe fn(*mut rvvm_mmio_dev_t) -> Option<Vec<u8>>,
+    #[cfg_attr(not(feature = "internals"), allow(dead_code))]
+    pub(crate) load_state: unsafe fn(*mut rvvm_mmio_dev_t, &[u8]) -> bool,
+
+    // Must outlive the device, RVVM refers to it until the
+    // device is removed
+    _ty: Option<Box<RawDeviceType>>,
 }
 
 impl Instance {
+    /// Attaches MMIO device to the machine.
+    ///
+    /// Guest reads and writes of the device region are
+    /// routed to the `Device::read`/`Device::write`. From
+    /// now on the device data is owned by the machine and
+    /// will be dropped when device is removed.
+    ///
+    /// - Returns `DeviceHandle` if device is attached
+    /// - Returns `DeviceAttachError` otherwise
     pub fn try_attach_device<Ty, Dev>(
         &mut self,
         dev: Dev,
     ) -> Result<DeviceHandle<Ty>, DeviceAttachError>
     where
-        Ty: Send + Sync,
+        Ty: Send + Sync + 'static,
+        Dev: Device<Ty>,
+    {
+        self.attach_device_impl(dev, None)
+    }
+
+    /// Attaches MMIO device of the specified type to the
+    /// machine. Same as the `Instance::try_attach_device`,
+    /// but device also receives reset/update/remove
+    /// callbacks from the `DeviceType`.
+    pub fn try_attach_device_with_type<Ty, Dev>(
+        &mut self,
+        dev: Dev,
+        ty: DeviceType<Ty>,
+    ) -> Result<DeviceHandle<Ty>, DeviceAttachError>
+    where
+        Ty: Send + Sync + 'static,
+        Dev: Device<Ty>,
+    {
+        self.attach_device_impl(dev, Some(ty.into_raw()))
+    }
+
+    fn attach_device_impl<Ty, Dev>(
+        &mut self,
+        dev: Dev,
+        mut ty: Option<Box<RawDeviceType>>,
+    ) -> Result<DeviceHandle<Ty>, DeviceAttachError>
+    where
+        Ty: Send + Sync + 'static,
         Dev: Device<Ty>,
     {
         // Dev is meant to be `repr(transparent)` to the
@@ -73,106 +248,460 @@ impl Instance {
             mem::ManuallyDrop::new(src)
         }
 
+        debug_assert_eq!(
+            mem::size_of::<Dev>(),
+            mem::size_of::<rvvm_mmio_dev_t>()
+        );
+
         let mut underlying = unsafe {
             CopyCast::<Dev, rvvm_mmio_dev_t> { src: no_drop(dev) }.dst
         };
 
-        todo!()
+        underlying.read = Some(read_trampoline::<Ty, Dev>);
+        underlying.write = Some(write_trampoline::<Ty, Dev>);
+        underlying.type_ = match ty {
+            Some(ref mut ty) => ty.as_mut_ptr(),
+            None => {
+                DefaultType::<Ty>::RAW as *const rvvm_mmio_type_t as *mut _
+            }
+        };
+
+        // SAFETY: `self.ptr` is obtained from
+        // `rvvm_create_machine`, RVVM copies the device
+        // description, so `underlying` can be dropped after
+        // the call. Failed device is handed to the type's
+        // remove handler, so nothing is freed here
+        let handle =
+            unsafe { rvvm_attach_mmio(self.ptr.as_ptr(), &underlying) };
+        if handle < 0 {
+            Err(DeviceAttachError::RegionIsOccupied)
+        } else {
+            self.devices.insert(
+                handle,
+                AttachedDevice {
+                    data_ty: TypeId::of::<Ty>(),
+                    save_state: save_state_trampoline::<Ty, Dev>,
+                    load_state: load_state_trampoline::<Ty, Dev>,
+                    _ty: ty,
+                },
+            );
+            Ok(DeviceHandle::new(handle))
+        }
+    }
+
+    /// Get reference to the data of the attached device.
+    ///
+    /// Machine is allowed to run, since devices observe
+    /// their data only through the shared reference.
+    ///
+    /// Returns `None` if `handle` doesn't refer to the
+    /// device of this instance.
+    pub fn device<T>(&self, handle: DeviceHandle<T>) -> Option<&T>
+    where
+        T: Send + Sync + 'static,
+    {
+        // SAFETY: pointer is valid while `self` is borrowed, since
+        // device can't be detached without the `&mut self`
+        self.device_data_ptr(handle)
+            .map(|ptr| unsafe { &*ptr })
+    }
+
+    /// Get exclusive access to the data of the attached
+    /// device.
+    ///
+    /// Machine is paused while returned `DeviceGuard` is
+    /// alive and started again upon its drop if it was
+    /// running before.
+    ///
+    /// Returns `None` if `handle` doesn't refer to the
+    /// device of this instance.
+    pub fn device_mut<T>(
+        &mut self,
+        handle: DeviceHandle<T>,
+    ) -> Option<DeviceGuard<'_, T>>
+    where
+        T: Send + Sync + 'static,
+    {
+        let ptr = self.device_data_ptr(handle)?;
+        let resume = self.pause().is_ok();
+
+        // SAFETY: CPUs are stopped, so nobody else observes the
+        // data while guard is alive
+        Some(unsafe { DeviceGuard::new(self, ptr, resume) })
+    }
+
+    /// Detaches device from the machine and returns its
+    /// data back.
+    ///
+    /// Machine is paused for the time of removal. Remove
+    /// handler of the device type is not called, since data
+    /// is not dropped.
+    ///
+    /// - Returns data of the device if it was detached
+    /// - Returns `DeviceDetachError` if `handle` doesn't
+    ///   refer to the device of this instance
+    pub fn detach_device<T>(
+        &mut self,
+        handle: DeviceHandle<T>,
+    ) -> Result<T, DeviceDetachError>
+    where
+        T: Send + Sync + 'static,
+    {
+        let data = self
+            .device_data_ptr(handle)
+            .ok_or(DeviceDetachError::NotAttached)?;
+        let resume = self.pause().is_ok();
+
+        // SAFETY: `self.ptr` is obtained from `rvvm_create_machine`
+        // and CPUs are stopped. Data pointer is cleared before the
+        // removal, so RVVM won't touch the data anymore
+        unsafe {
+            let dev = rvvm_get_mmio(self.ptr.as_ptr(), handle.inner);
+            (*dev).data = ptr::null_mut();
+            rvvm_detach_mmio(self.ptr.as_ptr(), handle.inner, false);
+        }
+        self.devices.remove(&handle.inner);
+
+        if resume {
+            let _ = self.start();
+        }
+
+        // SAFETY: data was allocated as `Box<T>` and the machine no
+        // longer refers to it
+        Ok(unsafe { take_boxed_voidptr::<T>(data as *mut _) })
+    }
+
+    fn device_data_ptr<T: 'static>(
+        &self,
+        handle: DeviceHandle<T>,
+    ) -> Option<*mut T> {
+        let attached = self.devices.get(&handle.inner)?;
+        if attached.data_ty != TypeId::of::<T>() {
+            return None;
+        }
+
+        // SAFETY: `self.ptr` is obtained from `rvvm_create_machine`
+        let dev =
+            unsafe { rvvm_get_mmio(self.ptr.as_ptr(), handle.inner) };
+        if dev.is_null() {
+            return None;
+        }
+
+        // SAFETY: `dev` is not null and points to the device
+        // description stored in the machine
+        let data = unsafe { (*dev).data };
+        if data.is_null() {
+            None
+        } else {
+            Some(data as *mut T)
+        }
+    }
+
+    /// Get names of the built-in RVVM devices attached to
+    /// the machine, i.e. the ones that are not attached
+    /// through the `Instance::try_attach_device`
+    pub fn builtin_devices(&self) -> Vec<String> {
+        // Detached devices leave null slots behind, so the
+        // scan doesn't stop at the first one
+        (0..MMIO_HANDLE_LIMIT)
+            .filter(|handle| !self.devices.contains_key(handle))
+            .filter_map(|handle| {
+                // SAFETY: `self.ptr` is obtained from
+                // `rvvm_create_machine`
+                let dev =
+                    unsafe { rvvm_get_mmio(self.ptr.as_ptr(), handle) };
+                (!dev.is_null()).then_some((handle, dev))
+            })
+            .filter_map(|(_, dev)| {
+                // SAFETY: `dev` is not null and points to the device
+                // description stored in the machine, type and its
+                // name outlive the device
+                unsafe {
+                    let ty = (*dev).type_;
+                    if ty.is_null() {
+                        return Some("unknown".to_owned());
+                    }
+                    if ptr::eq(ty, RESET_WATCHER) {
+                        return None;
+                    }
+
+                    Some(
+                        CStr::from_ptr((*ty).name)
+                            .to_string_lossy()
+                            .into_owned(),
+                    )
+                }
+            })
+            .collect()
+    }
+
+    /// Get raw descriptions of the attached Rust devices
+    /// ordered by their handles
+    #[cfg_attr(not(feature = "internals"), allow(dead_code))]
+    pub(crate) fn attached_devices(
+        &self,
+    ) -> Vec<(*mut rvvm_mmio_dev_t, &AttachedDevice)> {
+        let mut devices: Vec<_> = self
+            .devices
+            .iter()
+            .map(|(&handle, attached)| {
+                // SAFETY: `self.ptr` is obtained from
+                // `rvvm_create_machine`
+                let dev =
+                    unsafe { rvvm_get_mmio(self.ptr.as_ptr(), handle) };
+                (handle, dev, attached)
+            })
+            .filter(|(_, dev, _)| !dev.is_null())
+            .collect();
+        devices.sort_by_key(|&(handle, ..)| handle);
+
+        devices
+            .into_iter()
+            .map(|(_, dev, attached)| (dev, attached))
+            .collect()
     }
 }
 
-// FIXME: add correct error reporting
-// mainly this could be fixed by RVVM, I'll make PR to ti
 impl Instance {
     pub fn try_dump_dtb(
         &mut self,
         dest: impl AsRef<Path>,
     ) -> Result<(), DtbDumpError> {
-        self.loader_dumper_impl(
-            DtbDumpError::FailedToOpenFile,
-            dest,
-            rvvm_dump_dtb,
-        )
+        let path = path_to_cstring(dest.as_ref());
+
+        // SAFETY: `self.ptr` is obtained from `rvvm_create_machine`
+        if unsafe { rvvm_dump_dtb(self.ptr.as_ptr(), path.as_ptr()) } {
+            Ok(())
+        } else {
+            Err(DtbDumpError::FailedToOpenFile)
+        }
     }
 
-    /// Load device tree binary into machine's RAM.
+    /// Load device tree binary file into machine's RAM.
+    /// Same as the `Instance::load_dtb_bytes`.
     ///
     /// - Returns `Ok` if load was successful
-    /// - Returns `MemoryAccessError` otherwise
-    ///
-    /// # Panics
-    ///
-    /// Panics if path has nul-byte character or if path is
-    /// not a valid utf8 sequence.
+    /// - Returns `LoadError` otherwise
     pub fn try_load_dtb(
         &mut self,
         path: impl AsRef<Path>,
-    ) -> Result<(), MemoryAccessError> {
-        self.loader_dumper_impl(
-            MemoryAccessError::OutOfBounds,
-            path,
-            rvvm_load_dtb,
-        )
+    ) -> Result<(), LoadError> {
+        let (file, _) = Self::open_image(path.as_ref())?;
+        self.load_dtb_from(file)
     }
 
-    /// Load kernel binary into machine's RAM.
+    /// Load kernel binary file into machine's RAM. Same as
+    /// the `Instance::load_kernel_bytes`.
     ///
     /// - Returns `Ok` if load was successful
-    /// - Returns `MemoryAccessError` otherwise
-    /// # Panics
-    ///
-    /// Panics if path has nul-byte character or if path is
-    /// not a valid utf8 sequence.
+    /// - Returns `LoadError` otherwise
     pub fn try_load_kernel(
         &mut self,
         path: impl AsRef<Path>,
-    ) -> Result<(), MemoryAccessError> {
-        self.loader_dumper_impl(
-            MemoryAccessError::OutOfBounds,
-            path,
-            rvvm_load_kernel,
-        )
+    ) -> Result<(), LoadError> {
+        let (file, size) = Self::open_image(path.as_ref())?;
+        self.check_image_size(size, self.kernel_offset())?;
+
+        self.load_kernel_from(file)
     }
 
-    /// Load bootrom binary into machine's RAM.
+    /// Load bootrom binary file into machine's RAM. Same as
+    /// the `Instance::load_bootrom_bytes`.
     ///
     /// - Returns `Ok` if load was successful
-    /// - Returns `MemoryAccessError` otherwise
-    /// # Panics
-    ///
-    /// Panics if path has nul-byte character or if path is
-    /// not a valid utf8 sequence.
+    /// - Returns `LoadError` otherwise
     pub fn try_load_bootrom(
         &mut self,
         path: impl AsRef<Path>,
-    ) -> Result<(), MemoryAccessError> {
-        self.loader_dumper_impl(
-            MemoryAccessError::OutOfBounds,
-            path,
-            rvvm_load_bootrom,
-        )
+    ) -> Result<(), LoadError> {
+        let (file, size) = Self::open_image(path.as_ref())?;
+        self.check_image_size(size, 0)?;
+
+        self.load_bootrom_from(file)
+    }
+
+    pub(crate) fn open_image(
+        path: &Path,
+    ) -> Result<(File, usize), LoadError> {
+        let file = File::open(path).map_err(|e| {
+            if e.kind() == io::ErrorKind::NotFound {
+                LoadError::NotFound(path.to_owned())
+            } else {
+                LoadError::Io(e)
+            }
+        })?;
+        let size = file.metadata()?.len();
+
+        Ok((file, size.try_into().unwrap_or(usize::MAX)))
+    }
+}
+
+impl Instance {
+    /// Offset of the kernel from the RAM beginning, same as
+    /// the one used by RVVM
+    pub const fn kernel_offset(&self) -> u64 {
+        if self.rv64 {
+            0x200000
+        } else {
+            0x400000
+        }
+    }
+
+    /// Load bootrom image from memory, bootrom is placed at
+    /// the RAM beginning.
+    ///
+    /// - Returns `Ok` if load was successful
+    /// - Returns `LoadError` otherwise
+    pub fn load_bootrom_bytes(
+        &mut self,
+        data: &[u8],
+    ) -> Result<(), LoadError> {
+        self.check_image_size(data.len(), 0)?;
+        self.check_not_running()?;
+
+        Ok(self.write_ram(self.mem_base, data)?)
     }
 
-    fn loader_dumper_impl<E>(
+    /// Load kernel image from memory, kernel is placed at
+    /// the `Instance::kernel_offset`.
+    ///
+    /// - Returns `Ok` if load was successful
+    /// - Returns `LoadError` otherwise
+    pub fn load_kernel_bytes(
         &mut self,
-        e: E,
-        path: impl AsRef<Path>,
-        fn_: unsafe extern "C" fn(
-            *mut rvvm_machine_t,
-            *const std::ffi::c_char,
-        ) -> bool,
-    ) -> Result<(), E> {
-        let path = CString::new(
-            path.as_ref()
-                .to_str()
-                .expect("path is not a valid utf8 sequence"),
-        )
-        .expect("Path contains nul-byte character");
+        data: &[u8],
+    ) -> Result<(), LoadError> {
+        let offset = self.kernel_offset();
+        self.check_image_size(data.len(), offset)?;
+        self.check_not_running()?;
+
+        Ok(self.write_ram(self.mem_base + offset, data)?)
+    }
+
+    /// Load device tree binary from memory. DTB is placed
+    /// at the end of RAM and its address is passed to the
+    /// kernel instead of the generated FDT.
+    ///
+    /// - Returns `Ok` if load was successful
+    /// - Returns `LoadError` otherwise
+    pub fn load_dtb_bytes(
+        &mut self,
+        data: &[u8],
+    ) -> Result<(), LoadError> {
+        self.check_image_size(data.len(), 0)?;
+        self.check_not_running()?;
+
+        self.place_dtb::<LoadError>(data)?;
+        Ok(())
+    }
+
+    /// Load bootrom image from the reader. Same as the
+    /// `Instance::load_bootrom_bytes`, but streams the
+    /// image.
+    pub fn load_bootrom_from(
+        &mut self,
+        reader: impl Read,
+    ) -> Result<(), LoadError> {
+        self.stream_to_ram(0, reader)
+    }
+
+    /// Load kernel image from the reader. Same as the
+    /// `Instance::load_kernel_bytes`, but streams the
+    /// image.
+    pub fn load_kernel_from(
+        &mut self,
+        reader: impl Read,
+    ) -> Result<(), LoadError> {
+        self.stream_to_ram(self.kernel_offset(), reader)
+    }
+
+    /// Load device tree binary from the reader. Same as the
+    /// `Instance::load_dtb_bytes`, DTB is read completely
+    /// first, since its placement depends on the size.
+    pub fn load_dtb_from(
+        &mut self,
+        mut reader: impl Read,
+    ) -> Result<(), LoadError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        self.load_dtb_bytes(&data)
+    }
+
+    /// Writes DTB to the end of RAM and passes its address
+    /// to the kernel. Returns physical address of the DTB
+    fn place_dtb<E>(&mut self, data: &[u8]) -> Result<u64, E>
+    where
+        E: From<MemoryAccessError> + From<SetOptError>,
+    {
+        let addr = self.mem_base + self.dtb_offset(data.len());
+
+        self.write_ram(addr, data)?;
+        self.set_opt(MachineOpt::DtbAddr(addr))?;
+
+        Ok(addr)
+    }
+
+    /// Offset of the DTB with specified size, DTB must be
+    /// 8-byte aligned. Size must not exceed the RAM size
+    fn dtb_offset(&self, size: usize) -> u64 {
+        ((self.mem_size - size) & !7) as u64
+    }
+
+    fn stream_to_ram(
+        &mut self,
+        offset: u64,
+        mut reader: impl Read,
+    ) -> Result<(), LoadError> {
+        const CHUNK_SIZE: usize = 64 * 1024;
+
+        self.check_not_running()?;
+
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let mut written = 0;
+        loop {
+            let read = match reader.read(&mut chunk) {
+                Ok(0) => break Ok(()),
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                    continue
+                }
+                Err(e) => break Err(e.into()),
+            };
+
+            self.check_image_size(written + read, offset)?;
+            self.write_ram(
+                self.mem_base + offset + written as u64,
+                &chunk[..read],
+            )?;
+            written += read;
+        }
+    }
+
+    /// Check whether image of `size` bytes fits into RAM
+    /// starting from `offset`
+    fn check_image_size(
+        &self,
+        size: usize,
+        offset: u64,
+    ) -> Result<(), LoadError> {
+        let available =
+            (self.mem_size as u64).saturating_sub(offset) as usize;
 
-        if unsafe { fn_(self.ptr.as_ptr(), path.as_ptr()) } {
+        if size > available {
+            Err(LoadError::TooLarge { size, available })
+        } else {
             Ok(())
+        }
+    }
+
+    pub(crate) fn check_not_running(
+        &self,
+    ) -> Result<(), MemoryAccessError> {
+        if self.is_running() {
+            Err(MemoryAccessError::MachineRunning)
         } else {
-            Err(e)
+            Ok(())
         }
     }
 }
@@ -187,14 +716,18 @@ impl Instance {
         dst: u64,
         data: &[u8],
     ) -> Result<(), MemoryAccessError> {
-        Self::bool_to_memacc(unsafe {
-            rvvm_write_ram(
-                self.ptr.as_ptr(),
-                dst,
-                data.as_ptr() as *mut _,
-                data.len(),
-            )
-        })
+        Self::bool_to_memacc(
+            unsafe {
+                rvvm_write_ram(
+                    self.ptr.as_ptr(),
+                    dst,
+                    data.as_ptr() as *mut _,
+                    data.len(),
+                )
+            },
+            dst,
+            data.len(),
+        )
     }
 
     /// Read from machine's memory to slice
@@ -219,7 +752,9 @@ impl Instance {
         }
     }
 
-    /// Read from machine's memory to uninitialized slice
+    /// Read from machine's memory to uninitialized slice.
+    /// Breakpoints are not visible, original instructions
+    /// are read instead.
     ///
     /// - Returns `Ok` if memory is successfully read
     /// - Returns `MemoryAccessError` otherwise
@@ -228,26 +763,152 @@ impl Instance {
         src: u64,
         dest: &mut [mem::MaybeUninit<u8>],
     ) -> Result<(), MemoryAccessError> {
-        Self::bool_to_memacc(unsafe {
-            rvvm_read_ram(
+        Self::bool_to_memacc(
+            unsafe {
+                rvvm_read_ram(
+                    self.ptr.as_ptr(),
+                    dest.as_ptr() as *mut _,
+                    src,
+                    dest.len(),
+                )
+            },
+            src,
+            dest.len(),
+        )?;
+
+        // SAFETY: whole slice is initialized by the RVVM
+        #[cfg(feature = "internals")]
+        self.lift_breakpoints(src, unsafe {
+            slice::from_raw_parts_mut(dest.as_mut_ptr().cast(), dest.len())
+        });
+        Ok(())
+    }
+
+    /// Get borrowed view of the whole guest RAM without
+    /// copying. `Instance::read_ram_to` is the safe
+    /// alternative.
+    ///
+    /// Returns `MemoryAccessError::MachineRunning` if
+    /// machine is running, since guest could modify memory
+    /// behind the reference.
+    ///
+    /// # Safety
+    ///
+    /// Paused CPUs are not the only users of the RAM:
+    /// built-in RVVM devices doing DMA (block, network and
+    /// display devices) keep working on the event loop and
+    /// their own threads. Nothing may write the guest RAM
+    /// while the view is alive, so such devices must not be
+    /// attached or must be idle. Rust devices don't access
+    /// the RAM and are fine.
+    ///
+    /// View shows the guest code as is, so breakpoints set
+    /// by the `Instance::add_breakpoint` are visible as the
+    /// patched instructions. Writes over them through the
+    /// `Instance::ram_mut` are undone once the breakpoint
+    /// is removed.
+    pub unsafe fn ram(
+        &self,
+    ) -> Result<GuestMemory<'_>, MemoryAccessError> {
+        let ptr = self.dma_ptr()?;
+
+        // SAFETY: pointer is valid for `mem_size` bytes while
+        // machine exists, CPUs are stopped until `self` is
+        // borrowed and the caller guarantees that devices don't
+        // touch the RAM
+        Ok(GuestMemory::new(
+            self.mem_base,
+            slice::from_raw_parts(ptr, self.mem_size),
+        ))
+    }
+
+    /// Get mutable borrowed view of the whole guest RAM
+    /// without copying. See `Instance::ram`.
+    ///
+    /// # Safety
+    ///
+    /// Same as the `Instance::ram`, also nothing may read
+    /// the guest RAM while the view is alive
+    pub unsafe fn ram_mut(
+        &mut self,
+    ) -> Result<GuestMemoryMut<'_>, MemoryAccessError> {
+        let ptr = self.dma_ptr()?;
+
+        // SAFETY: see `Instance::ram`
+        Ok(GuestMemoryMut::new(
+            self.mem_base,
+            slice::from_raw_parts_mut(ptr, self.mem_size),
+        ))
+    }
+
+    /// Copies the whole guest RAM, machine must be paused
+    pub(crate) fn ram_copy(&self) -> Result<Vec<u8>, MemoryAccessError> {
+        self.check_not_running()?;
+
+        let mut ram = vec![0; self.mem_size];
+        self.read_ram_to(self.mem_base, &mut ram)?;
+        Ok(ram)
+    }
+
+    fn dma_ptr(&self) -> Result<*mut u8, MemoryAccessError> {
+        self.check_not_running()?;
+
+        // SAFETY: `self.ptr` is obtained from `rvvm_create_machine`
+        let ptr = unsafe {
+            rvvm_get_dma_ptr(
                 self.ptr.as_ptr(),
-                dest.as_ptr() as *mut _,
-                src,
-                dest.len(),
+                self.mem_base,
+                self.mem_size,
             )
-        })
+        };
+        if ptr.is_null() {
+            Err(MemoryAccessError::OutOfBounds {
+                start: self.mem_base,
+                end: self.mem_base + self.mem_size as u64,
+            })
+        } else {
+            Ok(ptr as *mut u8)
+        }
     }
 
-    fn bool_to_memacc(b: bool) -> Result<(), MemoryAccessError> {
+    fn bool_to_memacc(
+        b: bool,
+        addr: u64,
+        len: usize,
+    ) -> Result<(), MemoryAccessError> {
         if b {
             Ok(())
         } else {
-            Err(MemoryAccessError::OutOfBounds)
+            Err(MemoryAccessError::OutOfBounds {
+                start: addr,
+                end: addr.saturating_add(len as u64),
+            })
         }
     }
 }
 
 impl Instance {
+    /// Serializes the root FDT and loads it into RAM, same
+    /// as the `Instance::load_dtb_bytes`. Changes made to
+    /// the FDT afterwards are not visible to the guest
+    /// until the next commit.
+    ///
+    /// - Returns physical address of the DTB if it was
+    ///   loaded
+    /// - Returns `FdtCommitError` otherwise
+    pub fn commit_fdt(&mut self) -> Result<u64, FdtCommitError> {
+        let dtb = self.fdt_root().serialize(0);
+        if dtb.len() > self.mem_size {
+            return Err(FdtCommitError::TooLarge {
+                size: dtb.len(),
+                mem_size: self.mem_size,
+            });
+        }
+        self.check_not_running()?;
+
+        self.place_dtb(&dtb)
+    }
+
     /// Get mutable reference to the root FDT
     pub fn fdt_root_mut<'a>(&'a mut self) -> &'a mut Node {
         unsafe {
@@ -278,16 +939,81 @@ impl Instance {
 }
 
 impl Instance {
+    /// Sets machine option.
+    ///
+    /// - Returns `Ok` if option was applied
+    /// - Returns `SetOptError` if RVVM rejected it
+    pub fn set_opt(&mut self, opt: MachineOpt) -> Result<(), SetOptError> {
+        let (id, value) = opt.raw();
+
+        // SAFETY: `self.ptr` is obtained from `rvvm_create_machine`
+        if unsafe { rvvm_set_opt(self.ptr.as_ptr(), id, value as _) } {
+            Ok(())
+        } else {
+            Err(SetOptError::Rejected)
+        }
+    }
+
+    /// Replaces the kernel command line
+    pub fn set_cmdline(&mut self, cmdline: &CStr) {
+        // SAFETY: `self.ptr` is obtained from
+        // `rvvm_create_machine`, RVVM copies the string
+        unsafe { rvvm_set_cmdline(self.ptr.as_ptr(), cmdline.as_ptr()) }
+    }
+
+    /// Appends to the kernel command line, arguments are
+    /// separated by the space
+    pub fn append_cmdline(&mut self, cmdline: &CStr) {
+        // SAFETY: `self.ptr` is obtained from
+        // `rvvm_create_machine`, RVVM copies the string
+        unsafe { rvvm_append_cmdline(self.ptr.as_ptr(), cmdline.as_ptr()) }
+    }
+}
+
+impl Instance {
+    /// Check whether machine is powered on. Paused machine
+    /// is still powered on.
     pub fn powered_on(&self) -> bool {
         // SAFETY: `self.ptr` is obtained from `rvvm_create_machine`
         unsafe { rvvm_machine_powered_on(self.ptr.as_ptr()) }
     }
 
+    /// Check whether machine CPUs are running
+    pub fn is_running(&self) -> bool {
+        self.state() == MachineState::Running
+    }
+
+    /// Get current state of the machine
+    pub fn state(&self) -> MachineState {
+        let powered_on = self.powered_on();
+        if !powered_on {
+            // CPUs are stopped once the guest powers off
+            self.running.store(false, Ordering::Release);
+        }
+
+        MachineState::new(powered_on, self.running.load(Ordering::Acquire))
+    }
+
     /// Spawns CPU threads and continues machine execution
     pub fn start(&mut self) -> Result<(), InstanceStartError> {
+        // Harts standing at the breakpoints are stepped over
+        #[cfg(feature = "internals")]
+        let points = self
+            .monitor
+            .as_ref()
+            .map(|monitor| monitor.prepare_start(&self.view()));
+        #[cfg(feature = "internals")]
+        if points
+            .as_ref()
+            .is_some_and(|points| points.trace_paused)
+        {
+            return Err(InstanceStartError::AlreadyRunning);
+        }
+
         // SAFETY: `self.ptr` is obtained from `rvvm_create_machine`
         let result = unsafe { rvvm_start_machine(self.ptr.as_ptr()) };
         if result {
+            self.running.store(true, Ordering::Release);
             Ok(())
         } else {
             Err(InstanceStartError::AlreadyRunning)
@@ -296,13 +1022,286 @@ impl Instance {
 
     /// Stops the CPUs, the machine is frozen upon return
     pub fn pause(&mut self) -> Result<(), InstancePauseError> {
+        self.pauser().pause()
+    }
+
+    /// Requests the machine reset. RAM contents and
+    /// attached devices are preserved, devices receive
+    /// the reset callback.
+    ///
+    /// Reset is performed asynchronously by the event loop,
+    /// so machine must be running.
+    ///
+    /// - Returns `Ok` if reset is requested
+    /// - Returns `InstanceResetError` if machine is not
+    ///   running
+    pub fn reset(&mut self) -> Result<(), InstanceResetError> {
+        // Monitor pauses the machine only while holding the lock
+        #[cfg(feature = "internals")]
+        let _points = self.monitor.as_ref().map(Monitor::lock);
         // SAFETY: `self.ptr` is obtained from `rvvm_create_machine`
-        let result = unsafe { rvvm_pause_machine(self.ptr.as_ptr()) };
+        if !unsafe { rvvm_machine_running(self.ptr.as_ptr()) } {
+            return Err(InstanceResetError::NotRunning);
+        }
 
-        if result {
-            Ok(())
+        // SAFETY: `self.ptr` is obtained from `rvvm_create_machine`
+        unsafe { rvvm_reset_machine(self.ptr.as_ptr(), true) };
+        Ok(())
+    }
+
+    /// Powers off the machine, same as the guest power off.
+    ///
+    /// CPUs are stopped upon return and machine is in the
+    /// `MachineState::PoweredOff` state.
+    pub fn power_off(&mut self) {
+        // Not running machine is fine, it is powered off anyway
+        let _ = self.pause();
+
+        // SAFETY: `self.ptr` is obtained from `rvvm_create_machine`
+        unsafe { rvvm_reset_machine(self.ptr.as_ptr(), false) }
+    }
+}
+
+/// Parts of the `Instance` that pause the machine, shared
+/// with the reset watcher of the `Instance::run`
+struct Pauser<'a> {
+    ptr: NonNull<rvvm_machine_t>,
+    running: &'a AtomicBool,
+    #[cfg(feature = "internals")]
+    monitor_paused: &'a AtomicBool,
+    #[cfg(feature = "internals")]
+    monitor: Option<&'a Monitor>,
+}
+
+// SAFETY: RVVM machine can be paused from any thread, other
+// fields are `Sync`
+unsafe impl Send for Pauser<'_> {}
+
+impl Pauser<'_> {
+    fn powered_on(&self) -> bool {
+        // SAFETY: `self.ptr` is obtained from `rvvm_create_machine`
+        unsafe { rvvm_machine_powered_on(self.ptr.as_ptr()) }
+    }
+
+    fn pause(&self) -> Result<(), InstancePauseError> {
+        // Keep the monitor from starting the machine again
+        #[cfg(feature = "internals")]
+        let mut points = self.monitor.map(Monitor::lock);
+        // Machine is already paused between the trace batches
+        #[cfg(feature = "internals")]
+        if let Some(points) = points.as_mut().filter(|p| p.trace_paused) {
+            points.trace_paused = false;
+            self.running.store(false, Ordering::Release);
+            self.monitor_paused
+                .store(false, Ordering::Release);
+            return Ok(());
+        }
+
+        // SAFETY: `self.ptr` is obtained from `rvvm_create_machine`
+        if unsafe { rvvm_pause_machine(self.ptr.as_ptr()) } {
+            self.running.store(false, Ordering::Release);
+            return Ok(());
+        }
+
+        // Machine could be stopped behind our back, e.g.
+        // powered off by the guest
+        // SAFETY: `self.ptr` is obtained from `rvvm_create_machine`
+        if !unsafe { rvvm_machine_running(self.ptr.as_ptr()) } {
+            self.running.store(false, Ordering::Release);
+        }
+        Err(InstancePauseError::NotRunning)
+    }
+}
+
+/// Runs the RVVM event loop, which exits once no machine is
+/// running. That includes the moments the monitor pauses
+/// the machine to check the breakpoints, so the loop is
+/// entered again once the monitor is done.
+fn run_eventloop(monitor_paused: &AtomicBool) {
+    loop {
+        // SAFETY: event loop takes no arguments and only
+        // touches the machines that are running
+        unsafe { rvvm_run_eventloop() };
+
+        if !monitor_paused.load(Ordering::Acquire) {
+            break;
+        }
+        while monitor_paused.load(Ordering::Acquire) {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+/// Upper bound of the MMIO handles scanned by the
+/// `Instance::builtin_devices`. RVVM hands out handles as
+/// indices of its device list, and returns null past its
+/// end.
+const MMIO_HANDLE_LIMIT: i32 = 1024;
+
+/// Type of the zero-sized device that observes the machine
+/// resets, see `Instance::loop_events`
+const RESET_WATCHER: &rvvm_mmio_type_t = &rvvm_mmio_type_t {
+    remove: Some(reset_watcher_remove),
+    update: None,
+    reset: Some(reset_watcher_reset),
+    name: c_str!("rust_reset_watcher").as_ptr(),
+};
+
+// SAFETY: callbacks below are called by RVVM with the
+// device created by the `Instance::loop_events`
+
+unsafe extern "C" fn reset_watcher_reset(dev: *mut rvvm_mmio_dev_t) {
+    (*((*dev).data as *const LoopEvents)).notify_reset();
+}
+
+unsafe extern "C" fn reset_watcher_remove(dev: *mut rvvm_mmio_dev_t) {
+    drop(Arc::from_raw((*dev).data as *const LoopEvents));
+}
+
+impl Instance {
+    /// Starts the machine and runs the RVVM event loop on
+    /// the current thread until the machine is paused,
+    /// powered off or reset. Short pauses made to check the
+    /// breakpoints don't count.
+    ///
+    /// Event loop is shared by all machines, so it returns
+    /// only when there is no running machine left. Use the
+    /// `Instance::spawn` to run it in the background.
+    ///
+    /// - Returns `ExitReason` if machine was started
+    /// - Returns `InstanceStartError` otherwise
+    pub fn run(&mut self) -> Result<ExitReason, InstanceStartError> {
+        let events = self.loop_events()?;
+        events.begin();
+        self.start()?;
+
+        let pauser = self.pauser();
+        let events = &*events;
+        let monitor_paused = &*self.monitor_paused;
+        let reset = thread::scope(|scope| {
+            // Reset doesn't end the event loop, so the machine is
+            // paused from the side once it's done
+            let watcher = thread::Builder::new()
+                .name("rvvm-reset-watcher".to_owned())
+                .spawn_scoped(scope, move || {
+                    let reset = events.wait();
+                    if reset {
+                        // RVVM restarts the CPUs once the reset is
+                        // done, so retry until there is something
+                        // to pause
+                        while pauser.pause().is_err()
+                            && pauser.powered_on()
+                            && !events.exited()
+                        {
+                            thread::sleep(Duration::from_millis(1));
+                        }
+                    }
+
+                    reset
+                })
+                .expect("Failed to spawn reset watcher thread");
+
+            run_eventloop(monitor_paused);
+            events.notify_exit();
+
+            watcher
+                .join()
+                .expect("Reset watcher thread panicked")
+        });
+
+        Ok(if reset {
+            ExitReason::Reset
+        } else {
+            self.exit_reason()
+        })
+    }
+
+    /// Starts the machine and runs the RVVM event loop on
+    /// the background thread.
+    ///
+    /// Same as the `Instance::run`, but returns
+    /// immediately. Exit reason is obtained through the
+    /// `EventLoopHandle::join`. Dropping the handle doesn't
+    /// block: the event loop thread is detached and the
+    /// machine keeps running until it is paused or powered
+    /// off.
+    ///
+    /// - Returns `EventLoopHandle` if machine was started
+    /// - Returns `InstanceStartError` otherwise
+    pub fn spawn(
+        &mut self,
+    ) -> Result<EventLoopHandle<'_>, InstanceStartError> {
+        let events = self.loop_events()?;
+        events.begin();
+        self.start()?;
+
+        let thread_events = Arc::clone(&events);
+        let monitor_paused = Arc::clone(&self.monitor_paused);
+        let thread = thread::Builder::new()
+            .name("rvvm-eventloop".to_owned())
+            .spawn(move || {
+                run_eventloop(&monitor_paused);
+                thread_events.notify_exit();
+            })
+            .expect("Failed to spawn event loop thread");
+
+        Ok(EventLoopHandle::new(self, thread, events))
+    }
+
+    /// Get the events of the event loop, attaching the
+    /// reset watcher on the first call
+    fn loop_events(
+        &mut self,
+    ) -> Result<Arc<LoopEvents>, InstanceStartError> {
+        if let Some(events) = &self.events {
+            return Ok(Arc::clone(events));
+        }
+
+        let events = Arc::new(LoopEvents::default());
+        let watcher = rvvm_mmio_dev_t {
+            addr: 0,
+            size: 0,
+            min_op_size: 1,
+            max_op_size: 1,
+            read: None,
+            write: None,
+            data: Arc::into_raw(Arc::clone(&events)) as *mut c_void,
+            machine: ptr::null_mut(),
+            type_: RESET_WATCHER as *const rvvm_mmio_type_t as *mut _,
+        };
+
+        // SAFETY: `self.ptr` is obtained from
+        // `rvvm_create_machine`. Zero-sized device never
+        // receives accesses, its `Arc` is released by the
+        // remove handler, also when attaching fails. It is not
+        // tracked in the `self.devices`, so users can't detach
+        // it
+        let handle =
+            unsafe { rvvm_attach_mmio(self.ptr.as_ptr(), &watcher) };
+        if handle < 0 {
+            return Err(InstanceStartError::ResetWatcherRejected);
+        }
+
+        self.events = Some(Arc::clone(&events));
+        Ok(events)
+    }
+
+    fn pauser(&self) -> Pauser<'_> {
+        Pauser {
+            ptr: self.ptr,
+            running: &self.running,
+            #[cfg(feature = "internals")]
+            monitor_paused: &self.monitor_paused,
+            #[cfg(feature = "internals")]
+            monitor: self.monitor.as_ref(),
+        }
+    }
+
+    pub(crate) fn exit_reason(&self) -> ExitReason {
+        if self.powered_on() {
+            ExitReason::Paused
         } else {
-            Err(InstancePauseError::NotRunning)
+            ExitReason::PoweredOff
         }
     }
 }
@@ -310,6 +1309,49 @@ impl Instance {
 impl Instance {
     pub const DEFAULT_MEMBASE: u64 = RVVM_DEFAULT_MEMBASE as _;
 
+    /// Get number of harts
+    pub const fn harts(&self) -> usize {
+        self.harts
+    }
+
+    /// Get physical address of the RAM beginning
+    pub const fn mem_base(&self) -> u64 {
+        self.mem_base
+    }
+
+    /// Get RAM size in bytes
+    pub const fn mem_size(&self) -> usize {
+        self.mem_size
+    }
+
+    /// Check whether machine is 64-bit
+    pub const fn is_rv64(&self) -> bool {
+        self.rv64
+    }
+
+    /// Get the monitor, spawning it on the first call
+    #[cfg(feature = "internals")]
+    pub(crate) fn monitor(&mut self) -> &Monitor {
+        let view = self.view();
+        let running = Arc::clone(&self.running);
+        let paused = Arc::clone(&self.monitor_paused);
+
+        self.monitor
+            .get_or_insert_with(|| Monitor::spawn(view, running, paused))
+    }
+
+    /// Get the monitor if it's already spawned
+    #[cfg(feature = "internals")]
+    pub(crate) fn spawned_monitor(&self) -> Option<&Monitor> {
+        self.monitor.as_ref()
+    }
+
+    /// Get raw pointer to the underlying machine
+    #[cfg_attr(not(feature = "internals"), allow(dead_code))]
+    pub(crate) const fn as_ptr(&self) -> *mut rvvm_machine_t {
+        self.ptr.as_ptr()
+    }
+
     /// Creates the `InstanceBuilder` for the builder
     /// pattern.
     pub fn builder() -> InstanceBuilder {
@@ -328,11 +1370,31 @@ impl Instance {
 
         rv64: bool,
     ) -> Result<Self, InstanceCreateError> {
-        NonNull::new(unsafe {
+        let instance = NonNull::new(unsafe {
             rvvm_create_machine(mem_base, mem_size, harts, rv64)
         })
-        .map(|ptr| Self { ptr })
-        .ok_or(InstanceCreateError::FailedToAllocate)
+        .map(|ptr| Self {
+            ptr,
+            devices: HashMap::new(),
+            running: Arc::new(AtomicBool::new(false)),
+            monitor_paused: Arc::new(AtomicBool::new(false)),
+            events: None,
+            #[cfg(feature = "internals")]
+            monitor: None,
+
+            harts,
+            mem_base,
+            mem_size,
+            rv64,
+        })
+        .ok_or(InstanceCreateError::FailedToAllocate)?;
+
+        #[cfg(feature = "internals")]
+        if !instance.layout_matches() {
+            return Err(InstanceCreateError::LayoutMismatch);
+        }
+
+        Ok(instance)
     }
 
     /// Creates virtual machine instance.
@@ -357,6 +1419,11 @@ impl Instance {
 
 impl Drop for Instance {
     fn drop(&mut self) {
+        // Monitor thread must be joined before the machine is
+        // freed
+        #[cfg(feature = "internals")]
+        drop(self.monitor.take());
+
         // SAFETY: `self.ptr` is allocated through the
         // `rvvm_create_machine`
         unsafe { rvvm_free_machine(self.ptr.as_ptr()) }
@@ -1,4 +1,10 @@
-use std::ffi::c_void;
+use std::{
+    ffi::{
+        c_void,
+        CString,
+    },
+    path::Path,
+};
 
 pub unsafe fn allocate_boxed_voidptr<T>(value: T) -> *mut c_void {
     let data = Box::new(value);
@@ -11,3 +17,30 @@ pub unsafe fn free_boxed_voidptr<T>(boxed: *mut c_void) {
     let _ = Box::from_raw(boxed as *mut T); // deallocate
                                             // the memory
 }
+
+pub unsafe fn take_boxed_voidptr<T>(boxed: *mut c_void) -> T {
+    *Box::from_raw(boxed as *mut T)
+}
+
+/// Converts path into the C string without the utf8
+/// validation on unix.
+///
+/// # Panics
+///
+/// Panics if path contains nul-byte character or if path is
+/// not a valid utf8 sequence on non-unix platforms
+pub fn path_to_cstring(path: &Path) -> CString {
+    #[cfg(unix)]
+    let bytes = {
+        use std::os::unix::ffi::OsStrExt;
+        path.as_os_str().as_bytes()
+    };
+
+    #[cfg(not(unix))]
+    let bytes = path
+        .to_str()
+        .expect("path is not a valid utf8 sequence")
+        .as_bytes();
+
+    CString::new(bytes).expect("Path contains nul-byte character")
+}
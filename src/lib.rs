@@ -19,9 +19,78 @@ pub mod fdt;
 ///
 /// - Do read/writes to the RAM
 /// - Start/stop/pause VM execution
+/// - Run the event loop
 /// - Load dtb/kernel/bootrom
 pub mod instance;
 
+/// # Guest memory
+///
+/// Borrowed views of the guest physical memory, refer to
+/// the `Instance::ram` and `Instance::ram_mut`.
+pub mod memory;
+
+/// # Virtual memory
+///
+/// Software page table walker for the Sv32/Sv39/Sv48/Sv57,
+/// refer to the `Instance::translate` and
+/// `Instance::read_virt`.
+pub mod mmu;
+
+/// # Harts
+///
+/// Access to the registers and CSRs of the paused machine,
+/// refer to the `Instance::hart`. Relies on the layout of
+/// the RVVM internals, so is available only with the
+/// `internals` feature.
+#[cfg(feature = "internals")]
+pub mod hart;
+
+/// # Snapshots
+///
+/// Owned copies of the guest RAM and their on-disk format,
+/// refer to the `Instance::snapshot_ram`. Whole machine
+/// state is saved with the `Instance::save_state`.
+pub mod snapshot;
+
+/// # ELF images
+///
+/// RISC-V ELF loader and symbol table, refer to the
+/// `Instance::load_elf_bytes`.
+pub mod elf;
+
+/// # Debugging
+///
+/// Breakpoints and watchpoints set from the host, refer to
+/// the `Instance::add_breakpoint` and
+/// `Instance::stop_events`. Requires the `internals`
+/// feature.
+#[cfg(feature = "internals")]
+pub mod debug;
+
+/// # GDB remote debugging
+///
+/// GDB remote serial protocol server over the TCP or Unix
+/// sockets, refer to the `GdbStub`. Breakpoints are
+/// implemented by patching the guest code, so machine must
+/// be built with the JIT disabled. Requires the
+/// `internals` feature.
+#[cfg(feature = "internals")]
+pub mod gdbstub;
+
+/// # Tracing
+///
+/// Opt-in instruction trace mode, refer to the
+/// `InstanceBuilder::trace` and `Instance::set_trace`.
+/// Requires the `internals` feature.
+#[cfg(feature = "internals")]
+pub mod trace;
+
+/// # Disassembler
+///
+/// RISC-V instruction pretty-printer, refer to the
+/// `Disassembler`.
+pub mod disasm;
+
 /// # Device
 ///
 /// Anything that is related to the mmio devices. Refer to
@@ -49,11 +118,16 @@ pub mod types;
 pub mod prelude;
 
 mod declmacro;
+mod insn;
 mod internal_utils;
 
 #[cfg(test)]
 mod tests;
 
+// Lets the `#[device]` and friends refer to the crate as
+// `::rvvm` from the inside too
+extern crate self as rvvm;
+
 #[doc(hidden)]
 pub use paste as __paste;
 pub use rvvm_macro as macros;
@@ -0,0 +1,556 @@
+use std::{
+    ffi::CString,
+    io::{
+        self,
+        Read,
+        Seek,
+        SeekFrom,
+        Write,
+    },
+    mem::{
+        self,
+        MaybeUninit,
+    },
+    ops::{
+        Deref,
+        DerefMut,
+        Range,
+    },
+    slice,
+};
+
+use crate::{
+    error::{
+        MemoryAccessError,
+        StringReadError,
+    },
+    instance::Instance,
+};
+
+/// Plain old data that can be copied from/to the guest
+/// memory as raw bytes.
+///
+/// Guest memory is always little-endian, conversions
+/// between the guest and host byte order are done through
+/// the `Pod::from_le` and `Pod::to_le`.
+///
+/// # Safety
+///
+/// Implementor must have no padding bytes and any bit
+/// pattern must be a valid value of the type.
+pub unsafe trait Pod: Copy + 'static {
+    /// Converts value from the little-endian byte order to
+    /// the host one
+    fn from_le(value: Self) -> Self;
+
+    /// Converts value from the host byte order to the
+    /// little-endian one
+    fn to_le(value: Self) -> Self;
+}
+
+macro_rules! impl_pod_int {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            unsafe impl Pod for $ty {
+                fn from_le(value: Self) -> Self {
+                    <$ty>::from_le(value)
+                }
+
+                fn to_le(value: Self) -> Self {
+                    <$ty>::to_le(value)
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_pod_float {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            unsafe impl Pod for $ty {
+                fn from_le(value: Self) -> Self {
+                    <$ty>::from_bits(Pod::from_le(value.to_bits()))
+                }
+
+                fn to_le(value: Self) -> Self {
+                    <$ty>::from_bits(Pod::to_le(value.to_bits()))
+                }
+            }
+        )*
+    };
+}
+
+impl_pod_int!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+impl_pod_float!(f32, f64);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {
+    fn from_le(value: Self) -> Self {
+        value.map(T::from_le)
+    }
+
+    fn to_le(value: Self) -> Self {
+        value.map(T::to_le)
+    }
+}
+
+macro_rules! typed_accessors {
+    ($($ty:ident),* $(,)?) => {
+        $crate::__paste::paste! {
+            $(
+                #[doc = "Reads little-endian `" $ty "` from the guest memory"]
+                pub fn [< read_ $ty >](
+                    &self,
+                    addr: u64,
+                ) -> Result<$ty, MemoryAccessError> {
+                    self.read_obj(addr)
+                }
+
+                #[doc = "Reads big-endian `" $ty "` from the guest memory"]
+                pub fn [< read_ $ty _be >](
+                    &self,
+                    addr: u64,
+                ) -> Result<$ty, MemoryAccessError> {
+                    self.[< read_ $ty >](addr).map(<$ty>::swap_bytes)
+                }
+
+                #[doc = "Writes little-endian `" $ty "` to the guest memory"]
+                pub fn [< write_ $ty >](
+                    &mut self,
+                    addr: u64,
+                    value: $ty,
+                ) -> Result<(), MemoryAccessError> {
+                    self.write_obj(addr, value)
+                }
+
+                #[doc = "Writes big-endian `" $ty "` to the guest memory"]
+                pub fn [< write_ $ty _be >](
+                    &mut self,
+                    addr: u64,
+                    value: $ty,
+                ) -> Result<(), MemoryAccessError> {
+                    self.[< write_ $ty >](addr, value.swap_bytes())
+                }
+            )*
+        }
+    };
+}
+
+impl Instance {
+    typed_accessors!(u16, u32, u64);
+
+    /// Reads `u8` from the guest memory
+    pub fn read_u8(&self, addr: u64) -> Result<u8, MemoryAccessError> {
+        self.read_obj(addr)
+    }
+
+    /// Writes `u8` to the guest memory
+    pub fn write_u8(
+        &mut self,
+        addr: u64,
+        value: u8,
+    ) -> Result<(), MemoryAccessError> {
+        self.write_obj(addr, value)
+    }
+
+    /// Reads `T` stored in the little-endian byte order
+    /// from the guest memory. Address is not required
+    /// to be aligned.
+    pub fn read_obj<T: Pod>(
+        &self,
+        addr: u64,
+    ) -> Result<T, MemoryAccessError> {
+        let mut value = MaybeUninit::<T>::uninit();
+
+        // SAFETY: `MaybeUninit<T>` has same size as the `T` and
+        // bytes are allowed to be uninitialized
+        let bytes = unsafe {
+            slice::from_raw_parts_mut(
+                value.as_mut_ptr() as *mut MaybeUninit<u8>,
+                mem::size_of::<T>(),
+            )
+        };
+        self.read_ram_to_uninit(addr, bytes)?;
+
+        // SAFETY: all bytes are initialized and any bit pattern is
+        // valid for the `Pod`
+        Ok(T::from_le(unsafe { value.assume_init() }))
+    }
+
+    /// Writes `T` to the guest memory in the little-endian
+    /// byte order. Address is not required to be aligned.
+    pub fn write_obj<T: Pod>(
+        &mut self,
+        addr: u64,
+        value: T,
+    ) -> Result<(), MemoryAccessError> {
+        let value = T::to_le(value);
+
+        // SAFETY: `Pod` has no padding, so every byte is
+        // initialized
+        let bytes = unsafe {
+            slice::from_raw_parts(
+                &value as *const T as *const u8,
+                mem::size_of::<T>(),
+            )
+        };
+        self.write_ram(addr, bytes)
+    }
+
+    /// Reads nul-terminated string from the guest memory.
+    /// At most `max_len` bytes (including the terminator)
+    /// are read.
+    pub fn read_cstr(
+        &self,
+        addr: u64,
+        max_len: usize,
+    ) -> Result<CString, StringReadError> {
+        const CHUNK_SIZE: usize = 256;
+
+        let ram_end = self.mem_base() + self.mem_size() as u64;
+        let mut chunk = [0; CHUNK_SIZE];
+        let mut string = Vec::new();
+
+        while string.len() < max_len {
+            let at = addr + string.len() as u64;
+            let len = CHUNK_SIZE
+                .min(max_len - string.len())
+                .min(ram_end.saturating_sub(at) as usize)
+                .max(1);
+            self.read_ram_to(at, &mut chunk[..len])?;
+
+            match chunk[..len].iter().position(|&b| b == 0) {
+                Some(nul) => {
+                    string.extend_from_slice(&chunk[..nul]);
+
+                    // SAFETY: string contains no nul-bytes, since we
+                    // stopped at the first one
+                    return Ok(unsafe {
+                        CString::from_vec_unchecked(string)
+                    });
+                }
+                None => string.extend_from_slice(&chunk[..len]),
+            }
+        }
+
+        Err(StringReadError::Unterminated { max_len })
+    }
+
+    /// Reads UTF-8 string of `len` bytes from the guest
+    /// memory
+    pub fn read_str(
+        &self,
+        addr: u64,
+        len: usize,
+    ) -> Result<String, StringReadError> {
+        let mut bytes = vec![0; len];
+        self.read_ram_to(addr, &mut bytes)?;
+
+        Ok(String::from_utf8(bytes)?)
+    }
+}
+
+/// Borrowed view of the guest physical memory.
+///
+/// Obtained through the `Instance::ram`, machine can't be
+/// started while the view is alive.
+#[derive(Debug)]
+pub struct GuestMemory<'a> {
+    base: u64,
+    data: &'a [u8],
+}
+
+/// Mutable borrowed view of the guest physical memory.
+///
+/// Obtained through the `Instance::ram_mut`, machine can't
+/// be started while the view is alive.
+#[derive(Debug)]
+pub struct GuestMemoryMut<'a> {
+    base: u64,
+    data: &'a mut [u8],
+}
+
+impl<'a> GuestMemory<'a> {
+    pub(crate) fn new(base: u64, data: &'a [u8]) -> Self {
+        Self { base, data }
+    }
+
+    /// Get physical address of the RAM beginning
+    pub const fn base(&self) -> u64 {
+        self.base
+    }
+
+    /// Get memory as a slice, index `0` corresponds to the
+    /// `GuestMemory::base` physical address
+    pub const fn as_slice(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Get slice of memory by the physical address range.
+    ///
+    /// Returns `MemoryAccessError` if range is not within
+    /// the RAM.
+    pub fn slice(
+        &self,
+        range: Range<u64>,
+    ) -> Result<&'a [u8], MemoryAccessError> {
+        let range = offset_range(self.base, self.data.len(), range)?;
+        Ok(&self.data[range])
+    }
+
+    /// Get reference to the `T` at the physical address
+    /// without copying. Value is in the guest
+    /// (little-endian) byte order, see `Pod::from_le`.
+    ///
+    /// - Returns reference to the value if it is within the
+    ///   RAM and `addr` is aligned for the `T`
+    /// - Returns `MemoryAccessError` otherwise
+    pub fn get<T: Pod>(
+        &self,
+        addr: u64,
+    ) -> Result<&'a T, MemoryAccessError> {
+        let offset = obj_offset::<T>(self.base, self.data, addr)?;
+
+        // SAFETY: object is within the slice and aligned, any bit
+        // pattern is valid for the `Pod`
+        Ok(unsafe { &*(self.data.as_ptr().add(offset) as *const T) })
+    }
+}
+
+impl<'a> GuestMemoryMut<'a> {
+    pub(crate) fn new(base: u64, data: &'a mut [u8]) -> Self {
+        Self { base, data }
+    }
+
+    /// Get physical address of the RAM beginning
+    pub const fn base(&self) -> u64 {
+        self.base
+    }
+
+    /// Get memory as a slice, see `GuestMemory::as_slice`
+    pub fn as_slice(&self) -> &[u8] {
+        self.data
+    }
+
+    /// Get memory as a mutable slice, see
+    /// `GuestMemory::as_slice`
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        self.data
+    }
+
+    /// Get slice of memory by the physical address range,
+    /// see `GuestMemory::slice`
+    pub fn slice(
+        &self,
+        range: Range<u64>,
+    ) -> Result<&[u8], MemoryAccessError> {
+        let range = offset_range(self.base, self.data.len(), range)?;
+        Ok(&self.data[range])
+    }
+
+    /// Get mutable slice of memory by the physical address
+    /// range, see `GuestMemory::slice`
+    pub fn slice_mut(
+        &mut self,
+        range: Range<u64>,
+    ) -> Result<&mut [u8], MemoryAccessError> {
+        let range = offset_range(self.base, self.data.len(), range)?;
+        Ok(&mut self.data[range])
+    }
+
+    /// Get reference to the `T` at the physical address,
+    /// see `GuestMemory::get`
+    pub fn get<T: Pod>(&self, addr: u64) -> Result<&T, MemoryAccessError> {
+        GuestMemory::new(self.base, self.data).get(addr)
+    }
+
+    /// Get mutable reference to the `T` at the physical
+    /// address, see `GuestMemory::get`
+    pub fn get_mut<T: Pod>(
+        &mut self,
+        addr: u64,
+    ) -> Result<&mut T, MemoryAccessError> {
+        let offset = obj_offset::<T>(self.base, self.data, addr)?;
+
+        // SAFETY: see `GuestMemory::get`, every value of the `Pod`
+        // is valid to be written
+        Ok(
+            unsafe {
+                &mut *(self.data.as_mut_ptr().add(offset) as *mut T)
+            },
+        )
+    }
+}
+
+impl Deref for GuestMemory<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        self.data
+    }
+}
+
+impl Deref for GuestMemoryMut<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        self.data
+    }
+}
+
+impl DerefMut for GuestMemoryMut<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.data
+    }
+}
+
+/// `Read + Write + Seek` cursor over the guest RAM.
+///
+/// Position `0` corresponds to the `Instance::mem_base`,
+/// data is copied through the
+/// `Instance::read_ram_to_uninit`
+/// and `Instance::write_ram`, so machine may be running.
+pub struct GuestMemoryCursor<'a> {
+    instance: &'a mut Instance,
+    pos: u64,
+}
+
+impl<'a> GuestMemoryCursor<'a> {
+    /// Creates cursor positioned at the RAM beginning
+    pub fn new(instance: &'a mut Instance) -> Self {
+        Self { instance, pos: 0 }
+    }
+
+    /// Get current offset from the RAM beginning
+    pub const fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Set offset from the RAM beginning, may point past
+    /// the end of RAM
+    pub fn set_position(&mut self, pos: u64) {
+        self.pos = pos;
+    }
+
+    /// Get physical address the cursor points to
+    pub fn phys_addr(&self) -> u64 {
+        self.instance.mem_base().wrapping_add(self.pos)
+    }
+
+    /// Unwraps the borrowed instance
+    pub fn into_inner(self) -> &'a mut Instance {
+        self.instance
+    }
+
+    fn remaining(&self) -> usize {
+        (self.instance.mem_size() as u64).saturating_sub(self.pos) as usize
+    }
+}
+
+impl Read for GuestMemoryCursor<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.remaining());
+        if len == 0 {
+            return Ok(0);
+        }
+
+        self.instance
+            .read_ram_to(self.phys_addr(), &mut buf[..len])
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl Write for GuestMemoryCursor<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.remaining());
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let addr = self.phys_addr();
+        self.instance
+            .write_ram(addr, &buf[..len])
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+        self.pos += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for GuestMemoryCursor<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(pos) => {
+                self.pos = pos;
+                return Ok(pos);
+            }
+            SeekFrom::End(offset) => {
+                (self.instance.mem_size() as u64, offset)
+            }
+            SeekFrom::Current(offset) => (self.pos, offset),
+        };
+
+        match base.checked_add_signed(offset) {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+impl Instance {
+    /// Get `Read + Write + Seek` cursor over the guest RAM,
+    /// see `GuestMemoryCursor`
+    pub fn ram_cursor(&mut self) -> GuestMemoryCursor<'_> {
+        GuestMemoryCursor::new(self)
+    }
+}
+
+/// Offset of the `T` at the physical `addr` within the
+/// `data` that starts at the `base`
+fn obj_offset<T>(
+    base: u64,
+    data: &[u8],
+    addr: u64,
+) -> Result<usize, MemoryAccessError> {
+    let end = addr.saturating_add(mem::size_of::<T>() as u64);
+    let offset = offset_range(base, data.len(), addr..end)?.start;
+
+    // Host alignment is checked, since the reference points to
+    // the host memory
+    let align = mem::align_of::<T>();
+    if (data.as_ptr() as usize + offset) % align != 0 {
+        Err(MemoryAccessError::Misaligned { addr, align })
+    } else {
+        Ok(offset)
+    }
+}
+
+/// Converts physical address range into the range of
+/// offsets from the `base`
+fn offset_range(
+    base: u64,
+    size: usize,
+    range: Range<u64>,
+) -> Result<Range<usize>, MemoryAccessError> {
+    let end = base + size as u64;
+    if range.start < base || range.end > end || range.start > range.end {
+        Err(MemoryAccessError::OutOfBounds {
+            start: range.start,
+            end: range.end,
+        })
+    } else {
+        Ok((range.start - base) as usize..(range.end - base) as usize)
+    }
+}
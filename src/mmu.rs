@@ -0,0 +1,361 @@
+use crate::{
+    error::{
+        MemoryAccessError,
+        PageFault,
+    },
+    instance::Instance,
+};
+
+const PAGE_SHIFT: u32 = 12;
+const PAGE_SIZE: u64 = 1 << PAGE_SHIFT;
+
+/// Address translation scheme selected by the `satp.MODE`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PagingMode {
+    /// No translation, virtual address is the physical one
+    Bare,
+
+    Sv32,
+    Sv39,
+    Sv48,
+    Sv57,
+}
+
+impl PagingMode {
+    /// Get amount of the page table levels
+    pub const fn levels(self) -> u32 {
+        match self {
+            Self::Bare => 0,
+            Self::Sv32 => 2,
+            Self::Sv39 => 3,
+            Self::Sv48 => 4,
+            Self::Sv57 => 5,
+        }
+    }
+
+    /// Get amount of significant virtual address bits
+    pub const fn va_bits(self) -> u32 {
+        match self {
+            Self::Bare => 64,
+            Self::Sv32 => 32,
+            _ => PAGE_SHIFT + self.levels() * self.vpn_bits(),
+        }
+    }
+
+    const fn vpn_bits(self) -> u32 {
+        match self {
+            Self::Sv32 => 10,
+            _ => 9,
+        }
+    }
+
+    const fn pte_size(self) -> u64 {
+        match self {
+            Self::Sv32 => 4,
+            _ => 8,
+        }
+    }
+
+    const fn ppn_mask(self) -> u64 {
+        match self {
+            Self::Sv32 => (1 << 22) - 1,
+            _ => (1 << 44) - 1,
+        }
+    }
+}
+
+/// Decoded `satp` CSR
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Satp {
+    pub mode: PagingMode,
+    pub asid: u16,
+    /// Physical page number of the root page table
+    pub ppn: u64,
+}
+
+impl Satp {
+    /// Decodes raw `satp` value, layout depends on the
+    /// `rv64`.
+    ///
+    /// - Returns `Ok` if translation mode is known
+    /// - Returns `PageFault::UnsupportedMode` otherwise
+    pub const fn decode(satp: u64, rv64: bool) -> Result<Self, PageFault> {
+        if rv64 {
+            let mode = match satp >> 60 {
+                0 => PagingMode::Bare,
+                8 => PagingMode::Sv39,
+                9 => PagingMode::Sv48,
+                10 => PagingMode::Sv57,
+                mode => {
+                    return Err(PageFault::UnsupportedMode(mode as u8))
+                }
+            };
+
+            Ok(Self {
+                mode,
+                asid: ((satp >> 44) & 0xffff) as u16,
+                ppn: satp & ((1 << 44) - 1),
+            })
+        } else {
+            let satp = satp as u32;
+            Ok(Self {
+                mode: if satp >> 31 == 0 {
+                    PagingMode::Bare
+                } else {
+                    PagingMode::Sv32
+                },
+                asid: ((satp >> 22) & 0x1ff) as u16,
+                ppn: (satp & ((1 << 22) - 1)) as u64,
+            })
+        }
+    }
+
+    /// Get physical address of the root page table
+    pub const fn root(&self) -> u64 {
+        self.ppn << PAGE_SHIFT
+    }
+}
+
+/// Low 8 bits of the page table entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PteFlags(u8);
+
+impl PteFlags {
+    pub const ACCESSED: Self = Self(1 << 6);
+    pub const DIRTY: Self = Self(1 << 7);
+    pub const EXEC: Self = Self(1 << 3);
+    pub const GLOBAL: Self = Self(1 << 5);
+    pub const READ: Self = Self(1 << 1);
+    pub const USER: Self = Self(1 << 4);
+    pub const VALID: Self = Self(1 << 0);
+    pub const WRITE: Self = Self(1 << 2);
+
+    pub const fn from_bits(bits: u8) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Check whether all bits of the `other` are set
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// Kind of the memory access being translated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+impl Access {
+    const fn required(self) -> PteFlags {
+        match self {
+            Self::Read => PteFlags::READ,
+            Self::Write => PteFlags::WRITE,
+            Self::Execute => PteFlags::EXEC,
+        }
+    }
+}
+
+/// Result of the successful address translation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Translation {
+    pub phys_addr: u64,
+    /// Flags of the leaf entry, all permissions are granted
+    /// in the `PagingMode::Bare`
+    pub flags: PteFlags,
+    /// Size of the (super)page containing the address
+    pub page_size: u64,
+}
+
+/// Walks the page table as the hart would do, reading
+/// page table entries through the `read_pte(address,
+/// size)`.
+///
+/// Privilege level, `SUM`/`MXR` and `A`/`D` bits are not
+/// taken into account, only the `R`/`W`/`X` permissions
+/// of the leaf entry are checked against the `access`.
+pub fn translate_with(
+    satp: Satp,
+    vaddr: u64,
+    access: Access,
+    mut read_pte: impl FnMut(u64, u64) -> Result<u64, MemoryAccessError>,
+) -> Result<Translation, PageFault> {
+    let mode = satp.mode;
+    if mode == PagingMode::Bare {
+        return Ok(Translation {
+            phys_addr: vaddr,
+            flags: PteFlags::VALID
+                .union(PteFlags::READ)
+                .union(PteFlags::WRITE)
+                .union(PteFlags::EXEC),
+            page_size: PAGE_SIZE,
+        });
+    }
+
+    let va_bits = mode.va_bits();
+    let canonical = if mode == PagingMode::Sv32 {
+        vaddr >> 32 == 0
+    } else {
+        let upper = (vaddr as i64) >> (va_bits - 1);
+        upper == 0 || upper == -1
+    };
+    if !canonical {
+        return Err(PageFault::NonCanonical { vaddr });
+    }
+
+    let vpn_bits = mode.vpn_bits();
+    let mut table = satp.root();
+
+    for level in (0..mode.levels()).rev() {
+        let shift = PAGE_SHIFT + level * vpn_bits;
+        let vpn = (vaddr >> shift) & ((1 << vpn_bits) - 1);
+        let pte_addr = table + vpn * mode.pte_size();
+
+        let pte = read_pte(pte_addr, mode.pte_size())?;
+        let flags = PteFlags(pte as u8);
+
+        if !flags.contains(PteFlags::VALID) {
+            return Err(PageFault::NotPresent {
+                vaddr,
+                pte_addr,
+                level,
+            });
+        }
+        let reserved = mode != PagingMode::Sv32 && (pte >> 54) & 0x7f != 0;
+        if reserved
+            || (flags.contains(PteFlags::WRITE)
+                && !flags.contains(PteFlags::READ))
+        {
+            return Err(PageFault::InvalidPte {
+                vaddr,
+                pte_addr,
+                level,
+            });
+        }
+
+        let ppn = (pte >> 10) & mode.ppn_mask();
+        if !flags.contains(PteFlags::READ)
+            && !flags.contains(PteFlags::EXEC)
+        {
+            if level == 0 {
+                return Err(PageFault::InvalidPte {
+                    vaddr,
+                    pte_addr,
+                    level,
+                });
+            }
+
+            table = ppn << PAGE_SHIFT;
+            continue;
+        }
+
+        let page_size = 1 << shift;
+        if (ppn << PAGE_SHIFT) & (page_size - 1) != 0 {
+            return Err(PageFault::MisalignedSuperpage {
+                vaddr,
+                pte_addr,
+                level,
+            });
+        }
+        if !flags.contains(access.required()) {
+            return Err(PageFault::PermissionDenied {
+                vaddr,
+                access,
+                flags,
+            });
+        }
+
+        return Ok(Translation {
+            phys_addr: (ppn << PAGE_SHIFT) | (vaddr & (page_size - 1)),
+            flags,
+            page_size,
+        });
+    }
+
+    unreachable!("page table walk always ends at the last level")
+}
+
+impl Instance {
+    /// Translates virtual address using the page table
+    /// pointed by the raw `satp` value. `satp` layout is
+    /// chosen by the `Instance::is_rv64`.
+    ///
+    /// See `translate_with` for the limitations.
+    pub fn translate(
+        &self,
+        satp: u64,
+        vaddr: u64,
+        access: Access,
+    ) -> Result<Translation, PageFault> {
+        let satp = Satp::decode(satp, self.is_rv64())?;
+        translate_with(satp, vaddr, access, |addr, size| {
+            if size == 4 {
+                self.read_u32(addr).map(u64::from)
+            } else {
+                self.read_u64(addr)
+            }
+        })
+    }
+
+    /// Reads guest memory by the virtual address, access
+    /// may cross page boundaries.
+    ///
+    /// - Returns `Ok` if whole range is translated and read
+    /// - Returns `PageFault` of the first failed page
+    ///   otherwise
+    pub fn read_virt(
+        &self,
+        satp: u64,
+        vaddr: u64,
+        dest: &mut [u8],
+    ) -> Result<(), PageFault> {
+        let mut done = 0;
+        while done < dest.len() {
+            let at = vaddr.wrapping_add(done as u64);
+            let page = self.translate(satp, at, Access::Read)?;
+            let len = page_chunk(&page, dest.len() - done);
+
+            self.read_ram_to(page.phys_addr, &mut dest[done..done + len])?;
+            done += len;
+        }
+
+        Ok(())
+    }
+
+    /// Writes guest memory by the virtual address, see
+    /// `Instance::read_virt`. Pages preceding the faulted
+    /// one are left written.
+    pub fn write_virt(
+        &mut self,
+        satp: u64,
+        vaddr: u64,
+        data: &[u8],
+    ) -> Result<(), PageFault> {
+        let mut done = 0;
+        while done < data.len() {
+            let at = vaddr.wrapping_add(done as u64);
+            let page = self.translate(satp, at, Access::Write)?;
+            let len = page_chunk(&page, data.len() - done);
+
+            self.write_ram(page.phys_addr, &data[done..done + len])?;
+            done += len;
+        }
+
+        Ok(())
+    }
+}
+
+fn page_chunk(page: &Translation, remaining: usize) -> usize {
+    let left = page.page_size - (page.phys_addr & (page.page_size - 1));
+    (left as usize).min(remaining)
+}
@@ -1,10 +1,24 @@
+#[cfg(feature = "internals")]
+pub use crate::{
+    debug::*,
+    hart::*,
+    trace::*,
+};
 pub use crate::{
     dev::{
         mmio::*,
+        plic::*,
         type_::*,
+        uart::*,
+        uart16550::*,
     },
+    disasm::*,
+    elf::*,
     fdt::*,
     instance::*,
     macros::*,
+    memory::*,
+    mmu::*,
+    snapshot::*,
     types::*,
 };
@@ -0,0 +1,576 @@
+use std::io::{
+    self,
+    Read,
+    Write,
+};
+
+use crate::{
+    error::{
+        MemoryAccessError,
+        SnapshotError,
+    },
+    instance::Instance,
+};
+
+const MAGIC: &[u8; 8] = b"RVVMSNAP";
+const VERSION: u32 = 1;
+const PAGE_SIZE: usize = 4096;
+
+const FLAG_RV64: u32 = 1 << 0;
+
+const RUN_ZERO: u8 = 0;
+const RUN_DATA: u8 = 1;
+
+/// Owned copy of the guest RAM.
+///
+/// # On-disk format
+///
+/// All integers are little-endian. Header:
+///
+/// - `b"RVVMSNAP"` magic
+/// - `u32` format version
+/// - `u32` flags, bit `0` is set for the rv64 machines
+/// - `u64` RAM base address
+/// - `u64` RAM size
+///
+/// followed by runs of 4 KiB pages covering the whole RAM,
+/// each run is `u8` kind (`0` - zeroed pages, `1` - raw
+/// pages) and `u32` amount of pages. Raw runs are followed
+/// by the page contents, last page is truncated to the
+/// RAM size.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    mem_base: u64,
+    rv64: bool,
+    data: Vec<u8>,
+}
+
+impl Snapshot {
+    pub(crate) fn new(mem_base: u64, rv64: bool, data: Vec<u8>) -> Self {
+        Self {
+            mem_base,
+            rv64,
+            data,
+        }
+    }
+
+    pub const fn mem_base(&self) -> u64 {
+        self.mem_base
+    }
+
+    pub fn mem_size(&self) -> usize {
+        self.data.len()
+    }
+
+    pub const fn is_rv64(&self) -> bool {
+        self.rv64
+    }
+
+    /// Get RAM contents, index `0` corresponds to the
+    /// `Snapshot::mem_base` physical address
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Serializes snapshot in the on-disk format
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(
+            &(if self.rv64 { FLAG_RV64 } else { 0 }).to_le_bytes(),
+        )?;
+        writer.write_all(&self.mem_base.to_le_bytes())?;
+        writer.write_all(&(self.data.len() as u64).to_le_bytes())?;
+
+        let mut pages = self.data.chunks(PAGE_SIZE).peekable();
+        while let Some(page) = pages.next() {
+            let zero = is_zero(page);
+            let mut run = vec![page];
+            while let Some(next) = pages.next_if(|next| {
+                is_zero(next) == zero && run.len() < u32::MAX as usize
+            }) {
+                run.push(next);
+            }
+
+            writer.write_all(&[if zero { RUN_ZERO } else { RUN_DATA }])?;
+            writer.write_all(&(run.len() as u32).to_le_bytes())?;
+            if !zero {
+                for page in run {
+                    writer.write_all(page)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Deserializes snapshot from the on-disk format
+    ///
+    /// - Returns `Ok` if snapshot is successfully read
+    /// - Returns `SnapshotError` otherwise
+    pub fn read_from(
+        mut reader: impl Read,
+    ) -> Result<Self, SnapshotError> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(SnapshotError::InvalidMagic);
+        }
+
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let rv64 = read_u32(&mut reader)? & FLAG_RV64 != 0;
+        let mem_base = read_u64(&mut reader)?;
+        let mem_size =
+            usize::try_from(read_u64(&mut reader)?).map_err(|_| {
+                SnapshotError::Corrupted("RAM size overflows usize")
+            })?;
+
+        let mut data = Vec::new();
+        data.try_reserve_exact(mem_size).map_err(|_| {
+            SnapshotError::Corrupted("RAM size is too large")
+        })?;
+
+        while data.len() < mem_size {
+            let mut kind = [0];
+            reader.read_exact(&mut kind)?;
+            let pages = read_u32(&mut reader)? as usize;
+
+            let len = pages
+                .checked_mul(PAGE_SIZE)
+                .map(|len| len.min(mem_size - data.len()))
+                .ok_or(SnapshotError::Corrupted("run is too long"))?;
+            if len == 0 {
+                return Err(SnapshotError::Corrupted("empty run"));
+            }
+            let start = data.len();
+            data.resize(start + len, 0);
+
+            match kind[0] {
+                RUN_ZERO => {}
+                RUN_DATA => reader.read_exact(&mut data[start..])?,
+                _ => {
+                    return Err(SnapshotError::Corrupted(
+                        "unknown run kind",
+                    ))
+                }
+            }
+        }
+
+        Ok(Self::new(mem_base, rv64, data))
+    }
+}
+
+/// Whole machine state: harts, Rust devices and RAM.
+///
+/// # On-disk format
+///
+/// All integers are little-endian. Header:
+///
+/// - `b"RVVMSTAT"` magic
+/// - `u32` format version
+/// - `u32` flags, bit `0` is set for the rv64 machines
+/// - `u32` amount of harts
+/// - `u64` RAM base address
+/// - `u64` RAM size
+///
+/// followed by the harts, each hart is `x0`-`x31` and `pc`
+/// as `u64`, raw bits of `f0`-`f31` as `u64`, `u8`
+/// privilege mode, `u16` amount of CSRs and `(u16 number,
+/// u64 value)` pairs. Then `u32` amount of devices, each
+/// device is `u64` address, `u64` size, `u32` state length
+/// and the state itself. RAM `Snapshot` closes the state.
+#[cfg(feature = "internals")]
+pub(crate) mod state {
+    use std::io::{
+        self,
+        Read,
+        Write,
+    };
+
+    use super::{
+        read_u32,
+        read_u64,
+        Snapshot,
+    };
+    use crate::{
+        error::StateError,
+        hart::{
+            HartState,
+            STATE_CSRS,
+        },
+        instance::Instance,
+    };
+
+    const MAGIC: &[u8; 8] = b"RVVMSTAT";
+    const VERSION: u32 = 1;
+
+    struct DeviceState {
+        addr: u64,
+        size: u64,
+        state: Vec<u8>,
+    }
+
+    impl Instance {
+        /// Saves state of the whole machine: registers and
+        /// CSRs of the harts, state of the Rust devices
+        /// (see `Device::save_state`) and RAM. Machine must
+        /// be paused.
+        ///
+        /// Built-in RVVM devices have no serialization API
+        /// and are not saved. That includes the CLINT and
+        /// the hart timers: `mtime` keeps running on the
+        /// host clock and `mtimecmp` keeps its current
+        /// value across the `Instance::load_state`.
+        ///
+        /// - Returns names of the built-in devices that
+        ///   were not saved (see
+        ///   `Instance::builtin_devices`) if state is
+        ///   successfully written
+        /// - Returns `StateError` otherwise
+        pub fn save_state(
+            &self,
+            mut writer: impl Write,
+        ) -> Result<Vec<String>, StateError> {
+            self.check_not_running()?;
+
+            let harts = (0..self.harts())
+                .map(|id| {
+                    self.capture_hart(id).map_err(|csr| {
+                        StateError::CsrRejected { hart: id, csr }
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let devices: Vec<_> = self
+                .attached_devices()
+                .into_iter()
+                .filter_map(|(dev, attached)| {
+                    // SAFETY: `dev` is the attached device description and
+                    // machine is paused
+                    let state = unsafe { (attached.save_state)(dev)? };
+                    let (addr, size) =
+                        unsafe { ((*dev).addr, (*dev).size) };
+
+                    Some(DeviceState {
+                        addr,
+                        size: size as u64,
+                        state,
+                    })
+                })
+                .collect();
+            let ram = self.snapshot_ram()?;
+
+            let w = &mut writer;
+            w.write_all(MAGIC)?;
+            w.write_all(&VERSION.to_le_bytes())?;
+            w.write_all(&(self.is_rv64() as u32).to_le_bytes())?;
+            w.write_all(&(self.harts() as u32).to_le_bytes())?;
+            w.write_all(&self.mem_base().to_le_bytes())?;
+            w.write_all(&(self.mem_size() as u64).to_le_bytes())?;
+
+            for hart in &harts {
+                write_hart(w, hart)?;
+            }
+
+            w.write_all(&(devices.len() as u32).to_le_bytes())?;
+            for dev in &devices {
+                w.write_all(&dev.addr.to_le_bytes())?;
+                w.write_all(&dev.size.to_le_bytes())?;
+                w.write_all(&(dev.state.len() as u32).to_le_bytes())?;
+                w.write_all(&dev.state)?;
+            }
+
+            ram.write_to(w)?;
+            Ok(self.builtin_devices())
+        }
+
+        /// Loads state saved by the `Instance::save_state`,
+        /// machine must be paused. State is fully read and
+        /// matched against the machine before being
+        /// applied. If a hart or a device rejects its
+        /// state, harts and devices are rolled back to
+        /// their previous state and RAM is left untouched.
+        /// Timers are not restored, see the
+        /// `Instance::save_state`.
+        ///
+        /// - Returns `Ok` if state is successfully restored
+        /// - Returns `StateError::ConfigMismatch` if state
+        ///   was saved from differently configured machine
+        /// - Returns `StateError::DeviceNotFound` if
+        ///   there's no matching Rust device for the saved
+        ///   state
+        /// - Returns `StateError` otherwise
+        pub fn load_state(
+            &mut self,
+            mut reader: impl Read,
+        ) -> Result<(), StateError> {
+            self.check_not_running()?;
+            let r = &mut reader;
+
+            let mut magic = [0; 8];
+            r.read_exact(&mut magic)?;
+            if &magic != MAGIC {
+                return Err(StateError::InvalidMagic);
+            }
+            let version = read_u32(r)?;
+            if version != VERSION {
+                return Err(StateError::UnsupportedVersion(version));
+            }
+
+            let rv64 = read_u32(r)? & 1 != 0;
+            let harts = read_u32(r)? as usize;
+            let mem_base = read_u64(r)?;
+            let mem_size = read_u64(r)?.try_into().unwrap_or(usize::MAX);
+            if rv64 != self.is_rv64()
+                || harts != self.harts()
+                || mem_base != self.mem_base()
+                || mem_size != self.mem_size()
+            {
+                return Err(StateError::ConfigMismatch {
+                    harts,
+                    mem_base,
+                    mem_size,
+                    rv64,
+                });
+            }
+
+            let harts = (0..harts)
+                .map(|_| read_hart(r))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let count = read_u32(r)?;
+            let mut devices = Vec::new();
+            for _ in 0..count {
+                let addr = read_u64(r)?;
+                let size = read_u64(r)?;
+                let len = read_u32(r)? as usize;
+
+                let mut state = Vec::new();
+                r.take(len as u64).read_to_end(&mut state)?;
+                if state.len() != len {
+                    return Err(io::Error::from(
+                        io::ErrorKind::UnexpectedEof,
+                    )
+                    .into());
+                }
+
+                devices.push(DeviceState { addr, size, state });
+            }
+
+            let ram = Snapshot::read_from(r)?;
+            self.check_snapshot(&ram)?;
+
+            let attached = self.attached_devices();
+            let mut targets = Vec::with_capacity(devices.len());
+            for dev in &devices {
+                let target = attached.iter().find(|(raw, _)| {
+                    // SAFETY: `raw` is the attached device description
+                    unsafe {
+                        (**raw).addr == dev.addr
+                            && (**raw).size as u64 == dev.size
+                    }
+                });
+
+                match target {
+                    Some(&(raw, attached)) => targets.push((
+                        raw,
+                        attached.save_state,
+                        attached.load_state,
+                    )),
+                    None => {
+                        return Err(StateError::DeviceNotFound {
+                            addr: dev.addr,
+                        })
+                    }
+                }
+            }
+
+            let backup = (0..self.harts())
+                .map(|id| {
+                    self.capture_hart(id).map_err(|csr| {
+                        StateError::CsrRejected { hart: id, csr }
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let device_backup: Vec<_> = targets
+                .iter()
+                // SAFETY: `raw` is the attached device description and
+                // machine is paused
+                .map(|&(raw, save_state, _)| unsafe { save_state(raw) })
+                .collect();
+
+            let restored = harts
+                .iter()
+                .enumerate()
+                .try_for_each(|(id, hart)| {
+                    self.restore_hart(id, hart).map_err(|csr| {
+                        StateError::CsrRejected { hart: id, csr }
+                    })
+                })
+                .and_then(|()| {
+                    devices.iter().zip(&targets).try_for_each(
+                        |(dev, &(raw, _, load_state))| {
+                            // SAFETY: `raw` is the attached device
+                            // description and machine is paused
+                            if unsafe { load_state(raw, &dev.state) } {
+                                Ok(())
+                            } else {
+                                Err(StateError::DeviceRejected {
+                                    addr: dev.addr,
+                                })
+                            }
+                        },
+                    )
+                })
+                .and_then(|()| Ok(self.restore_ram(&ram)?));
+
+            if restored.is_err() {
+                // Best effort, previous state was accepted before
+                for (id, hart) in backup.iter().enumerate() {
+                    let _ = self.restore_hart(id, hart);
+                }
+                for (state, &(raw, _, load_state)) in
+                    device_backup.iter().zip(&targets)
+                {
+                    if let Some(state) = state {
+                        // SAFETY: see above
+                        unsafe { load_state(raw, state) };
+                    }
+                }
+            }
+
+            restored
+        }
+    }
+
+    pub(crate) fn write_hart(
+        w: &mut impl Write,
+        hart: &HartState,
+    ) -> io::Result<()> {
+        for reg in hart.regs.iter().chain(&hart.fregs) {
+            w.write_all(&reg.to_le_bytes())?;
+        }
+        w.write_all(&[hart.priv_mode])?;
+
+        w.write_all(&(STATE_CSRS.len() as u16).to_le_bytes())?;
+        for (csr, value) in STATE_CSRS.iter().zip(&hart.csrs) {
+            w.write_all(&csr.to_le_bytes())?;
+            w.write_all(&value.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn read_hart(
+        r: &mut impl Read,
+    ) -> Result<HartState, StateError> {
+        let mut hart = HartState {
+            regs: [0; 33],
+            fregs: [0; 32],
+            priv_mode: 0,
+            csrs: vec![0; STATE_CSRS.len()],
+        };
+        for reg in hart.regs.iter_mut().chain(&mut hart.fregs) {
+            *reg = read_u64(r)?;
+        }
+
+        let mut priv_mode = [0];
+        r.read_exact(&mut priv_mode)?;
+        hart.priv_mode = priv_mode[0];
+
+        let mut csr = [0; 2];
+        r.read_exact(&mut csr)?;
+        let count = u16::from_le_bytes(csr);
+        if count as usize != STATE_CSRS.len() {
+            return Err(StateError::Corrupted("unexpected set of CSRs"));
+        }
+
+        for (&expected, value) in STATE_CSRS.iter().zip(&mut hart.csrs) {
+            r.read_exact(&mut csr)?;
+            if u16::from_le_bytes(csr) != expected {
+                return Err(StateError::Corrupted(
+                    "unexpected set of CSRs",
+                ));
+            }
+
+            *value = read_u64(r)?;
+        }
+
+        Ok(hart)
+    }
+}
+
+impl Instance {
+    /// Copies the whole guest RAM. Machine must be paused,
+    /// otherwise snapshot would be inconsistent. Snapshot
+    /// holds the original instructions in place of the
+    /// breakpoints.
+    ///
+    /// - Returns `Ok` if RAM is successfully copied
+    /// - Returns `MemoryAccessError` otherwise
+    pub fn snapshot_ram(&self) -> Result<Snapshot, MemoryAccessError> {
+        let data = self.ram_copy()?;
+
+        Ok(Snapshot::new(self.mem_base(), self.is_rv64(), data))
+    }
+
+    /// Restores guest RAM from the snapshot, machine must
+    /// be paused. Harts and devices state is left
+    /// untouched, breakpoints are patched into the
+    /// restored code.
+    ///
+    /// - Returns `Ok` if RAM is successfully restored
+    /// - Returns `SnapshotError::ConfigMismatch` if
+    ///   snapshot was taken from differently configured
+    ///   machine
+    /// - Returns `SnapshotError` otherwise
+    pub fn restore_ram(
+        &mut self,
+        snapshot: &Snapshot,
+    ) -> Result<(), SnapshotError> {
+        self.check_snapshot(snapshot)?;
+        self.check_not_running()?;
+        self.write_ram(self.mem_base(), &snapshot.data)?;
+
+        #[cfg(feature = "internals")]
+        self.repatch_breakpoints();
+        Ok(())
+    }
+
+    fn check_snapshot(
+        &self,
+        snapshot: &Snapshot,
+    ) -> Result<(), SnapshotError> {
+        if snapshot.mem_base != self.mem_base()
+            || snapshot.mem_size() != self.mem_size()
+            || snapshot.rv64 != self.is_rv64()
+        {
+            return Err(SnapshotError::ConfigMismatch {
+                mem_base: snapshot.mem_base,
+                mem_size: snapshot.mem_size(),
+                rv64: snapshot.rv64,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+fn is_zero(page: &[u8]) -> bool {
+    page.iter().all(|&b| b == 0)
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
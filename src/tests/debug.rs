@@ -0,0 +1,453 @@
+use std::{
+    io::{
+        Read,
+        Write,
+    },
+    net::{
+        TcpListener,
+        TcpStream,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    debug::{
+        successors,
+        Breakpoints,
+        StopEvent,
+        StopReason,
+        WatchKind,
+    },
+    error::DebugError,
+    gdbstub::{
+        encode_packet,
+        parse_thread,
+        unhex,
+        GdbStub,
+    },
+    hart::Privilege,
+    instance::{
+        Instance,
+        MachineOpt,
+    },
+    tests::instance::{
+        guest,
+        SPIN,
+    },
+    types::{
+        ExitReason,
+        MachineState,
+    },
+};
+
+fn next(insn: u32, pc: u64) -> Vec<u64> {
+    successors(
+        insn,
+        pc,
+        true,
+        |reg| (reg as u64) << 12 | 1,
+        |csr| csr as u64,
+    )
+}
+
+#[test]
+fn control_flow_successors() {
+    // jal ra, 16
+    assert_eq!(next(0x0100_00ef, 0x1000), [0x1010]);
+    // beq ra, sp, -8
+    assert_eq!(next(0xfe20_8ce3, 0x1000), [0x1004, 0xff8]);
+    // jalr zero, 4(t0)
+    assert_eq!(next(0x0042_8067, 0x1000), [0x5004]);
+    // c.j 0
+    assert_eq!(next(0xa001, 0x1000), [0x1000]);
+    // mret
+    assert_eq!(next(0x3020_0073, 0x1000), [0x341]);
+    // addi zero, zero, 0
+    assert_eq!(next(0x0000_0013, 0x1000), [0x1004]);
+    // ecall
+    assert!(next(0x0000_0073, 0x1000).is_empty());
+}
+
+/// Machine with `nop` and `c.nop` at the RAM start and the
+/// hart in machine mode, so addresses are not translated.
+/// JIT is disabled, so points can be set.
+fn machine() -> Instance {
+    let mut instance = Instance::new(1, 0x8000_0000, 4096, true);
+    instance.set_opt(MachineOpt::Jit(false)).unwrap();
+    instance
+        .write_ram(0x8000_0000, &[0x13, 0, 0, 0, 0x01, 0])
+        .unwrap();
+    instance
+        .hart_mut(0)
+        .unwrap()
+        .set_privilege(Privilege::Machine);
+
+    instance
+}
+
+/// Reads the code as is, with the breakpoints patched in
+fn code(instance: &Instance) -> [u8; 6] {
+    let mut code = [0; 6];
+    assert!(instance.view().read(0x8000_0000, &mut code));
+    code
+}
+
+#[test]
+fn breakpoints_are_keyed_by_physical_address() {
+    let instance = machine();
+    let m = instance.view();
+    let mut breakpoints = Breakpoints::default();
+
+    assert!(breakpoints.insert_phys(&m, 0x8000_0000));
+    assert!(breakpoints.insert_phys(&m, 0x8000_0004));
+    assert_eq!(code(&instance), [0x6f, 0, 0, 0, 0x01, 0xa0]);
+    assert!(!breakpoints.insert_phys(&m, 0x9000_0000));
+
+    // Same address doesn't patch the spin over the original
+    assert!(breakpoints.insert_phys(&m, 0x8000_0000));
+    assert!(breakpoints.contains(&m, 0, 0x8000_0000));
+    assert!(breakpoints.remove_phys(&m, 0x8000_0000));
+    assert!(!breakpoints.remove_phys(&m, 0x8000_0000));
+    assert!(!breakpoints.contains_phys(0x8000_0000));
+    assert_eq!(code(&instance), [0x13, 0, 0, 0, 0x01, 0xa0]);
+
+    assert!(breakpoints.remove_phys(&m, 0x8000_0004));
+    assert!(breakpoints.is_empty());
+    assert_eq!(code(&instance), [0x13, 0, 0, 0, 0x01, 0]);
+}
+
+#[test]
+fn stop_reasons() {
+    let mut instance = machine();
+    let range = 0x8000_0010..0x8000_0018;
+
+    assert!(matches!(
+        instance
+            .add_watchpoint(range.start..range.start, WatchKind::Write),
+        Err(DebugError::EmptyRange)
+    ));
+    assert!(matches!(
+        instance.add_watchpoint(range.clone(), WatchKind::Read),
+        Err(DebugError::UnsupportedWatchKind)
+    ));
+    assert!(matches!(
+        instance
+            .add_watchpoint(0x8000_0ffc..0x8000_1004, WatchKind::Write),
+        Err(DebugError::OutOfRam)
+    ));
+    // Rejected before anything is allocated
+    assert!(matches!(
+        instance.add_watchpoint(0x8000_0000..u64::MAX, WatchKind::Write),
+        Err(DebugError::OutOfRam)
+    ));
+    assert!(matches!(
+        instance.add_watchpoint(0..0x8000_0004, WatchKind::Write),
+        Err(DebugError::OutOfRam)
+    ));
+    instance
+        .add_watchpoint(range.clone(), WatchKind::Write)
+        .unwrap();
+    instance.add_breakpoint(0x8000_0004).unwrap();
+
+    let m = instance.view();
+    assert_eq!(instance.monitor().lock().check(&m), None);
+
+    instance.write_ram(0x8000_0014, &[1]).unwrap();
+    let watchpoint = StopEvent {
+        hart: None,
+        pc: None,
+        reason: StopReason::Watchpoint {
+            range: range.clone(),
+            kind: WatchKind::Write,
+        },
+    };
+    assert_eq!(instance.monitor().lock().check(&m), Some(watchpoint));
+    assert_eq!(instance.monitor().lock().check(&m), None);
+
+    instance.hart_mut(0).unwrap().set_pc(0x8000_0004);
+    let breakpoint = StopEvent {
+        hart: Some(0),
+        pc: Some(0x8000_0004),
+        reason: StopReason::Breakpoint(0x8000_0004),
+    };
+    assert_eq!(instance.monitor().lock().check(&m), Some(breakpoint));
+
+    assert!(instance.remove_watchpoint(range.clone()));
+    assert!(!instance.remove_watchpoint(range));
+    assert!(instance.remove_breakpoint(0x8000_0004));
+    assert!(!instance.remove_breakpoint(0x8000_0004));
+    assert_eq!(code(&instance), [0x13, 0, 0, 0, 0x01, 0]);
+}
+
+#[test]
+fn watchpoints_see_through_breakpoints() {
+    let mut instance = machine();
+    let m = instance.view();
+    let range = 0x8000_0000..0x8000_0008;
+
+    // Breakpoint set before and after the watchpoint
+    instance.add_breakpoint(0x8000_0000).unwrap();
+    instance
+        .add_watchpoint(range.clone(), WatchKind::Write)
+        .unwrap();
+    instance.add_breakpoint(0x8000_0004).unwrap();
+    assert_eq!(code(&instance), [0x6f, 0, 0, 0, 0x01, 0xa0]);
+    assert_eq!(instance.monitor().lock().check(&m), None);
+
+    instance.write_ram(0x8000_0006, &[1]).unwrap();
+    assert!(matches!(
+        instance.monitor().lock().check(&m),
+        Some(StopEvent {
+            reason: StopReason::Watchpoint { .. },
+            ..
+        })
+    ));
+    assert_eq!(instance.monitor().lock().check(&m), None);
+}
+
+#[test]
+fn start_gives_up_stepping_over_breakpoints() {
+    // 1: wfi
+    //    j 1b
+    // Hart can't leave the `wfi` without interrupts
+    let mut instance = guest(&[0x1050_0073, 0xffdf_f06f]);
+    instance.set_opt(MachineOpt::Jit(false)).unwrap();
+    instance.add_breakpoint(0x8000_0000).unwrap();
+
+    instance.start().unwrap();
+    // Monitor may have already stopped the machine at the
+    // breakpoint
+    let _ = instance.pause();
+    assert!(instance.remove_breakpoint(0x8000_0000));
+    assert_eq!(instance.read_u32(0x8000_0000), Ok(0x1050_0073));
+}
+
+/// Counts `t0` up to `0x100_0000`, then spins at the
+/// `0x8000_000c`, so the monitor checks it many times
+/// before the breakpoint is reached
+fn counting_guest() -> Instance {
+    //    lui t1, 0x1000
+    // 1: addi t0, t0, 1
+    //    bne t0, t1, 1b
+    let mut instance =
+        guest(&[0x0100_0337, 0x0012_8293, 0xfe62_9ee3, SPIN]);
+    instance.set_opt(MachineOpt::Jit(false)).unwrap();
+    instance.add_breakpoint(0x8000_000c).unwrap();
+    instance
+}
+
+const COUNTED: StopEvent = StopEvent {
+    hart: Some(0),
+    pc: Some(0x8000_000c),
+    reason: StopReason::Breakpoint(0x8000_000c),
+};
+
+#[test]
+fn breakpoint_stops_running_machine() {
+    let mut instance = counting_guest();
+    let events = instance.stop_events();
+
+    instance.start().unwrap();
+    let event = events
+        .recv_timeout(Duration::from_secs(10))
+        .unwrap();
+    assert_eq!(event, COUNTED);
+    assert_eq!(instance.state(), MachineState::Paused);
+    assert_eq!(instance.hart(0).unwrap().x(5), 0x100_0000);
+}
+
+#[test]
+fn event_loop_runs_until_breakpoint() {
+    let mut instance = counting_guest();
+    let events = instance.stop_events();
+
+    // Monitor checks don't end the event loop
+    assert_eq!(instance.run().unwrap(), ExitReason::Paused);
+    assert_eq!(events.try_recv(), Ok(COUNTED));
+    assert_eq!(instance.state(), MachineState::Paused);
+    assert_eq!(instance.hart(0).unwrap().pc(), 0x8000_000c);
+}
+
+#[test]
+fn gdb_hex_decoding() {
+    assert_eq!(unhex(""), Some(Vec::new()));
+    assert_eq!(unhex("00ff7A"), Some(vec![0x00, 0xff, 0x7a]));
+    assert_eq!(unhex("abc"), None);
+    assert_eq!(unhex("zz"), None);
+    assert_eq!(unhex("+1"), None);
+    assert_eq!(unhex("é0"), None);
+}
+
+#[test]
+fn gdb_thread_ids() {
+    assert_eq!(parse_thread("0"), Some(None));
+    assert_eq!(parse_thread("-1"), Some(None));
+    assert_eq!(parse_thread("1"), Some(Some(0)));
+    assert_eq!(parse_thread("1f"), Some(Some(30)));
+    assert_eq!(parse_thread(""), None);
+    assert_eq!(parse_thread("-2"), None);
+    assert_eq!(parse_thread("p1.1"), None);
+}
+
+#[test]
+fn gdb_packet_encoding() {
+    assert_eq!(encode_packet(""), b"$#00");
+    assert_eq!(encode_packet("OK"), b"$OK#9a");
+    // `$`, `#`, `}` and `*` are escaped, checksum covers
+    // the escaped data
+    assert_eq!(encode_packet("a$#}*"), b"$a}\x04}\x03}]}\x0a#c3");
+}
+
+/// Sends the packet and reads the reply, acks are skipped
+fn request(conn: &mut TcpStream, packet: &str) -> String {
+    conn.write_all(&encode_packet(packet)).unwrap();
+
+    let mut byte = [0];
+    while byte[0] != b'$' {
+        conn.read_exact(&mut byte).unwrap();
+    }
+    let mut reply = Vec::new();
+    loop {
+        conn.read_exact(&mut byte).unwrap();
+        if byte[0] == b'#' {
+            break;
+        }
+        reply.push(byte[0]);
+    }
+    conn.read_exact(&mut [0; 2]).unwrap();
+
+    String::from_utf8(reply).unwrap()
+}
+
+#[test]
+fn gdb_session() {
+    // 1: addi t0, t0, 1
+    //    j 1b
+    let mut instance = guest(&[0x0012_8293, 0xffdf_f06f]);
+    instance.set_opt(MachineOpt::Jit(false)).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = thread::spawn(move || {
+        let mut conn = TcpStream::connect(addr).unwrap();
+        assert_eq!(request(&mut conn, "QStartNoAckMode"), "OK");
+
+        assert_eq!(request(&mut conn, "Z0,80000004,4"), "OK");
+        assert_eq!(request(&mut conn, "c"), "T05thread:1;swbreak:;");
+        assert_eq!(request(&mut conn, "p20"), "0400008000000000");
+
+        assert_eq!(request(&mut conn, "s"), "T05thread:1;");
+        let regs = request(&mut conn, "g");
+        assert_eq!(regs.len(), 33 * 16);
+        assert_eq!(&regs[32 * 16..], "0000008000000000");
+        assert_ne!(&regs[5 * 16..6 * 16], "0000000000000000");
+
+        // Breakpoint is kept after the step, but hidden from
+        // the reads
+        assert_eq!(request(&mut conn, "m80000000,8"), "938212006ff0dfff");
+        assert_eq!(request(&mut conn, "c"), "T05thread:1;swbreak:;");
+        assert_eq!(request(&mut conn, "z0,80000004,4"), "OK");
+
+        conn.write_all(&encode_packet("k")).unwrap();
+    });
+
+    let (conn, _) = listener.accept().unwrap();
+    GdbStub::new(&mut instance)
+        .unwrap()
+        .serve(conn)
+        .unwrap();
+    client.join().unwrap();
+
+    // Killed target is left paused
+    assert!(instance.pause().is_err());
+    assert_eq!(instance.hart(0).unwrap().pc(), 0x8000_0004);
+}
+
+#[test]
+fn gdb_shares_instance_breakpoints() {
+    // 1: addi t0, t0, 1
+    //    j 1b
+    let mut instance = guest(&[0x0012_8293, 0xffdf_f06f]);
+    instance.set_opt(MachineOpt::Jit(false)).unwrap();
+    instance.add_breakpoint(0x8000_0004).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = thread::spawn(move || {
+        let mut conn = TcpStream::connect(addr).unwrap();
+        assert_eq!(request(&mut conn, "QStartNoAckMode"), "OK");
+
+        assert_eq!(request(&mut conn, "Z0,80000000,4"), "OK");
+        assert_eq!(request(&mut conn, "Z0,80000004,4"), "OK");
+        assert_eq!(request(&mut conn, "m80000000,8"), "938212006ff0dfff");
+        assert_eq!(request(&mut conn, "c"), "T05thread:1;swbreak:;");
+        assert_eq!(request(&mut conn, "p20"), "0400008000000000");
+        assert_eq!(request(&mut conn, "z0,80000004,4"), "OK");
+
+        conn.write_all(&encode_packet("k")).unwrap();
+    });
+
+    let (conn, _) = listener.accept().unwrap();
+    GdbStub::new(&mut instance)
+        .unwrap()
+        .serve(conn)
+        .unwrap();
+    client.join().unwrap();
+
+    // Only the debugger's breakpoint is removed
+    let mut raw = [0; 8];
+    assert!(instance.view().read(0x8000_0000, &mut raw));
+    assert_eq!(raw, [0x93, 0x82, 0x12, 0x00, 0x6f, 0, 0, 0]);
+    assert!(instance.remove_breakpoint(0x8000_0004));
+    assert!(instance.view().read(0x8000_0000, &mut raw));
+    assert_eq!(raw, [0x93, 0x82, 0x12, 0x00, 0x6f, 0xf0, 0xdf, 0xff]);
+}
+
+#[test]
+fn points_require_disabled_jit() {
+    let mut instance = machine();
+    // RVVM built without the JIT can't enable it
+    if instance.set_opt(MachineOpt::Jit(true)).is_err() {
+        return;
+    }
+
+    assert!(matches!(
+        instance.add_breakpoint(0x8000_0000),
+        Err(DebugError::JitEnabled)
+    ));
+    assert!(matches!(
+        instance
+            .add_watchpoint(0x8000_0000..0x8000_0004, WatchKind::Write),
+        Err(DebugError::JitEnabled)
+    ));
+    assert!(!instance.remove_breakpoint(0x8000_0000));
+    assert_eq!(code(&instance), [0x13, 0, 0, 0, 0x01, 0]);
+}
+
+#[test]
+fn snapshots_lift_breakpoints() {
+    let mut instance = machine();
+    instance
+        .write_ram(0x8000_0000, &[0x01, 0, 0x01, 0])
+        .unwrap();
+    let compressed = instance.snapshot_ram().unwrap();
+    instance
+        .write_ram(0x8000_0000, &[0x13, 0, 0, 0])
+        .unwrap();
+
+    instance.add_breakpoint(0x8000_0000).unwrap();
+    let snapshot = instance.snapshot_ram().unwrap();
+    assert_eq!(snapshot.as_slice()[..6], [0x13, 0, 0, 0, 0x01, 0]);
+    assert_eq!(code(&instance), [0x6f, 0, 0, 0, 0x01, 0]);
+    // Reads show the original instruction as well, even
+    // partially overlapping ones
+    assert_eq!(instance.read_u32(0x8000_0000), Ok(0x13));
+    assert_eq!(instance.read_u8(0x8000_0000), Ok(0x13));
+
+    // Original instruction is read from the restored code
+    instance.restore_ram(&compressed).unwrap();
+    assert_eq!(code(&instance), [0x01, 0xa0, 0x01, 0, 0x01, 0]);
+    assert!(instance.remove_breakpoint(0x8000_0000));
+    assert_eq!(code(&instance), [0x01, 0, 0x01, 0, 0x01, 0]);
+}
@@ -0,0 +1,421 @@
+use std::{
+    ffi::CStr,
+    marker::PhantomData,
+    mem,
+    ptr,
+    sync::{
+        atomic::{
+            AtomicU32,
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+    },
+};
+
+use rvvm_sys::{
+    rvvm_attach_mmio,
+    rvvm_get_mmio,
+    rvvm_mmio_dev_t,
+    rvvm_mmio_type_t,
+};
+
+use crate::{
+    c_str,
+    dev::{
+        mmio::{
+            Device,
+            DeviceData,
+            DeviceExt,
+        },
+        type_::DeviceType,
+    },
+    error::DeviceDetachError,
+    instance::Instance,
+    macros::{
+        device,
+        on_remove,
+        type_handler,
+    },
+    types::{
+        DeviceHandle,
+        UnsafeDevice,
+    },
+};
+
+#[device]
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Named {
+    regs: [u32; 4],
+    irq: u32,
+}
+
+#[device]
+struct Tuple<T>(T)
+where
+    T: Clone;
+
+#[device]
+struct Generic<'a, T: Send + Sync> {
+    value: T,
+    name: &'a str,
+}
+
+#[device]
+struct Marker<T>;
+
+// Lint would fire if the attribute was dropped
+#[device]
+#[allow(non_camel_case_types)]
+struct raw_tuple(u8);
+
+#[device]
+struct Unused<T> {
+    count: usize,
+    _ty: PhantomData<fn() -> T>,
+}
+
+#[test]
+fn named_fields() {
+    let mut dev = Named::new(
+        0x1000,
+        16,
+        1..=4,
+        NamedData {
+            regs: [1, 2, 3, 4],
+            irq: 5,
+        },
+    );
+
+    assert_eq!(dev.regs()[2], 3);
+    *dev.irq_mut() = 7;
+    assert_eq!(
+        dev.data(),
+        &NamedData {
+            regs: [1, 2, 3, 4],
+            irq: 7,
+        }
+    );
+}
+
+#[test]
+fn tuple_and_generics() {
+    let counter = Arc::new(());
+    let dev = Tuple::new(0, 4, 1..=4, Arc::clone(&counter));
+    assert_eq!(Arc::strong_count(dev.data()), 2);
+    drop(dev);
+    assert_eq!(Arc::strong_count(&counter), 1);
+
+    let dev = Generic::new(
+        0,
+        4,
+        1..=1,
+        GenericData {
+            value: 3u8,
+            name: "uart",
+        },
+    );
+    assert_eq!((*dev.value(), *dev.name()), (3, "uart"));
+
+    let dev = Unused::<String>::new(
+        0,
+        4,
+        1..=1,
+        UnusedData {
+            count: 1,
+            _ty: PhantomData,
+        },
+    );
+    assert_eq!(*dev.count(), 1);
+
+    let dev = raw_tuple::new(0, 4, 1..=1, 9);
+    assert_eq!(*dev.data(), 9);
+
+    // Devices must stay transparent to the `rvvm_mmio_dev_t`
+    let dev = Marker::<String>::new(0, 4, 1..=1, ());
+    assert_eq!(mem::size_of_val(&dev), mem::size_of::<rvvm_mmio_dev_t>());
+}
+
+// Remembers the last written word
+#[device]
+struct Latch(AtomicU32);
+
+impl Device<AtomicU32> for Latch {
+    type Error = ();
+
+    fn read(
+        &self,
+        dest: &mut [u8],
+        _size: u8,
+        offset: usize,
+    ) -> Result<(), ()> {
+        if offset != 0 {
+            return Err(());
+        }
+
+        let value = self.data().load(Ordering::Relaxed).to_le_bytes();
+        dest.copy_from_slice(&value[..dest.len()]);
+        Ok(())
+    }
+
+    fn write(
+        &self,
+        dest: &mut [u8],
+        _size: u8,
+        _offset: usize,
+    ) -> Result<(), ()> {
+        let mut value = [0; 4];
+        value[..dest.len()].copy_from_slice(dest);
+        self.data()
+            .store(u32::from_le_bytes(value), Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+#[test]
+fn accesses_are_routed_to_the_device() {
+    let mut instance = Instance::new(1, 0x8000_0000, 4096, true);
+    let handle = instance
+        .try_attach_device(Latch::new(
+            0x4000_0000,
+            16,
+            1..=4,
+            AtomicU32::new(0),
+        ))
+        .unwrap();
+
+    // Call the handlers the same way RVVM does on the guest
+    // access
+    // SAFETY: handle refers to the device of this machine
+    let dev = unsafe { rvvm_get_mmio(instance.as_ptr(), handle.raw()) };
+    // SAFETY: handlers are set by the `try_attach_device`
+    let (read, write) =
+        unsafe { ((*dev).read.unwrap(), (*dev).write.unwrap()) };
+
+    let mut value = 0xdead_beef_u32.to_le_bytes();
+    // SAFETY: buffers are valid for the passed size
+    assert!(unsafe { write(dev, value.as_mut_ptr().cast(), 0, 4) });
+    assert_eq!(
+        instance
+            .device(handle)
+            .unwrap()
+            .load(Ordering::Relaxed),
+        0xdead_beef
+    );
+
+    let mut dest = [0; 2];
+    // SAFETY: see above
+    assert!(unsafe { read(dev, dest.as_mut_ptr().cast(), 0, 2) });
+    assert_eq!(dest, [0xef, 0xbe]);
+
+    // Device errors are reported back to RVVM
+    // SAFETY: see above
+    assert!(!unsafe { read(dev, dest.as_mut_ptr().cast(), 8, 2) });
+}
+
+#[test]
+fn typed_access_through_handle() {
+    let mut instance = Instance::new(1, 0x8000_0000, 4096, true);
+    let handle = instance
+        .try_attach_device(Latch::new(
+            0x4000_0000,
+            16,
+            1..=4,
+            AtomicU32::new(1),
+        ))
+        .unwrap();
+
+    assert_eq!(
+        instance
+            .device(handle)
+            .unwrap()
+            .load(Ordering::Relaxed),
+        1
+    );
+    *instance.device_mut(handle).unwrap().get_mut() = 2;
+    assert_eq!(
+        instance
+            .device(handle)
+            .unwrap()
+            .load(Ordering::Relaxed),
+        2
+    );
+
+    // Handle of the other data type is rejected
+    let forged = DeviceHandle::<u32>::new(handle.raw());
+    assert!(instance.device(forged).is_none());
+    assert!(instance.device_mut(forged).is_none());
+}
+
+#[test]
+fn detached_device_returns_data() {
+    let mut instance = Instance::new(1, 0x8000_0000, 4096, true);
+    let latch = || Latch::new(0x4000_0000, 16, 1..=4, AtomicU32::new(7));
+    let handle = instance.try_attach_device(latch()).unwrap();
+
+    let data = instance.detach_device(handle).unwrap();
+    assert_eq!(data.into_inner(), 7);
+    assert!(instance.device(handle).is_none());
+    assert!(matches!(
+        instance.detach_device(handle),
+        Err(DeviceDetachError::NotAttached)
+    ));
+
+    // Region is free again
+    instance.try_attach_device(latch()).unwrap();
+}
+
+const RAW_TYPE: &rvvm_mmio_type_t = &rvvm_mmio_type_t {
+    remove: None,
+    update: None,
+    reset: None,
+    name: c_str!("raw_device").as_ptr(),
+};
+
+#[test]
+fn builtin_devices_are_found_past_detached_ones() {
+    let mut instance = Instance::new(1, 0x8000_0000, 4096, true);
+    let latch = Latch::new(0x4000_0000, 16, 1..=4, AtomicU32::new(7));
+    let handle = instance.try_attach_device(latch).unwrap();
+
+    let raw = rvvm_mmio_dev_t {
+        addr: 0x5000_0000,
+        size: 0,
+        min_op_size: 1,
+        max_op_size: 1,
+        read: None,
+        write: None,
+        data: ptr::null_mut(),
+        machine: ptr::null_mut(),
+        type_: RAW_TYPE as *const rvvm_mmio_type_t as *mut _,
+    };
+    // SAFETY: zero-sized device without callbacks and data
+    // never receives accesses
+    let raw = unsafe { rvvm_attach_mmio(instance.as_ptr(), &raw) };
+    assert!(raw > handle.raw());
+
+    instance.detach_device(handle).unwrap();
+    assert_eq!(instance.builtin_devices(), ["raw_device"]);
+}
+
+static LATCHES_REMOVED: AtomicUsize = AtomicUsize::new(0);
+
+#[type_handler]
+fn reset_latch(dev: &Latch) {
+    dev.data().store(0, Ordering::Relaxed);
+}
+
+#[type_handler(ty = "AtomicU32")]
+fn update_latch(dev: &UnsafeDevice<AtomicU32>) {
+    dev.data().fetch_add(1, Ordering::Relaxed);
+}
+
+#[on_remove]
+fn remove_latch(_dev: &mut Latch) {
+    LATCHES_REMOVED.fetch_add(1, Ordering::Relaxed);
+}
+
+#[test]
+fn type_handlers_receive_the_device() {
+    let mut instance = Instance::new(1, 0x8000_0000, 4096, true);
+    let ty = DeviceType::custom(
+        "latch",
+        remove_latch,
+        update_latch,
+        reset_latch,
+    );
+    let handle = instance
+        .try_attach_device_with_type(
+            Latch::new(0x4000_0000, 16, 1..=4, AtomicU32::new(5)),
+            ty,
+        )
+        .unwrap();
+
+    // Call the callbacks the same way RVVM does
+    // SAFETY: handle refers to the device of this machine
+    let dev = unsafe { rvvm_get_mmio(instance.as_ptr(), handle.raw()) };
+    // SAFETY: type is linked by the
+    // `try_attach_device_with_type` and outlives the device
+    let ty = unsafe { &*(*dev).type_ };
+    assert_eq!(
+        // SAFETY: name is a valid C string
+        unsafe { CStr::from_ptr(ty.name) },
+        c_str!("latch")
+    );
+
+    // SAFETY: `dev` is the device of the handlers' type
+    unsafe { ty.update.unwrap()(dev) };
+    assert_eq!(
+        instance
+            .device(handle)
+            .unwrap()
+            .load(Ordering::Relaxed),
+        6
+    );
+    // SAFETY: see above
+    unsafe { ty.reset.unwrap()(dev) };
+    assert_eq!(
+        instance
+            .device(handle)
+            .unwrap()
+            .load(Ordering::Relaxed),
+        0
+    );
+
+    drop(instance);
+    assert_eq!(LATCHES_REMOVED.load(Ordering::Relaxed), 1);
+}
+
+/// Finds the attached device by its type name
+pub(crate) fn find_device(
+    instance: &Instance,
+    name: &CStr,
+) -> *mut rvvm_mmio_dev_t {
+    (0..)
+        // SAFETY: machine is alive
+        .map(|handle| unsafe { rvvm_get_mmio(instance.as_ptr(), handle) })
+        .take_while(|dev| !dev.is_null())
+        // SAFETY: device type and its name outlive the device
+        .find(
+            |&dev| unsafe { CStr::from_ptr((*(*dev).type_).name) } == name,
+        )
+        .expect("Device is not attached")
+}
+
+/// Reads the device register the same way RVVM does on the
+/// guest access
+///
+/// # Safety
+///
+/// `dev` must be attached to the alive machine
+pub(crate) unsafe fn mmio_read(
+    dev: *mut rvvm_mmio_dev_t,
+    offset: usize,
+    size: u8,
+) -> u32 {
+    let mut value = [0u8; 4];
+    assert!((*dev).read.unwrap()(
+        dev,
+        value.as_mut_ptr().cast(),
+        offset,
+        size
+    ));
+    u32::from_le_bytes(value)
+}
+
+/// # Safety
+///
+/// See `mmio_read`
+pub(crate) unsafe fn mmio_write(
+    dev: *mut rvvm_mmio_dev_t,
+    offset: usize,
+    value: u8,
+) {
+    let mut value = [value];
+    assert!((*dev).write.unwrap()(
+        dev,
+        value.as_mut_ptr().cast(),
+        offset,
+        1
+    ));
+}
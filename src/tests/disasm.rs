@@ -0,0 +1,63 @@
+use super::elf::elf64_with_symbols;
+use crate::{
+    disasm::Disassembler,
+    elf::SymbolTable,
+};
+
+#[test]
+fn disassembles_rv64gc() {
+    let disasm = Disassembler::new(true);
+    let cases: [(u32, &str); 14] = [
+        (0x0000_0297, "auipc t0,0x0"),
+        (0x00a0_0513, "li a0,10"),
+        (0x0000_8067, "ret"),
+        (0xfe20_8ce3, "beq ra,sp,ff8"),
+        (0x3420_2573, "csrr a0,mcause"),
+        (0x3020_0073, "mret"),
+        (0x0ff0_000f, "fence iorw,iorw"),
+        (0x1005_27af, "lr.w a5,(a0)"),
+        (0x02b5_7553, "fadd.d fa0,fa0,fa1"),
+        (0x4505, "li a0,1"),
+        (0x8082, "ret"),
+        (0x1141, "addi sp,sp,-16"),
+        (0xe406, "sd ra,8(sp)"),
+        (0xffff_ffff, ".4byte 0xffffffff"),
+    ];
+
+    for (insn, text) in cases {
+        assert_eq!(disasm.disassemble(insn, 0x1000), text, "{insn:#x}");
+    }
+}
+
+#[test]
+fn disassembles_rv32gc() {
+    let rv32 = Disassembler::new(false);
+    let rv64 = Disassembler::new(true);
+    // Encodings are reused by the RV64-only instructions
+    let cases: [(u32, &str, &str); 7] = [
+        (0x2021, "jal 1008", ".2byte 0x2021"),
+        (0x61c8, "flw fa0,4(a1)", "ld a0,128(a1)"),
+        (0xe1c8, "fsw fa0,4(a1)", "sd a0,128(a1)"),
+        (0x6522, "flw fa0,8(sp)", "ld a0,8(sp)"),
+        (0x0005_b503, ".4byte 0x0005b503", "ld a0,0(a1)"),
+        (0x0005_e503, ".4byte 0x0005e503", "lwu a0,0(a1)"),
+        (0x0015_051b, ".4byte 0x0015051b", "addiw a0,a0,1"),
+    ];
+
+    for (insn, text32, text64) in cases {
+        assert_eq!(rv32.disassemble(insn, 0x1000), text32, "{insn:#x}");
+        assert_eq!(rv64.disassemble(insn, 0x1000), text64, "{insn:#x}");
+    }
+}
+
+#[test]
+fn decodes_symbols() {
+    let symbols = SymbolTable::from_elf(&elf64_with_symbols()).unwrap();
+    let disasm = Disassembler::new(true).with_symbols(&symbols);
+
+    assert_eq!(
+        disasm.disassemble(0x0080_00ef, 0x8000_0000),
+        "jal ra,80000008 <_start+0x8>"
+    );
+    assert_eq!(disasm.describe(0x8000_0000), "80000000 <_start>");
+}
@@ -0,0 +1,207 @@
+use crate::{
+    elf::*,
+    error::ElfError,
+    instance::Instance,
+};
+
+fn put(image: &mut [u8], offset: usize, bytes: &[u8]) {
+    image[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
+
+/// ELF64 image with `.symtab` and `.strtab` sections only
+pub(super) fn elf64_with_symbols() -> Vec<u8> {
+    const STRTAB: usize = 64;
+    const SYMTAB: usize = 80;
+    const SHDRS: usize = SYMTAB + 3 * 24;
+
+    let mut image = vec![0; SHDRS + 3 * 64];
+    put(&mut image, 0, b"\x7fELF\x02\x01\x01");
+    put(&mut image, 18, &243u16.to_le_bytes());
+    put(&mut image, 40, &(SHDRS as u64).to_le_bytes());
+    put(&mut image, 58, &64u16.to_le_bytes());
+    put(&mut image, 60, &3u16.to_le_bytes());
+
+    put(&mut image, STRTAB, b"\0_start\0data\0");
+
+    let symbols =
+        [(1u32, 2u8, 0x8000_0000u64, 16u64), (8, 1, 0x8000_1000, 0)];
+    for (i, (name, kind, value, size)) in symbols.into_iter().enumerate() {
+        let sym = SYMTAB + (i + 1) * 24;
+        put(&mut image, sym, &name.to_le_bytes());
+        put(&mut image, sym + 4, &[kind]);
+        put(&mut image, sym + 6, &1u16.to_le_bytes());
+        put(&mut image, sym + 8, &value.to_le_bytes());
+        put(&mut image, sym + 16, &size.to_le_bytes());
+    }
+
+    // [1] = .symtab linked to the [2] = .strtab
+    let sh = SHDRS + 64;
+    put(&mut image, sh + 4, &2u32.to_le_bytes());
+    put(&mut image, sh + 24, &(SYMTAB as u64).to_le_bytes());
+    put(&mut image, sh + 32, &(3 * 24u64).to_le_bytes());
+    put(&mut image, sh + 40, &2u32.to_le_bytes());
+
+    let sh = SHDRS + 2 * 64;
+    put(&mut image, sh + 4, &3u32.to_le_bytes());
+    put(&mut image, sh + 24, &(STRTAB as u64).to_le_bytes());
+    put(&mut image, sh + 32, &13u64.to_le_bytes());
+
+    image
+}
+
+#[test]
+fn symbol_table_lookup() {
+    let symbols = SymbolTable::from_elf(&elf64_with_symbols()).unwrap();
+    assert_eq!(symbols.len(), 2);
+
+    let (start, offset) = symbols.lookup(0x8000_0004).unwrap();
+    assert_eq!((start.name.as_str(), offset), ("_start", 4));
+    assert_eq!(start.kind, SymbolKind::Function);
+
+    assert!(symbols.lookup(0x8000_0010).is_none());
+    assert!(symbols.lookup(0x7fff_ffff).is_none());
+    assert_eq!(symbols.lookup(0x8000_1100).unwrap().1, 0x100);
+    assert_eq!(symbols.get("data").unwrap().addr, 0x8000_1000);
+}
+
+#[test]
+fn rejects_foreign_images() {
+    let mut image = elf64_with_symbols();
+    image[18] = 62;
+    assert!(matches!(
+        SymbolTable::from_elf(&image),
+        Err(ElfError::NotRiscV { machine: 62 })
+    ));
+    assert!(matches!(
+        SymbolTable::from_elf(b"MZ\x90\0"),
+        Err(ElfError::NotElf)
+    ));
+}
+
+/// Appends a `PT_LOAD` program header with an empty file
+/// part placed at the `paddr`
+fn with_segment(mut image: Vec<u8>, paddr: u64, mem_size: u64) -> Vec<u8> {
+    let ph = image.len();
+    image.resize(ph + 56, 0);
+    put(&mut image, 32, &(ph as u64).to_le_bytes());
+    put(&mut image, 54, &56u16.to_le_bytes());
+    put(&mut image, 56, &1u16.to_le_bytes());
+
+    put(&mut image, ph, &1u32.to_le_bytes());
+    put(&mut image, ph + 24, &paddr.to_le_bytes());
+    put(&mut image, ph + 40, &mem_size.to_le_bytes());
+    image
+}
+
+#[test]
+fn relocates_segments_only() {
+    let mut instance = Instance::new(1, 0x8000_0000, 4096, true);
+    let image = instance
+        .load_elf_bytes(&with_segment(elf64_with_symbols(), 0, 16))
+        .unwrap();
+
+    assert_eq!(image.segments.len(), 1);
+    assert_eq!(image.segments[0], 0x8000_0000..0x8000_0010);
+    assert_eq!(image.entry, 0x8000_0000);
+    assert_eq!(image.symbols.get("_start").unwrap().addr, 0x8000_0000);
+    assert_eq!(image.symbols.get("data").unwrap().addr, 0x8000_1000);
+}
+
+#[test]
+fn entry_is_translated_to_physical() {
+    let mut instance = Instance::new(1, 0x8000_0000, 4096, true);
+    // Linked at the 0xffff_ffff_8000_0000, loaded at the RAM
+    // start
+    let mut image = with_segment(elf64_with_symbols(), 0x8000_0000, 16);
+    let ph = image.len() - 56;
+    put(&mut image, ph + 16, &0xffff_ffff_8000_0000u64.to_le_bytes());
+    put(&mut image, 24, &0xffff_ffff_8000_0004u64.to_le_bytes());
+
+    let image = instance.load_elf_bytes(&image).unwrap();
+    assert_eq!(image.segments[0], 0x8000_0000..0x8000_0010);
+    assert_eq!(image.entry, 0x8000_0004);
+}
+
+#[test]
+fn rejects_oversized_headers() {
+    let malformed = |image: &[u8]| {
+        matches!(SymbolTable::from_elf(image), Err(ElfError::Malformed(_)))
+    };
+
+    let mut image = elf64_with_symbols();
+    put(&mut image, 40, &(u64::MAX - 8).to_le_bytes());
+    assert!(malformed(&image));
+
+    let mut image = elf64_with_symbols();
+    put(&mut image, 58, &u16::MAX.to_le_bytes());
+    assert!(malformed(&image));
+
+    let mut image = elf64_with_symbols();
+    put(&mut image, 58, &8u16.to_le_bytes());
+    assert!(malformed(&image));
+
+    let mut instance = Instance::new(1, 0x8000_0000, 4096, true);
+    let mut image = with_segment(elf64_with_symbols(), 0x8000_0000, 16);
+    put(&mut image, 32, &(u64::MAX - 8).to_le_bytes());
+    assert!(matches!(
+        instance.load_elf_bytes(&image),
+        Err(ElfError::Malformed(_))
+    ));
+
+    let mut image = with_segment(elf64_with_symbols(), 0x8000_0000, 16);
+    let ph = image.len() - 56;
+    put(&mut image, ph + 8, &(u64::MAX - 8).to_le_bytes());
+    put(&mut image, ph + 32, &16u64.to_le_bytes());
+    assert!(matches!(
+        instance.load_elf_bytes(&image),
+        Err(ElfError::Malformed(_))
+    ));
+}
+
+#[test]
+fn loads_elf32_segments() {
+    const PH: usize = 52;
+    const CODE: usize = PH + 32;
+
+    let mut image = vec![0; CODE + 8];
+    put(&mut image, 0, b"\x7fELF\x01\x01\x01");
+    put(&mut image, 18, &243u16.to_le_bytes());
+    put(&mut image, 24, &0x8000_0004u32.to_le_bytes());
+    put(&mut image, 28, &(PH as u32).to_le_bytes());
+    put(&mut image, 42, &32u16.to_le_bytes());
+    put(&mut image, 44, &1u16.to_le_bytes());
+
+    put(&mut image, PH, &1u32.to_le_bytes());
+    put(&mut image, PH + 4, &(CODE as u32).to_le_bytes());
+    put(&mut image, PH + 8, &0x8000_0000u32.to_le_bytes());
+    put(&mut image, PH + 12, &0x8000_0000u32.to_le_bytes());
+    put(&mut image, PH + 16, &8u32.to_le_bytes());
+    put(&mut image, PH + 20, &16u32.to_le_bytes());
+    put(&mut image, CODE, &[0x13, 0, 0, 0, 0x6f, 0, 0, 0]);
+
+    let mut rv64 = Instance::new(1, 0x8000_0000, 4096, true);
+    assert!(matches!(
+        rv64.load_elf_bytes(&image),
+        Err(ElfError::ClassMismatch {
+            elf_bits: 32,
+            machine_bits: 64
+        })
+    ));
+
+    let mut instance = Instance::new(1, 0x8000_0000, 4096, false);
+    instance
+        .write_ram(0x8000_0008, &[0xff; 8])
+        .unwrap();
+    let elf = instance.load_elf_bytes(&image).unwrap();
+    assert_eq!(elf.entry, 0x8000_0004);
+    assert_eq!(elf.segments.len(), 1);
+    assert_eq!(elf.segments[0], 0x8000_0000..0x8000_0010);
+    assert!(elf.symbols.is_empty());
+
+    let mut ram = [0xaa; 16];
+    instance
+        .read_ram_to(0x8000_0000, &mut ram)
+        .unwrap();
+    assert_eq!(ram[..8], image[CODE..]);
+    assert_eq!(ram[8..], [0; 8]);
+}
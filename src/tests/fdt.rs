@@ -1 +1,44 @@
+use rvvm_sys::rvvm_get_opt;
 
+use crate::{
+    c_str,
+    error::FdtCommitError,
+    instance::{
+        Instance,
+        MachineOpt,
+    },
+};
+
+#[test]
+fn committed_fdt_is_loaded_into_ram() {
+    let mut instance = Instance::new(1, 0x8000_0000, 0x10_0000, true);
+    instance
+        .fdt_root_mut()
+        .prop(c_str!("test-prop"), 0x1234u32);
+
+    let addr = instance.commit_fdt().unwrap();
+    let dtb = instance.fdt_root().serialize(0);
+    assert_eq!(dtb[..4], [0xd0, 0x0d, 0xfe, 0xed]);
+    assert_eq!(addr % 8, 0);
+
+    let mut loaded = vec![0; dtb.len()];
+    instance.read_ram_to(addr, &mut loaded).unwrap();
+    assert_eq!(loaded, dtb);
+
+    let (id, _) = MachineOpt::DtbAddr(0).raw();
+    // SAFETY: pointer is obtained from the live instance
+    assert_eq!(unsafe { rvvm_get_opt(instance.as_ptr(), id) }, addr);
+}
+
+#[test]
+fn oversized_fdt_is_rejected() {
+    let mut instance = Instance::new(1, 0x8000_0000, 4096, true);
+    instance
+        .fdt_root_mut()
+        .prop(c_str!("blob"), [0u8; 8192]);
+
+    assert!(matches!(
+        instance.commit_fdt(),
+        Err(FdtCommitError::TooLarge { mem_size: 4096, .. })
+    ));
+}
@@ -0,0 +1,75 @@
+use crate::{
+    error::HartAccessError,
+    hart::Privilege,
+    instance::Instance,
+};
+
+#[test]
+fn registers_roundtrip() {
+    let mut instance = Instance::new(2, 0x8000_0000, 4096, true);
+
+    let mut hart = instance.hart_mut(1).unwrap();
+    hart.set_pc(0x8000_0004);
+    hart.set_x(0, 1);
+    hart.set_x(5, u64::MAX);
+    hart.set_f(3, 1.5);
+    hart.set_f_bits(4, 0xffff_ffff_3fc0_0000);
+    hart.set_privilege(Privilege::Supervisor);
+    assert_eq!(hart.pc(), 0x8000_0004);
+
+    let hart = instance.hart(1).unwrap();
+    assert_eq!(hart.id(), 1);
+    assert_eq!(hart.pc(), 0x8000_0004);
+    assert_eq!(hart.x(0), 0);
+    assert_eq!(hart.xs()[5], u64::MAX);
+    assert_eq!(hart.f(3), 1.5);
+    assert_eq!(hart.f_bits(4), 0xffff_ffff_3fc0_0000);
+    assert_eq!(hart.privilege(), Privilege::Supervisor);
+
+    let other = instance.hart(0).unwrap();
+    assert_eq!((other.pc(), other.x(5)), (0, 0));
+}
+
+#[test]
+fn rv32_values_are_truncated() {
+    let mut instance = Instance::new(1, 0x8000_0000, 4096, false);
+
+    let mut hart = instance.hart_mut(0).unwrap();
+    assert!(!hart.is_rv64());
+    hart.set_pc(0x1_8000_0000);
+    hart.set_x(1, u64::MAX);
+    assert_eq!(hart.pc(), 0x8000_0000);
+    assert_eq!(hart.x(1), u32::MAX as u64);
+}
+
+#[test]
+fn rejects_out_of_range_harts() {
+    let mut instance = Instance::new(1, 0x8000_0000, 4096, true);
+
+    assert!(matches!(instance.hart(1), Err(HartAccessError::OutOfRange)));
+    assert!(matches!(
+        instance.hart_mut(1),
+        Err(HartAccessError::OutOfRange)
+    ));
+}
+
+#[test]
+fn csrs_are_accessed_in_any_privilege() {
+    let mut instance = Instance::new(1, 0x8000_0000, 4096, true);
+
+    let mut hart = instance.hart_mut(0).unwrap();
+    hart.set_privilege(Privilege::User);
+    // mscratch
+    hart.write_csr(0x340, 0x1234).unwrap();
+    assert_eq!(hart.read_csr(0x340).unwrap(), 0x1234);
+    assert!(hart.read_csr(0xfff).is_err());
+    assert_eq!(hart.privilege(), Privilege::User);
+}
+
+#[test]
+#[should_panic(expected = "floating-point registers")]
+fn rejects_out_of_range_fpu_registers() {
+    let mut instance = Instance::new(1, 0x8000_0000, 4096, true);
+
+    instance.hart_mut(0).unwrap().set_f(32, 1.0);
+}
@@ -0,0 +1,306 @@
+use std::{
+    io::Cursor,
+    sync::{
+        atomic::{
+            AtomicPtr,
+            Ordering,
+        },
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use rvvm_sys::{
+    rvvm_get_opt,
+    rvvm_machine_t,
+    rvvm_reset_machine,
+};
+
+use crate::{
+    dev::mmio::{
+        Device,
+        DeviceData,
+        DeviceExt,
+    },
+    error::{
+        InstancePauseError,
+        InstanceResetError,
+        LoadError,
+        MemoryAccessError,
+    },
+    instance::{
+        Instance,
+        MachineOpt,
+    },
+    macros::device,
+    types::{
+        ExitReason,
+        LoopEvents,
+        MachineState,
+    },
+};
+
+/// `lui t0, 0x100`, loads the `SYSCON` address
+pub(crate) const LOAD_SYSCON: u32 = 0x0010_02b7;
+/// `sw zero, 0(t0)`, powers the machine off
+pub(crate) const POWER_OFF: u32 = 0x0002_a023;
+/// `sw zero, 4(t0)`, resets the machine
+pub(crate) const RESET: u32 = 0x0002_a223;
+/// `j .`
+pub(crate) const SPIN: u32 = 0x0000_006f;
+
+pub(crate) const SYSCON: u64 = 0x10_0000;
+
+/// Powers the machine off on the writes to the offset 0
+/// and resets it on the writes to the offset 4
+#[device]
+pub(crate) struct Syscon(AtomicPtr<rvvm_machine_t>);
+
+impl Device<AtomicPtr<rvvm_machine_t>> for Syscon {
+    type Error = ();
+
+    fn read(
+        &self,
+        dest: &mut [u8],
+        _size: u8,
+        _offset: usize,
+    ) -> Result<(), ()> {
+        dest.fill(0);
+        Ok(())
+    }
+
+    fn write(
+        &self,
+        _dest: &mut [u8],
+        _size: u8,
+        offset: usize,
+    ) -> Result<(), ()> {
+        let reset = match offset {
+            0 => false,
+            4 => true,
+            _ => return Err(()),
+        };
+
+        // SAFETY: machine outlives its devices
+        unsafe {
+            rvvm_reset_machine(self.data().load(Ordering::Relaxed), reset)
+        };
+        Ok(())
+    }
+}
+
+/// 64-bit machine with the `code` at the RAM start and the
+/// `Syscon` at the `SYSCON`
+pub(crate) fn guest(code: &[u32]) -> Instance {
+    let mut instance = Instance::new(1, 0x8000_0000, 0x10_0000, true);
+    let bytes: Vec<u8> = code
+        .iter()
+        .flat_map(|insn| insn.to_le_bytes())
+        .collect();
+    instance.write_ram(0x8000_0000, &bytes).unwrap();
+
+    let machine = AtomicPtr::new(instance.as_ptr());
+    instance
+        .try_attach_device(Syscon::new(SYSCON, 8, 4..=4, machine))
+        .unwrap();
+
+    instance
+}
+
+#[test]
+fn loop_events() {
+    let events = Arc::new(LoopEvents::default());
+
+    let notifier = Arc::clone(&events);
+    let thread = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(10));
+        notifier.notify_reset();
+    });
+    assert!(events.wait());
+    thread.join().unwrap();
+
+    // Reset of the previous run is forgotten
+    events.begin();
+    events.notify_exit();
+    assert!(!events.wait());
+}
+
+#[test]
+fn machine_state() {
+    assert_eq!(MachineState::new(true, true), MachineState::Running);
+    assert_eq!(MachineState::new(true, false), MachineState::Paused);
+    // Stale running flag of the powered off machine is ignored
+    assert_eq!(MachineState::new(false, true), MachineState::PoweredOff);
+}
+
+#[test]
+fn run_until_power_off() {
+    let mut instance = guest(&[LOAD_SYSCON, POWER_OFF, SPIN]);
+
+    assert_eq!(instance.run().unwrap(), ExitReason::PoweredOff);
+    assert_eq!(instance.state(), MachineState::PoweredOff);
+}
+
+#[test]
+fn run_stops_after_reset() {
+    let mut instance = guest(&[LOAD_SYSCON, RESET, SPIN]);
+
+    assert_eq!(instance.run().unwrap(), ExitReason::Reset);
+    assert_eq!(instance.state(), MachineState::Paused);
+}
+
+#[test]
+fn spawned_loop_exits() {
+    let mut instance = guest(&[LOAD_SYSCON, POWER_OFF, SPIN]);
+    assert_eq!(instance.spawn().unwrap().join(), ExitReason::PoweredOff);
+
+    let mut instance = guest(&[SPIN]);
+    let mut handle = instance.spawn().unwrap();
+    assert!(!handle.is_finished());
+    handle.instance().pause().unwrap();
+    assert_eq!(handle.join(), ExitReason::Paused);
+}
+
+#[test]
+fn pause_and_reset_follow_the_machine() {
+    let mut instance = guest(&[SPIN]);
+    assert!(matches!(
+        instance.pause(),
+        Err(InstancePauseError::NotRunning)
+    ));
+    assert!(matches!(
+        instance.reset(),
+        Err(InstanceResetError::NotRunning)
+    ));
+
+    instance.start().unwrap();
+    assert_eq!(instance.state(), MachineState::Running);
+    instance.pause().unwrap();
+    assert_eq!(instance.state(), MachineState::Paused);
+
+    instance.start().unwrap();
+    instance.reset().unwrap();
+    instance.power_off();
+    assert_eq!(instance.state(), MachineState::PoweredOff);
+    assert!(instance.reset().is_err());
+}
+
+#[test]
+fn guest_power_off_is_observed() {
+    let mut instance = guest(&[LOAD_SYSCON, POWER_OFF, SPIN]);
+    instance.start().unwrap();
+    while instance.powered_on() {
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    // Running flag is dropped, though nobody paused the
+    // machine
+    assert!(instance.pause().is_err());
+    assert!(!instance.is_running());
+}
+
+#[test]
+fn builder_options_reach_the_machine() {
+    let opts = [MachineOpt::Jit(false), MachineOpt::MaxCpuPercent(50)];
+    let instance = opts
+        .iter()
+        .fold(Instance::builder(), |builder, &opt| builder.opt(opt))
+        .cmdline("console=ttyS0")
+        .append_cmdline("quiet")
+        .build();
+
+    for opt in opts {
+        let (id, value) = opt.raw();
+        // SAFETY: pointer is obtained from the live instance
+        assert_eq!(unsafe { rvvm_get_opt(instance.as_ptr(), id) }, value);
+    }
+
+    // Command line ends up in the generated device tree
+    let dtb = instance.fdt_root().serialize(0);
+    assert!(dtb
+        .windows(19)
+        .any(|window| window == b"console=ttyS0 quiet"));
+}
+
+#[test]
+fn images_are_loaded_from_memory() {
+    let mut instance = Instance::new(1, 0x8000_0000, 0x40_0000, true);
+    let read = |instance: &Instance, addr, len| {
+        let mut data = vec![0; len];
+        instance.read_ram_to(addr, &mut data).unwrap();
+        data
+    };
+
+    instance
+        .load_bootrom_bytes(&[1, 2, 3, 4])
+        .unwrap();
+    assert_eq!(read(&instance, 0x8000_0000, 4), [1, 2, 3, 4]);
+
+    // Streamed in several chunks
+    let kernel: Vec<u8> = (0..200_000).map(|i| i as u8).collect();
+    instance
+        .load_kernel_from(Cursor::new(&kernel))
+        .unwrap();
+    let kernel_addr = 0x8000_0000 + instance.kernel_offset();
+    assert_eq!(read(&instance, kernel_addr, kernel.len()), kernel);
+
+    // DTB is placed at the RAM end and passed to the kernel
+    instance
+        .load_dtb_bytes(&[0xd0, 0x0d, 0xfe, 0xed])
+        .unwrap();
+    let dtb_addr = 0x8000_0000 + 0x40_0000 - 8;
+    assert_eq!(read(&instance, dtb_addr, 4), [0xd0, 0x0d, 0xfe, 0xed]);
+    let (id, _) = MachineOpt::DtbAddr(0).raw();
+    // SAFETY: pointer is obtained from the live instance
+    assert_eq!(unsafe { rvvm_get_opt(instance.as_ptr(), id) }, dtb_addr);
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_paths_are_accepted() {
+    use std::{
+        ffi::OsStr,
+        os::unix::ffi::OsStrExt,
+    };
+
+    let mut instance = Instance::new(1, 0x8000_0000, 4096, true);
+    let path = OsStr::from_bytes(b"/nonexistent/\xff.bin");
+
+    assert!(matches!(
+        instance.try_load_kernel(path),
+        Err(LoadError::NotFound(_))
+    ));
+}
+
+#[test]
+fn loader_and_memory_errors_are_detailed() {
+    let mut instance = guest(&[SPIN]);
+    let end = 0x8000_0000 + 0x10_0000;
+
+    assert_eq!(
+        instance.write_ram(end - 2, &[0; 4]),
+        Err(MemoryAccessError::OutOfBounds {
+            start: end - 2,
+            end: end + 2
+        })
+    );
+    assert!(matches!(
+        instance.load_kernel_bytes(&vec![0; 0x10_0000]),
+        Err(LoadError::TooLarge {
+            size: 0x10_0000,
+            available
+        }) if available == 0x10_0000 - instance.kernel_offset() as usize
+    ));
+    assert!(matches!(
+        instance.try_load_bootrom("/nonexistent/bootrom.bin"),
+        Err(LoadError::NotFound(path)) if path.ends_with("bootrom.bin")
+    ));
+
+    instance.start().unwrap();
+    assert!(matches!(
+        instance.load_bootrom_bytes(&[0; 4]),
+        Err(LoadError::Memory(MemoryAccessError::MachineRunning))
+    ));
+    instance.pause().unwrap();
+}
@@ -0,0 +1,254 @@
+use std::io::{
+    Read,
+    Seek,
+    SeekFrom,
+    Write,
+};
+
+use crate::{
+    error::{
+        MemoryAccessError,
+        StringReadError,
+    },
+    instance::Instance,
+    memory::*,
+    tests::instance::{
+        guest,
+        SPIN,
+    },
+};
+
+#[test]
+fn slice_by_physical_address() {
+    let data: Vec<u8> = (0..16).collect();
+    let mem = GuestMemory::new(0x8000_0000, &data);
+
+    assert_eq!(
+        mem.slice(0x8000_0004..0x8000_0008).unwrap(),
+        &[4, 5, 6, 7]
+    );
+    assert_eq!(mem.slice(0x8000_0010..0x8000_0010).unwrap(), &[]);
+    assert_eq!(
+        mem.slice(0x8000_000c..0x8000_0014),
+        Err(MemoryAccessError::OutOfBounds {
+            start: 0x8000_000c,
+            end: 0x8000_0014
+        })
+    );
+    assert!(mem.slice(0x7fff_ffff..0x8000_0001).is_err());
+}
+
+#[test]
+fn mutable_slice_by_physical_address() {
+    let mut data = [0u8; 8];
+    let mut mem = GuestMemoryMut::new(0x1000, &mut data);
+
+    mem.slice_mut(0x1002..0x1004)
+        .unwrap()
+        .copy_from_slice(&[1, 2]);
+    assert!(mem.slice_mut(0x1004..0x1009).is_err());
+    assert_eq!(mem.as_slice(), &[0, 0, 1, 2, 0, 0, 0, 0]);
+}
+
+#[test]
+fn aligned_object_references() {
+    // `u64` storage keeps the host buffer 8-byte aligned
+    let mut storage = [0u64; 2];
+    // SAFETY: `storage` is 16 bytes long, outlives `data` and
+    // isn't accessed otherwise while `data` is alive
+    let data = unsafe {
+        std::slice::from_raw_parts_mut(storage.as_mut_ptr() as *mut u8, 16)
+    };
+    let mut mem = GuestMemoryMut::new(0x1000, data);
+
+    *mem.get_mut::<u32>(0x1004).unwrap() = 0x1122_3344u32.to_le();
+    assert_eq!(
+        u32::from_le(*mem.get::<u32>(0x1004).unwrap()),
+        0x1122_3344
+    );
+    assert_eq!(mem.as_slice()[4..8], [0x44, 0x33, 0x22, 0x11]);
+    assert_eq!(*mem.get::<u8>(0x1005).unwrap(), 0x33);
+
+    assert_eq!(
+        mem.get::<u32>(0x1002),
+        Err(MemoryAccessError::Misaligned {
+            addr: 0x1002,
+            align: 4
+        })
+    );
+    assert_eq!(
+        mem.get_mut::<u64>(0x1010),
+        Err(MemoryAccessError::OutOfBounds {
+            start: 0x1010,
+            end: 0x1018
+        })
+    );
+}
+
+#[test]
+fn borrowed_ram_views() {
+    let mut instance = guest(&[SPIN]);
+    instance.write_ram(0x8000_0100, b"rvvm").unwrap();
+
+    // SAFETY: machine has no devices accessing the RAM
+    unsafe {
+        let ram = instance.ram().unwrap();
+        assert_eq!(ram.as_slice().len(), instance.mem_size());
+        assert_eq!(ram.slice(0x8000_0100..0x8000_0104).unwrap(), b"rvvm");
+
+        let mut ram = instance.ram_mut().unwrap();
+        ram.slice_mut(0x8000_0100..0x8000_0102)
+            .unwrap()
+            .copy_from_slice(b"RV");
+    }
+    let mut buf = [0; 4];
+    instance
+        .read_ram_to(0x8000_0100, &mut buf)
+        .unwrap();
+    assert_eq!(&buf, b"RVvm");
+
+    instance.start().unwrap();
+    // SAFETY: views are rejected before they are created
+    unsafe {
+        assert!(matches!(
+            instance.ram(),
+            Err(MemoryAccessError::MachineRunning)
+        ));
+        assert!(matches!(
+            instance.ram_mut(),
+            Err(MemoryAccessError::MachineRunning)
+        ));
+    }
+    instance.pause().unwrap();
+}
+
+#[test]
+fn c_strings_are_read_in_chunks() {
+    let mut instance = Instance::new(1, 0x8000_0000, 4096, false);
+    let end = 0x8000_0000 + 4096;
+
+    // Crosses the chunk boundary
+    let long = [b'a'; 300];
+    instance.write_ram(0x8000_0010, &long).unwrap();
+    instance.write_u8(0x8000_0010 + 300, 0).unwrap();
+    let string = instance.read_cstr(0x8000_0010, 1024).unwrap();
+    assert_eq!(string.as_bytes(), long);
+
+    // Terminator is the last byte allowed
+    instance
+        .write_ram(0x8000_0400, b"rvvm\0")
+        .unwrap();
+    assert_eq!(
+        instance
+            .read_cstr(0x8000_0400, 5)
+            .unwrap()
+            .as_bytes(),
+        b"rvvm"
+    );
+    assert!(matches!(
+        instance.read_cstr(0x8000_0400, 4),
+        Err(StringReadError::Unterminated { max_len: 4 })
+    ));
+    assert!(matches!(
+        instance.read_cstr(0x8000_0010, 300),
+        Err(StringReadError::Unterminated { max_len: 300 })
+    ));
+
+    // Runs into the end of RAM
+    instance.write_ram(end - 4, b"tail").unwrap();
+    assert!(matches!(
+        instance.read_cstr(end - 4, 64),
+        Err(StringReadError::Memory(
+            MemoryAccessError::OutOfBounds { .. }
+        ))
+    ));
+}
+
+#[test]
+fn utf8_strings() {
+    let mut instance = Instance::new(1, 0x8000_0000, 4096, false);
+    instance
+        .write_ram(0x8000_0000, "héllo".as_bytes())
+        .unwrap();
+
+    assert_eq!(instance.read_str(0x8000_0000, 6).unwrap(), "héllo");
+    assert!(matches!(
+        instance.read_str(0x8000_0000, 2),
+        Err(StringReadError::Utf8(_))
+    ));
+    assert!(matches!(
+        instance.read_str(0x8000_0ffe, 4),
+        Err(StringReadError::Memory(_))
+    ));
+}
+
+#[test]
+fn big_endian_accessors() {
+    let mut instance = Instance::new(1, 0x8000_0000, 4096, false);
+
+    instance
+        .write_u32_be(0x8000_0000, 0x1122_3344)
+        .unwrap();
+    let mut bytes = [0; 4];
+    instance
+        .read_ram_to(0x8000_0000, &mut bytes)
+        .unwrap();
+    assert_eq!(bytes, [0x11, 0x22, 0x33, 0x44]);
+    assert_eq!(instance.read_u32_be(0x8000_0000).unwrap(), 0x1122_3344);
+    assert_eq!(instance.read_u32(0x8000_0000).unwrap(), 0x4433_2211);
+    assert_eq!(instance.read_u16_be(0x8000_0002).unwrap(), 0x3344);
+
+    instance
+        .write_u64_be(0x8000_0008, 0x0102_0304_0506_0708)
+        .unwrap();
+    assert_eq!(instance.read_u8(0x8000_0008).unwrap(), 0x01);
+    assert_eq!(
+        instance.read_u64(0x8000_0008).unwrap(),
+        0x0807_0605_0403_0201
+    );
+    instance
+        .write_u16_be(0x8000_0010, 0xabcd)
+        .unwrap();
+    assert_eq!(instance.read_u16(0x8000_0010).unwrap(), 0xcdab);
+}
+
+#[test]
+fn pod_little_endian_roundtrip() {
+    let value = [0x1122_3344u32, 0x5566_7788];
+    let le = Pod::to_le(value);
+    let bytes: [u8; 8] = unsafe { std::mem::transmute(le) };
+
+    assert_eq!(bytes, [0x44, 0x33, 0x22, 0x11, 0x88, 0x77, 0x66, 0x55]);
+    assert_eq!(<[u32; 2]>::from_le(le), value);
+    assert_eq!(f64::from_le(Pod::to_le(1.5f64)), 1.5);
+}
+
+#[test]
+fn cursor_seek_and_eof() {
+    let mut instance = Instance::new(1, 0x8000_0000, 4096, false);
+    let mut cursor = instance.ram_cursor();
+
+    cursor.write_all(b"rvvm").unwrap();
+    assert_eq!(cursor.seek(SeekFrom::Start(0)).unwrap(), 0);
+    let mut buf = [0; 4];
+    cursor.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"rvvm");
+
+    assert_eq!(cursor.seek(SeekFrom::End(-4)).unwrap(), 4092);
+    cursor.write_all(&[1, 2, 3, 4]).unwrap();
+    assert_eq!(cursor.write(&[5]).unwrap(), 0);
+    assert_eq!(cursor.read(&mut buf).unwrap(), 0);
+
+    assert_eq!(cursor.seek(SeekFrom::Current(-2)).unwrap(), 4094);
+    let mut tail = Vec::new();
+    cursor.read_to_end(&mut tail).unwrap();
+    assert_eq!(tail, [3, 4]);
+
+    // Past the end is EOF, before the beginning is an error
+    assert_eq!(cursor.seek(SeekFrom::End(16)).unwrap(), 4112);
+    assert_eq!(cursor.read(&mut buf).unwrap(), 0);
+    assert_eq!(cursor.write(&buf).unwrap(), 0);
+    assert!(cursor.seek(SeekFrom::Current(-5000)).is_err());
+    assert_eq!(cursor.position(), 4112);
+    assert_eq!(cursor.phys_addr(), 0x8000_1010);
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use crate::{
+    error::{
+        MemoryAccessError,
+        PageFault,
+    },
+    mmu::*,
+};
+
+const V: u64 = 1;
+const R: u64 = 1 << 1;
+const W: u64 = 1 << 2;
+const X: u64 = 1 << 3;
+
+fn reader(
+    table: &HashMap<u64, u64>,
+) -> impl FnMut(u64, u64) -> Result<u64, MemoryAccessError> + '_ {
+    |addr, size| {
+        table
+            .get(&addr)
+            .copied()
+            .ok_or(MemoryAccessError::OutOfBounds {
+                start: addr,
+                end: addr + size,
+            })
+    }
+}
+
+#[test]
+fn sv39_walk() {
+    let satp = Satp::decode((8 << 60) | 0x80001, true).unwrap();
+    assert_eq!(satp.mode, PagingMode::Sv39);
+    assert_eq!(satp.root(), 0x8000_1000);
+
+    let mut table = HashMap::new();
+    table.insert(0x8000_1000, 0);
+    // vpn[2] = 2 -> next level at 0x80002000
+    table.insert(0x8000_1000 + 2 * 8, (0x80002 << 10) | V);
+    // vpn[1] = 0 -> next level at 0x80003000
+    table.insert(0x8000_2000, (0x80003 << 10) | V);
+    // vpn[0] = 1 -> leaf 0x80004000, read-only
+    table.insert(0x8000_3000 + 8, (0x80004 << 10) | V | R);
+    // vpn[2] = 3 -> aligned 1 GiB superpage
+    table.insert(0x8000_1000 + 3 * 8, (0x40000 << 10) | V | R | W | X);
+
+    let page =
+        translate_with(satp, 0x8000_1234, Access::Read, reader(&table))
+            .unwrap();
+    assert_eq!(page.phys_addr, 0x8000_4234);
+    assert_eq!(page.page_size, 0x1000);
+
+    assert!(matches!(
+        translate_with(satp, 0x8000_1234, Access::Write, reader(&table)),
+        Err(PageFault::PermissionDenied { .. })
+    ));
+
+    let page =
+        translate_with(satp, 0xc123_4567, Access::Write, reader(&table))
+            .unwrap();
+    assert_eq!(page.phys_addr, 0x4000_0000 + 0x0123_4567);
+    assert_eq!(page.page_size, 1 << 30);
+
+    assert!(matches!(
+        translate_with(satp, 0x1000, Access::Read, reader(&table)),
+        Err(PageFault::NotPresent { level: 2, .. })
+    ));
+    assert_eq!(
+        translate_with(satp, 1 << 40, Access::Read, reader(&table)),
+        Err(PageFault::NonCanonical { vaddr: 1 << 40 })
+    );
+}
+
+#[test]
+fn sv32_walk() {
+    let satp = Satp::decode((1 << 31) | 0x80001, false).unwrap();
+    assert_eq!(satp.mode, PagingMode::Sv32);
+
+    let mut table = HashMap::new();
+    // vpn[1] = 1 -> misaligned 4 MiB superpage
+    table.insert(0x8000_1000 + 4, (0x80001 << 10) | V | R);
+    // vpn[1] = 2 -> leaf with W but without R is reserved
+    table.insert(0x8000_1000 + 8, (0x80000 << 10) | V | W);
+    // vpn[1] = 3 -> aligned 4 MiB superpage
+    table.insert(0x8000_1000 + 12, (0x80000 << 10) | V | X);
+
+    assert!(matches!(
+        translate_with(satp, 0x0040_0000, Access::Read, reader(&table)),
+        Err(PageFault::MisalignedSuperpage { level: 1, .. })
+    ));
+    assert!(matches!(
+        translate_with(satp, 0x0080_0000, Access::Read, reader(&table)),
+        Err(PageFault::InvalidPte { level: 1, .. })
+    ));
+
+    let page =
+        translate_with(satp, 0x00c1_2345, Access::Execute, reader(&table))
+            .unwrap();
+    assert_eq!(page.phys_addr, 0x8001_2345);
+    assert_eq!(page.page_size, 1 << 22);
+}
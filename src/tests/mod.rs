@@ -1 +1,16 @@
+#[cfg(feature = "internals")]
+pub mod debug;
+pub mod device;
+pub mod disasm;
+pub mod elf;
 pub mod fdt;
+#[cfg(feature = "internals")]
+pub mod hart;
+pub mod instance;
+pub mod memory;
+pub mod mmu;
+pub mod snapshot;
+#[cfg(feature = "internals")]
+pub mod trace;
+pub mod uart;
+pub mod uart16550;
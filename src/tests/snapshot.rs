@@ -0,0 +1,302 @@
+#[cfg(feature = "internals")]
+use std::sync::atomic::{
+    AtomicU32,
+    Ordering,
+};
+
+#[cfg(feature = "internals")]
+use crate::{
+    dev::mmio::{
+        Device,
+        DeviceData,
+        DeviceExt,
+    },
+    error::StateError,
+    hart::{
+        HartState,
+        STATE_CSRS,
+    },
+    macros::device,
+    snapshot::state::{
+        read_hart,
+        write_hart,
+    },
+};
+use crate::{
+    error::{
+        MemoryAccessError,
+        SnapshotError,
+    },
+    instance::Instance,
+    snapshot::Snapshot,
+    tests::instance::{
+        guest,
+        SPIN,
+    },
+};
+
+#[test]
+fn zero_pages_are_compressed() {
+    let mut data = vec![0u8; 64 * 4096 + 100];
+    data[5 * 4096 + 7] = 0xaa;
+    data[64 * 4096 + 99] = 0x55;
+    let snapshot = Snapshot::new(0x8000_0000, true, data);
+
+    let mut encoded = Vec::new();
+    snapshot.write_to(&mut encoded).unwrap();
+    // header + 4 runs + 2 raw pages, the last one is truncated
+    assert_eq!(encoded.len(), 32 + 4 * 5 + 4096 + 100);
+
+    let decoded = Snapshot::read_from(encoded.as_slice()).unwrap();
+    assert_eq!(decoded, snapshot);
+}
+
+#[test]
+fn rejects_corrupted_snapshots() {
+    let snapshot = Snapshot::new(0x1000, false, vec![1; 4096]);
+    let mut encoded = Vec::new();
+    snapshot.write_to(&mut encoded).unwrap();
+
+    assert!(matches!(
+        Snapshot::read_from(&encoded[..encoded.len() - 1]),
+        Err(SnapshotError::Io(_))
+    ));
+
+    encoded[0] = b'X';
+    assert!(matches!(
+        Snapshot::read_from(encoded.as_slice()),
+        Err(SnapshotError::InvalidMagic)
+    ));
+}
+
+#[test]
+fn ram_snapshot_roundtrip() {
+    let mut instance = guest(&[SPIN]);
+    instance.write_ram(0x8000_1000, b"saved").unwrap();
+    let snapshot = instance.snapshot_ram().unwrap();
+    assert_eq!(snapshot.mem_base(), 0x8000_0000);
+    assert_eq!(snapshot.mem_size(), instance.mem_size());
+
+    instance.write_ram(0x8000_1000, b"wiped").unwrap();
+    instance
+        .write_ram(0x8000_2000, &[0xff; 16])
+        .unwrap();
+    instance.restore_ram(&snapshot).unwrap();
+
+    let mut ram = [0; 5];
+    instance
+        .read_ram_to(0x8000_1000, &mut ram)
+        .unwrap();
+    assert_eq!(&ram, b"saved");
+    let mut ram = [0xaa; 16];
+    instance
+        .read_ram_to(0x8000_2000, &mut ram)
+        .unwrap();
+    assert_eq!(ram, [0; 16]);
+
+    instance.start().unwrap();
+    assert!(matches!(
+        instance.snapshot_ram(),
+        Err(MemoryAccessError::MachineRunning)
+    ));
+    assert!(matches!(
+        instance.restore_ram(&snapshot),
+        Err(SnapshotError::Memory(MemoryAccessError::MachineRunning))
+    ));
+    instance.pause().unwrap();
+}
+
+#[test]
+fn ram_snapshot_config_mismatch() {
+    let instance = Instance::new(1, 0x8000_0000, 8192, true);
+    let snapshot = instance.snapshot_ram().unwrap();
+
+    let mismatch = |mut other: Instance| {
+        matches!(
+            other.restore_ram(&snapshot),
+            Err(SnapshotError::ConfigMismatch {
+                mem_base: 0x8000_0000,
+                mem_size: 8192,
+                rv64: true,
+            })
+        )
+    };
+    assert!(mismatch(Instance::new(1, 0x8000_0000, 4096, true)));
+    assert!(mismatch(Instance::new(1, 0x8000_0000, 8192, false)));
+    assert!(mismatch(Instance::new(1, 0x9000_0000, 8192, true)));
+    Instance::new(2, 0x8000_0000, 8192, true)
+        .restore_ram(&snapshot)
+        .unwrap();
+}
+
+#[cfg(feature = "internals")]
+fn hart_state() -> HartState {
+    HartState {
+        regs: std::array::from_fn(|n| n as u64 * 0x1_0000_0001),
+        fregs: std::array::from_fn(|n| (n as f64 / 3.0).to_bits()),
+        priv_mode: 1,
+        csrs: (0..STATE_CSRS.len() as u64).map(|n| !n).collect(),
+    }
+}
+
+#[cfg(feature = "internals")]
+#[test]
+fn hart_state_roundtrip() {
+    let hart = hart_state();
+    let mut encoded = Vec::new();
+    write_hart(&mut encoded, &hart).unwrap();
+    write_hart(&mut encoded, &hart).unwrap();
+    // 65 registers, privilege, CSR count and CSR pairs
+    assert_eq!(
+        encoded.len(),
+        2 * (65 * 8 + 1 + 2 + STATE_CSRS.len() * 10)
+    );
+
+    let mut r = encoded.as_slice();
+    assert_eq!(read_hart(&mut r).unwrap(), hart);
+    assert_eq!(read_hart(&mut r).unwrap(), hart);
+    assert!(r.is_empty());
+}
+
+#[cfg(feature = "internals")]
+#[test]
+fn rejects_unexpected_csrs() {
+    let mut encoded = Vec::new();
+    write_hart(&mut encoded, &hart_state()).unwrap();
+
+    let count = 65 * 8 + 1;
+    let mut swapped = encoded.clone();
+    swapped[count + 2..count + 4].copy_from_slice(&0x105u16.to_le_bytes());
+    assert!(matches!(
+        read_hart(&mut swapped.as_slice()),
+        Err(StateError::Corrupted(_))
+    ));
+
+    encoded[count] += 1;
+    assert!(matches!(
+        read_hart(&mut encoded.as_slice()),
+        Err(StateError::Corrupted(_))
+    ));
+
+    let mut truncated = Vec::new();
+    write_hart(&mut truncated, &hart_state()).unwrap();
+    truncated.pop();
+    assert!(matches!(
+        read_hart(&mut truncated.as_slice()),
+        Err(StateError::Io(_))
+    ));
+}
+
+/// Keeps the last written word and saves it with the state
+#[cfg(feature = "internals")]
+#[device]
+struct Register(AtomicU32);
+
+#[cfg(feature = "internals")]
+impl Device<AtomicU32> for Register {
+    type Error = ();
+
+    fn read(
+        &self,
+        dest: &mut [u8],
+        _size: u8,
+        _offset: usize,
+    ) -> Result<(), ()> {
+        let value = self.data().load(Ordering::Relaxed).to_le_bytes();
+        dest.copy_from_slice(&value[..dest.len()]);
+        Ok(())
+    }
+
+    fn write(
+        &self,
+        dest: &mut [u8],
+        _size: u8,
+        _offset: usize,
+    ) -> Result<(), ()> {
+        let mut value = [0; 4];
+        value[..dest.len()].copy_from_slice(dest);
+        self.data()
+            .store(u32::from_le_bytes(value), Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn save_state(&self) -> Option<Vec<u8>> {
+        Some(
+            self.data()
+                .load(Ordering::Relaxed)
+                .to_le_bytes()
+                .to_vec(),
+        )
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> bool {
+        let Ok(value) = state.try_into() else {
+            return false;
+        };
+        self.data()
+            .store(u32::from_le_bytes(value), Ordering::Relaxed);
+        true
+    }
+}
+
+#[cfg(feature = "internals")]
+#[test]
+fn machine_state_roundtrip() {
+    let mut instance = guest(&[SPIN]);
+    let handle = instance
+        .try_attach_device(Register::new(
+            0x4000_0000,
+            4,
+            4..=4,
+            AtomicU32::new(0x1234),
+        ))
+        .unwrap();
+    instance.write_ram(0x8000_1000, b"saved").unwrap();
+    let mut hart = instance.capture_hart(0).unwrap();
+    hart.regs[5] = 42;
+    hart.regs[32] = 0x8000_0000;
+    hart.fregs[1] = 2.5f64.to_bits();
+    instance.restore_hart(0, &hart).unwrap();
+
+    let mut state = Vec::new();
+    let skipped = instance.save_state(&mut state).unwrap();
+    assert!(skipped.is_empty());
+    assert_eq!(skipped, instance.builtin_devices());
+
+    instance.write_ram(0x8000_1000, b"wiped").unwrap();
+    let mut wiped = hart.clone();
+    wiped.regs[5] = 0;
+    wiped.regs[32] = 0x8000_0100;
+    wiped.fregs[1] = 0;
+    instance.restore_hart(0, &wiped).unwrap();
+    instance
+        .device(handle)
+        .unwrap()
+        .store(0, Ordering::Relaxed);
+
+    instance.load_state(state.as_slice()).unwrap();
+
+    let mut ram = [0; 5];
+    instance
+        .read_ram_to(0x8000_1000, &mut ram)
+        .unwrap();
+    assert_eq!(&ram, b"saved");
+    let restored = instance.capture_hart(0).unwrap();
+    assert_eq!(
+        (restored.regs, restored.fregs, restored.priv_mode),
+        (hart.regs, hart.fregs, hart.priv_mode)
+    );
+    assert_eq!(
+        instance
+            .device(handle)
+            .unwrap()
+            .load(Ordering::Relaxed),
+        0x1234
+    );
+
+    let mut other = guest(&[SPIN]);
+    assert!(matches!(
+        other.load_state(state.as_slice()),
+        Err(StateError::DeviceNotFound { addr: 0x4000_0000 })
+    ));
+}
@@ -0,0 +1,150 @@
+use std::{
+    sync::{
+        Arc,
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use super::{
+    elf::elf64_with_symbols,
+    instance::guest,
+};
+use crate::{
+    disasm::Disassembler,
+    elf::SymbolTable,
+    error::TraceError,
+    instance::{
+        Instance,
+        MachineOpt,
+    },
+    trace::{
+        TraceConfig,
+        TraceEntry,
+        Tracer,
+    },
+};
+
+fn entry(pc: u64) -> TraceEntry {
+    TraceEntry {
+        hart: 0,
+        pc,
+        insn: 0x0000_0013,
+    }
+}
+
+#[test]
+fn ring_evicts_oldest_entries() {
+    let mut tracer = Tracer::new(TraceConfig::ring(2));
+    for pc in [0x1000, 0x1004, 0x1008] {
+        tracer.record(entry(pc));
+    }
+
+    let pcs: Vec<_> = tracer.entries().map(|entry| entry.pc).collect();
+    assert_eq!(pcs, [0x1004, 0x1008]);
+
+    let mut tracer = Tracer::new(TraceConfig::ring(0));
+    tracer.record(entry(0x1000));
+    assert_eq!(tracer.entries().count(), 0);
+}
+
+#[test]
+fn callback_receives_entries() {
+    let recorded = Arc::new(Mutex::new(Vec::new()));
+    let config = TraceConfig::callback({
+        let recorded = Arc::clone(&recorded);
+        move |entry| recorded.lock().unwrap().push(*entry)
+    });
+
+    let mut tracer = Tracer::new(config.hart(1));
+    assert_eq!(tracer.hart(), 1);
+    tracer.record(entry(0x1000));
+    assert_eq!(*recorded.lock().unwrap(), [entry(0x1000)]);
+    assert_eq!(tracer.entries().count(), 0);
+}
+
+#[test]
+fn entry_display() {
+    let symbols = SymbolTable::from_elf(&elf64_with_symbols()).unwrap();
+    let disasm = Disassembler::new(true).with_symbols(&symbols);
+
+    let auipc = TraceEntry {
+        hart: 0,
+        pc: 0x8000_0000,
+        insn: 0x0000_0297,
+    };
+    assert_eq!(
+        auipc.display(&disasm),
+        "0: 80000000 <_start>: 00000297  auipc t0,0x0"
+    );
+
+    // Upper half of the compressed instruction is ignored
+    let ret = TraceEntry {
+        hart: 1,
+        pc: 0x8000_0004,
+        insn: 0xdead_8082,
+    };
+    assert_eq!(
+        ret.display(&disasm),
+        "1: 80000004 <_start+0x4>: 8082      ret"
+    );
+}
+
+#[test]
+fn set_trace_checks() {
+    let mut instance = Instance::new(1, 0x8000_0000, 4096, true);
+    instance.set_opt(MachineOpt::Jit(false)).unwrap();
+
+    assert!(matches!(
+        instance.set_trace(Some(TraceConfig::ring(4).hart(1))),
+        Err(TraceError::HartOutOfRange)
+    ));
+    instance
+        .set_trace(Some(TraceConfig::ring(4)))
+        .unwrap();
+    instance.set_trace(None).unwrap();
+
+    // RVVM built without the JIT can't enable it
+    if instance.set_opt(MachineOpt::Jit(true)).is_ok() {
+        assert!(matches!(
+            instance.set_trace(Some(TraceConfig::ring(4))),
+            Err(TraceError::JitEnabled)
+        ));
+        instance.set_trace(None).unwrap();
+    }
+}
+
+#[test]
+fn running_machine_is_traced() {
+    // 1: addi t0, t0, 1
+    //    j 1b
+    let mut instance = guest(&[0x0012_8293, 0xffdf_f06f]);
+    instance.set_opt(MachineOpt::Jit(false)).unwrap();
+    instance
+        .set_trace(Some(TraceConfig::ring(16)))
+        .unwrap();
+
+    instance.start().unwrap();
+    thread::sleep(Duration::from_millis(50));
+    instance.pause().unwrap();
+
+    let entries = instance.trace_buffer();
+    assert!(entries.len() >= 2);
+    for entry in entries {
+        let expected = match entry.pc {
+            0x8000_0000 => 0x0012_8293,
+            0x8000_0004 => 0xffdf_f06f,
+            pc => panic!("unexpected pc {pc:#x}"),
+        };
+        assert_eq!((entry.hart, entry.insn), (0, expected));
+    }
+
+    // Guest keeps running between the steps
+    let t0 = instance.hart(0).unwrap().x(5);
+    assert!(t0 >= 1);
+
+    // Stepping is given up once the machine is dropped
+    instance.start().unwrap();
+    drop(instance);
+}
@@ -0,0 +1,96 @@
+use std::{
+    io::{
+        ErrorKind,
+        Read,
+        Write,
+    },
+    time::Duration,
+};
+
+use crate::{
+    c_str,
+    dev::uart::Console,
+    error::DeviceAttachError,
+    fdt::Region,
+    instance::Instance,
+    tests::device::{
+        find_device,
+        mmio_read,
+        mmio_write,
+    },
+};
+
+#[test]
+fn console_streams() {
+    let console = Console::new();
+    let (mut reader, mut writer) = console.streams();
+
+    console.push_output(b"login: ");
+    let mut buf = [0; 16];
+    assert_eq!(reader.read(&mut buf).unwrap(), 7);
+    assert_eq!(&buf[..7], b"login: ");
+
+    reader.set_read_timeout(Some(Duration::from_millis(1)));
+    assert_eq!(
+        reader.read(&mut buf).unwrap_err().kind(),
+        ErrorKind::TimedOut
+    );
+
+    writer.write_all(b"root\n").unwrap();
+    assert!(console.has_input());
+    assert_eq!(console.take_input(&mut buf), 5);
+    assert_eq!(&buf[..5], b"root\n");
+
+    console.close();
+    assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    assert!(writer.write(b"x").is_err());
+}
+
+#[test]
+fn attach_uart() {
+    let mut instance = Instance::new(1, 0x8000_0000, 4096, true);
+
+    let (mut reader, _writer) =
+        instance.attach_uart(0x1000_0000, 1).unwrap();
+    assert!(instance
+        .fdt_soc()
+        .find(Region(c_str!("uart"), 0x1000_0000))
+        .is_some());
+
+    assert!(matches!(
+        instance.attach_uart(0x1000_0004, 1),
+        Err(DeviceAttachError::RegionIsOccupied)
+    ));
+    assert!(instance
+        .fdt_soc()
+        .find(Region(c_str!("uart"), 0x1000_0004))
+        .is_none());
+
+    // Output of the attached UART ends with the machine
+    drop(instance);
+    assert_eq!(reader.read(&mut [0; 1]).unwrap(), 0);
+}
+
+#[test]
+fn attached_uart_is_driven_through_mmio() {
+    let mut instance = Instance::new(1, 0x8000_0000, 4096, true);
+    let (mut reader, mut writer) =
+        instance.attach_uart(0x1000_0000, 1).unwrap();
+    let uart = find_device(&instance, c_str!("ns16550a"));
+
+    // SAFETY: UART is attached to the machine
+    unsafe {
+        // THR write goes to the guest output
+        mmio_write(uart, 0, b'A');
+        let mut buf = [0; 4];
+        assert_eq!(reader.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[0], b'A');
+
+        // Host input sets LSR.DR and is read from the RBR
+        assert_eq!(mmio_read(uart, 5, 1) & 0x01, 0);
+        writer.write_all(b"x").unwrap();
+        assert_eq!(mmio_read(uart, 5, 1) & 0x01, 0x01);
+        assert_eq!(mmio_read(uart, 0, 1), b'x' as u32);
+        assert_eq!(mmio_read(uart, 5, 1) & 0x01, 0);
+    }
+}
@@ -0,0 +1,168 @@
+use std::io::{
+    Read,
+    Write,
+};
+
+use rvvm_sys::rvvm_get_mmio;
+
+use crate::{
+    c_str,
+    dev::{
+        uart::Console,
+        uart16550::Registers,
+    },
+    error::DeviceAttachError,
+    instance::Instance,
+    tests::device::{
+        find_device,
+        mmio_read,
+        mmio_write,
+    },
+};
+
+#[test]
+fn fifo_and_interrupts() {
+    let console = Console::new();
+    let (mut reader, mut writer) = console.streams();
+    let mut regs = Registers::default();
+
+    // Divisor latch shadows the data and IER registers
+    regs.write(3, 0x83, &console);
+    regs.write(0, 0x0c, &console);
+    regs.write(1, 0x00, &console);
+    assert_eq!(regs.read(0, &console), 0x0c);
+    regs.write(3, 0x03, &console);
+    regs.write(7, 0x5a, &console);
+    assert_eq!(regs.read(7, &console), 0x5a);
+
+    // Enabling THRE interrupt raises it right away, IIR read
+    // clears it
+    regs.write(1, 0x03, &console);
+    assert!(regs.irq_edge(false));
+    assert_eq!(regs.read(2, &console), 0x02);
+    assert_eq!(regs.read(2, &console), 0x01);
+    assert!(!regs.irq_edge(false));
+
+    regs.write(0, b'A', &console);
+    let mut buf = [0; 4];
+    assert_eq!(reader.read(&mut buf).unwrap(), 1);
+    assert_eq!(buf[0], b'A');
+    assert_eq!(regs.read(2, &console), 0x02);
+
+    // FIFO with the trigger level of 4 bytes, timeout is
+    // reported for the remainder
+    regs.write(2, 0x47, &console);
+    writer.write_all(b"hello").unwrap();
+    regs.tick(&console);
+    assert_eq!(regs.read(2, &console), 0xc4);
+    assert_eq!(regs.read(5, &console) & 0x61, 0x61);
+    for &byte in b"hell" {
+        assert_eq!(regs.read(0, &console), byte);
+    }
+    assert_eq!(regs.read(2, &console), 0xc1);
+    // Receiver must stay idle for the whole tick
+    regs.tick(&console);
+    assert_eq!(regs.read(2, &console), 0xc1);
+    regs.tick(&console);
+    assert_eq!(regs.read(2, &console), 0xcc);
+    assert_eq!(regs.read(0, &console), b'o');
+    assert_eq!(regs.read(5, &console) & 0x01, 0);
+
+    // Loopback wires the output to the input and the modem
+    // outputs to the modem inputs
+    regs.write(4, 0x13, &console);
+    assert_eq!(regs.read(6, &console), 0x38);
+    regs.write(0, b'x', &console);
+    assert_eq!(regs.read(0, &console), b'x');
+    assert_eq!(reader.available(), 0);
+
+    let state = regs.save();
+    let loaded = Registers::load(&state).unwrap();
+    assert_eq!(loaded.save(), state);
+    assert!(Registers::load(&state[..3]).is_none());
+
+    // IER, FCR, MCR and LSR bits the guest can't set
+    for (offset, bits) in
+        [(0, 0x10), (1, 0x02), (3, 0x20), (4, 0x01), (4, 0x20)]
+    {
+        let mut corrupted = state.clone();
+        corrupted[offset] |= bits;
+        assert!(Registers::load(&corrupted).is_none(), "{offset}");
+    }
+}
+
+#[test]
+fn attached_uart_is_driven_through_mmio() {
+    let mut instance = Instance::new(1, 0x8000_0000, 0x10_0000, true);
+    instance.attach_plic().unwrap();
+    assert!(matches!(
+        instance.attach_plic(),
+        Err(DeviceAttachError::RegionIsOccupied)
+    ));
+    let (handle, mut reader, mut writer) = instance
+        .attach_uart16550(0x1000_0000, 10)
+        .unwrap();
+
+    // SAFETY: handle refers to the device of this machine
+    let uart = unsafe { rvvm_get_mmio(instance.as_ptr(), handle.raw()) };
+    let plic = find_device(&instance, c_str!("plic"));
+    // SAFETY: PLIC is attached to the machine
+    let pending = || unsafe { mmio_read(plic, 0x1000, 4) } & 1 << 10 != 0;
+
+    // SAFETY: UART is attached to the machine, handlers are set
+    // by the `attach_uart16550`
+    unsafe {
+        mmio_write(uart, 0, b'A');
+        let mut buf = [0; 4];
+        assert_eq!(reader.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[0], b'A');
+
+        // Input is received by the update handler called from
+        // the event loop
+        writer.write_all(b"x").unwrap();
+        (*(*uart).type_).update.unwrap()(uart);
+        assert_eq!(mmio_read(uart, 5, 1) & 0x01, 0x01);
+        assert_eq!(mmio_read(uart, 0, 1), b'x' as u32);
+
+        // Enabling THRE interrupt raises it right away
+        assert!(!pending());
+        mmio_write(uart, 1, 0x02);
+        assert!(pending());
+
+        mmio_write(uart, 7, 0x5a);
+        (*(*uart).type_).reset.unwrap()(uart);
+        assert_eq!(mmio_read(uart, 7, 1), 0);
+    }
+}
+
+#[cfg(feature = "internals")]
+#[test]
+fn uart_state_is_saved_with_the_machine() {
+    let mut instance = Instance::new(1, 0x8000_0000, 0x10_0000, true);
+    let (handle, ..) = instance
+        .attach_uart16550(0x1000_0000, 10)
+        .unwrap();
+    // SAFETY: handle refers to the device of this machine
+    let uart = unsafe { rvvm_get_mmio(instance.as_ptr(), handle.raw()) };
+
+    // SAFETY: UART is attached to the machine
+    unsafe {
+        mmio_write(uart, 3, 0x03);
+        mmio_write(uart, 7, 0x5a);
+    }
+    let mut state = Vec::new();
+    instance.save_state(&mut state).unwrap();
+
+    // SAFETY: see above
+    unsafe {
+        mmio_write(uart, 3, 0x00);
+        mmio_write(uart, 7, 0x00);
+    }
+    instance.load_state(state.as_slice()).unwrap();
+
+    // SAFETY: see above
+    unsafe {
+        assert_eq!(mmio_read(uart, 3, 1), 0x03);
+        assert_eq!(mmio_read(uart, 7, 1), 0x5a);
+    }
+}
@@ -0,0 +1,185 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::Arc,
+};
+
+use crate::{
+    disasm::Disassembler,
+    error::TraceError,
+    insn::insn_len,
+    instance::Instance,
+};
+
+/// Instruction executed by the traced hart
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceEntry {
+    pub hart: usize,
+    pub pc: u64,
+    /// Instruction word, upper half is garbage for the
+    /// compressed instructions
+    pub insn: u32,
+}
+
+impl TraceEntry {
+    /// Formats the entry as
+    /// `0: 80000000 <_start>: 00000297  auipc t0,0x0`
+    pub fn display(&self, disasm: &Disassembler) -> String {
+        let raw = if insn_len(self.insn as u16) == 2 {
+            format!("{:04x}    ", self.insn & 0xffff)
+        } else {
+            format!("{:08x}", self.insn)
+        };
+
+        format!(
+            "{}: {}: {raw}  {}",
+            self.hart,
+            disasm.describe(self.pc),
+            disasm.disassemble(self.insn, self.pc)
+        )
+    }
+}
+
+/// Destination of the trace entries
+#[derive(Clone)]
+pub enum TraceSink {
+    /// Keeps the last `n` entries, see the
+    /// `Instance::trace_buffer`
+    Ring(usize),
+
+    /// Called for every entry from the monitor thread.
+    /// Instance must not be accessed from the callback.
+    Callback(Arc<dyn Fn(&TraceEntry) + Send + Sync>),
+}
+
+impl fmt::Debug for TraceSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ring(capacity) => {
+                f.debug_tuple("Ring").field(capacity).finish()
+            }
+            Self::Callback(_) => f.write_str("Callback(..)"),
+        }
+    }
+}
+
+/// Configuration of the trace mode, see the
+/// `Instance::set_trace`
+#[derive(Debug, Clone)]
+pub struct TraceConfig {
+    pub hart: usize,
+    pub sink: TraceSink,
+}
+
+impl TraceConfig {
+    /// Traces hart `0` into the ring buffer of `capacity`
+    /// entries
+    pub fn ring(capacity: usize) -> Self {
+        Self {
+            hart: 0,
+            sink: TraceSink::Ring(capacity),
+        }
+    }
+
+    /// Traces hart `0` into the callback
+    pub fn callback(
+        callback: impl Fn(&TraceEntry) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            hart: 0,
+            sink: TraceSink::Callback(Arc::new(callback)),
+        }
+    }
+
+    pub fn hart(mut self, hart: usize) -> Self {
+        self.hart = hart;
+        self
+    }
+}
+
+/// Trace mode state, owned by the monitor
+#[derive(Debug)]
+pub(crate) struct Tracer {
+    config: TraceConfig,
+    ring: VecDeque<TraceEntry>,
+}
+
+impl Tracer {
+    pub(crate) fn new(config: TraceConfig) -> Self {
+        Self {
+            config,
+            ring: VecDeque::new(),
+        }
+    }
+
+    pub(crate) const fn hart(&self) -> usize {
+        self.config.hart
+    }
+
+    /// Entries of the `TraceSink::Ring`, oldest first
+    pub(crate) fn entries(&self) -> impl Iterator<Item = &TraceEntry> {
+        self.ring.iter()
+    }
+
+    pub(crate) fn record(&mut self, entry: TraceEntry) {
+        match &self.config.sink {
+            TraceSink::Ring(0) => {}
+            TraceSink::Ring(capacity) => {
+                if self.ring.len() == *capacity {
+                    self.ring.pop_front();
+                }
+                self.ring.push_back(entry);
+            }
+            TraceSink::Callback(callback) => callback(&entry),
+        }
+    }
+}
+
+impl Instance {
+    /// Switches the trace mode, `None` disables it.
+    ///
+    /// While tracing, the traced hart is single-stepped and
+    /// every executed instruction is recorded, so guest
+    /// runs orders of magnitude slower: at most about 20k
+    /// instructions per second. Other harts run
+    /// only while the traced one is stepped and are not
+    /// recorded. Breakpoints and watchpoints are still
+    /// checked after every instruction.
+    ///
+    /// Stepping patches the guest code, so JIT must be
+    /// disabled first, e.g. with the `MachineOpt::Jit`.
+    /// `InstanceBuilder::trace` disables it itself.
+    ///
+    /// - Returns `Ok` if trace mode is switched
+    /// - Returns `TraceError::HartOutOfRange` if hart is
+    ///   out of range
+    /// - Returns `TraceError::JitEnabled` if JIT is enabled
+    pub fn set_trace(
+        &mut self,
+        config: Option<TraceConfig>,
+    ) -> Result<(), TraceError> {
+        if let Some(config) = &config {
+            if config.hart >= self.harts() {
+                return Err(TraceError::HartOutOfRange);
+            }
+            if self.jit_enabled() {
+                return Err(TraceError::JitEnabled);
+            }
+        }
+
+        self.monitor().lock().tracer = config.map(Tracer::new);
+        Ok(())
+    }
+
+    /// Get entries of the `TraceSink::Ring`, oldest first.
+    /// Empty if trace mode is disabled.
+    pub fn trace_buffer(&mut self) -> Vec<TraceEntry> {
+        self.monitor()
+            .lock()
+            .tracer
+            .as_ref()
+            .map_or_else(Vec::new, |tracer| {
+                tracer.entries().copied().collect()
+            })
+    }
+}
@@ -1,11 +1,33 @@
 use std::{
     fmt::Debug,
     marker::PhantomData,
+    ops::{
+        Deref,
+        DerefMut,
+    },
+    sync::{
+        Arc,
+        Condvar,
+        Mutex,
+        MutexGuard,
+        PoisonError,
+    },
+    thread::{
+        self,
+        JoinHandle,
+    },
+    time::Duration,
 };
 
 use rvvm_sys::rvvm_mmio_dev_t;
 
+use crate::{
+    dev::mmio::DeviceData,
+    instance::Instance,
+};
+
 // Это везде таскать будем, для безопасности
+#[repr(transparent)]
 pub struct UnsafeDevice<T: Send + Sync> {
     inner: rvvm_mmio_dev_t,
     phantom: PhantomData<T>,
@@ -35,9 +57,26 @@ impl<T: Send + Sync> UnsafeDevice<T> {
     }
 }
 
+// SAFETY: `UnsafeDevice` is `repr(transparent)` to the
+// `rvvm_mmio_dev_t`
+unsafe impl<T: Send + Sync> DeviceData for UnsafeDevice<T> {
+    type Ty = T;
+
+    fn data(&self) -> &T {
+        UnsafeDevice::data(self)
+    }
+
+    fn data_mut(&mut self) -> &mut T {
+        UnsafeDevice::data_mut(self)
+    }
+}
+
 impl<T: Send + Sync> Drop for UnsafeDevice<T> {
     fn drop(&mut self) {
         // вот тут очистим
+        if self.inner.data.is_null() {
+            return;
+        }
 
         let _ =
             unsafe { Box::from_raw(self.inner.data as *mut () as *mut T) };
@@ -57,12 +96,261 @@ impl<T> Debug for DeviceHandle<T> {
     }
 }
 
+impl<T> DeviceHandle<T> {
+    /// Create typed handle from the raw handle returned by
+    /// the `rvvm_attach_mmio`
+    pub(crate) const fn new(inner: i32) -> Self {
+        Self {
+            inner,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Get raw MMIO handle
+    pub const fn raw(&self) -> i32 {
+        self.inner
+    }
+}
+
 impl<T> Copy for DeviceHandle<T> {}
 impl<T> Clone for DeviceHandle<T> {
     fn clone(&self) -> Self {
+        *self
+    }
+}
+
+/// Exclusive access to the data of the attached device.
+///
+/// Obtained through the `Instance::device_mut`, keeps the
+/// machine paused until dropped.
+pub struct DeviceGuard<'a, T> {
+    instance: &'a mut Instance,
+    data: *mut T,
+    resume: bool,
+}
+
+impl<'a, T> DeviceGuard<'a, T> {
+    /// # Safety
+    ///
+    /// `data` must be valid for the `'a` and machine must
+    /// be paused
+    pub(crate) unsafe fn new(
+        instance: &'a mut Instance,
+        data: *mut T,
+        resume: bool,
+    ) -> Self {
         Self {
-            inner: self.inner,
-            phantom: PhantomData,
+            instance,
+            data,
+            resume,
+        }
+    }
+}
+
+impl<T> Deref for DeviceGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: see `DeviceGuard::new`
+        unsafe { &*self.data }
+    }
+}
+
+impl<T> DerefMut for DeviceGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: see `DeviceGuard::new`
+        unsafe { &mut *self.data }
+    }
+}
+
+impl<T: Debug> Debug for DeviceGuard<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("DeviceGuard")
+            .field(&**self)
+            .finish()
+    }
+}
+
+impl<T> Drop for DeviceGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.resume {
+            let _ = self.instance.start();
+        }
+    }
+}
+
+/// Observable state of the machine, see `Instance::state`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MachineState {
+    /// CPUs are running
+    Running,
+
+    /// Machine is powered on, but CPUs are stopped
+    Paused,
+
+    /// Machine is powered off by the guest or through the
+    /// `Instance::power_off`
+    PoweredOff,
+}
+
+impl MachineState {
+    pub(crate) const fn new(powered_on: bool, running: bool) -> Self {
+        match (powered_on, running) {
+            (false, _) => Self::PoweredOff,
+            (true, true) => Self::Running,
+            (true, false) => Self::Paused,
+        }
+    }
+}
+
+/// Reason of the event loop exit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// Machine was powered off by the guest
+    PoweredOff,
+
+    /// Machine was reset by the guest or through the
+    /// `Instance::reset`. RVVM restarts the harts once the
+    /// reset is done and the machine is paused afterwards,
+    /// so the guest may execute some instructions past
+    /// the reset
+    Reset,
+
+    /// Machine was paused
+    Paused,
+}
+
+#[derive(Debug, Default)]
+struct LoopState {
+    reset: bool,
+    exited: bool,
+}
+
+/// Events that end the `Instance::run`, machine resets are
+/// reported by the reset callback of the internal device
+#[derive(Debug, Default)]
+pub(crate) struct LoopEvents {
+    state: Mutex<LoopState>,
+    changed: Condvar,
+}
+
+impl LoopEvents {
+    fn lock(&self) -> MutexGuard<'_, LoopState> {
+        self.state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Forgets the events of the previous run
+    pub(crate) fn begin(&self) {
+        *self.lock() = LoopState::default();
+    }
+
+    pub(crate) fn notify_reset(&self) {
+        self.lock().reset = true;
+        self.changed.notify_all();
+    }
+
+    pub(crate) fn notify_exit(&self) {
+        self.lock().exited = true;
+        self.changed.notify_all();
+    }
+
+    pub(crate) fn exited(&self) -> bool {
+        self.lock().exited
+    }
+
+    /// Waits until the machine is reset or the event loop
+    /// exits, returns whether machine was reset
+    pub(crate) fn wait(&self) -> bool {
+        let mut state = self.lock();
+        while !state.reset && !state.exited {
+            state = self
+                .changed
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+
+        state.reset
+    }
+}
+
+/// Handle to the event loop running on the background
+/// thread, see `Instance::spawn`.
+///
+/// Dropping the handle detaches the event loop thread
+/// without waiting for it, the machine keeps running. Use
+/// the `EventLoopHandle::join` to wait for the exit reason.
+pub struct EventLoopHandle<'a> {
+    instance: &'a mut Instance,
+    thread: Option<JoinHandle<()>>,
+    events: Arc<LoopEvents>,
+}
+
+impl<'a> EventLoopHandle<'a> {
+    pub(crate) fn new(
+        instance: &'a mut Instance,
+        thread: JoinHandle<()>,
+        events: Arc<LoopEvents>,
+    ) -> Self {
+        Self {
+            instance,
+            thread: Some(thread),
+            events,
+        }
+    }
+
+    /// Get the instance, e.g. to pause it while the event
+    /// loop is running
+    pub fn instance(&mut self) -> &mut Instance {
+        self.instance
+    }
+
+    /// Waits for the event loop to exit or the machine
+    /// reset
+    pub fn join(mut self) -> ExitReason {
+        self.wait()
+    }
+
+    /// Check whether the event loop has exited
+    pub fn is_finished(&self) -> bool {
+        match &self.thread {
+            Some(thread) => thread.is_finished(),
+            None => true,
         }
     }
+
+    fn wait(&mut self) -> ExitReason {
+        let Some(thread) = self.thread.take() else {
+            return self.instance.exit_reason();
+        };
+
+        let reset = self.events.wait();
+        if reset {
+            // RVVM restarts the CPUs once the reset is done, so
+            // retry until there is something to pause
+            while self.instance.pause().is_err()
+                && self.instance.powered_on()
+                && !thread.is_finished()
+            {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+
+        thread.join().expect("Event loop thread panicked");
+
+        if reset {
+            ExitReason::Reset
+        } else {
+            self.instance.exit_reason()
+        }
+    }
+}
+
+impl Debug for EventLoopHandle<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventLoopHandle")
+            .field("thread", &self.thread)
+            .finish()
+    }
 }